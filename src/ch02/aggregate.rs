@@ -0,0 +1,309 @@
+//! group-by aggregation of temperature over `Record`,
+//! e.g. the hottest record per prefecture per decade.
+extern crate chrono;
+extern crate serde_json;
+
+use std::collections::BTreeMap;
+use self::chrono::Datelike;
+use self::serde_json::{Map, Value};
+
+use super::as_csv::Record;
+use super::prefecture;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupKey {
+    Pref,
+    Region,
+    Year,
+    /// month of the year, `01` to `12`
+    Month,
+    /// e.g. `1990s`
+    Decade,
+    /// region block of the prefecture like `関東`. empty if the prefecture is unknown.
+    Block,
+}
+
+impl GroupKey {
+    pub fn name(&self)->&'static str {
+        match *self {
+            GroupKey::Pref => "pref",
+            GroupKey::Region => "region",
+            GroupKey::Year => "year",
+            GroupKey::Month => "month",
+            GroupKey::Decade => "decade",
+            GroupKey::Block => "block",
+        }
+    }
+
+    fn of(&self, record: &Record)->String {
+        match *self {
+            GroupKey::Pref => record.pref.clone(),
+            GroupKey::Region => record.region.clone(),
+            GroupKey::Year => record.date.year().to_string(),
+            GroupKey::Month => format!("{:02}", record.date.month()),
+            GroupKey::Decade => format!("{}s", record.date.year() / 10 * 10),
+            GroupKey::Block => prefecture::find(&record.pref)
+                .map_or(String::new(), |p| p.block.name().to_string()),
+        }
+    }
+}
+
+/// statistics of temperature
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    pub count: usize,
+    pub min: f32,
+    pub max: f32,
+    pub mean: f64,
+    pub median: f64,
+    /// sample standard deviation. 0 for a single record.
+    pub stddev: f64,
+}
+
+impl Stats {
+    const NAMES: [&'static str; 6] = ["count", "min", "max", "mean", "median", "stddev"];
+
+    fn of(temps: &[f32])->Stats {
+        let mut sorted = temps.to_vec();
+        // NaN goes last, so max and mean are NaN if any
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let count = sorted.len();
+        let values = sorted.iter().map(|&t| t as f64).collect::<Vec<_>>();
+        let mean = values.iter().sum::<f64>() / count as f64;
+        let median = if count % 2 == 1 {
+            values[count / 2]
+        } else {
+            (values[count / 2 - 1] + values[count / 2]) / 2.0
+        };
+        let stddev = if count < 2 {0.0} else {
+            (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (count - 1) as f64).sqrt()
+        };
+        Stats {count, min: sorted[0], max: sorted[count - 1], mean, median, stddev}
+    }
+
+    fn values(&self)->Vec<String> {
+        vec![
+            self.count.to_string(),
+            self.min.to_string(),
+            self.max.to_string(),
+            format!("{:.2}", self.mean),
+            format!("{:.2}", self.median),
+            format!("{:.2}", self.stddev),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    /// values of the group keys
+    pub key: Vec<String>,
+    pub stats: Stats,
+}
+
+/// records in descending order of temperature in a group
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ranking {
+    pub key: Vec<String>,
+    pub records: Vec<Record>,
+}
+
+/// partition records by keys in ascending order of keys.
+fn partition<'a>(records: &'a [Record], keys: &[GroupKey])->BTreeMap<Vec<String>, Vec<&'a Record>> {
+    let mut groups = BTreeMap::new();
+    for record in records {
+        let key = keys.iter().map(|k| k.of(record)).collect::<Vec<_>>();
+        groups.entry(key).or_insert_with(Vec::new).push(record);
+    }
+    groups
+}
+
+/// statistics of temperature in each group, in ascending order of keys.
+pub fn group_by(records: &[Record], keys: &[GroupKey])->Vec<Group> {
+    partition(records, keys)
+        .into_iter()
+        .map(|(key, rs)| Group {
+            key,
+            stats: Stats::of(&rs.iter().map(|r| r.temp).collect::<Vec<_>>()),
+        })
+        .collect()
+}
+
+/// the hottest n records in each group. ties keep the original order, and NaN ranks last.
+pub fn top_n_per_group(records: &[Record], keys: &[GroupKey], n: usize)->Vec<Ranking> {
+    partition(records, keys)
+        .into_iter()
+        .map(|(key, mut rs)| {
+            rs.sort_by(|r1, r2| r1.temp.is_nan().cmp(&r2.temp.is_nan()).then(r2.temp.total_cmp(&r1.temp)));
+            Ranking {key, records: rs.into_iter().take(n).cloned().collect()}
+        })
+        .collect()
+}
+
+/// tab-separated with a header of key names and statistics
+pub fn groups_to_tsv(keys: &[GroupKey], groups: &[Group])->String {
+    let header = keys.iter()
+        .map(|k| k.name())
+        .chain(Stats::NAMES.iter().cloned())
+        .collect::<Vec<_>>()
+        .join("\t");
+    ::std::iter::once(header)
+        .chain(groups.iter().map(|g|
+            g.key.iter().cloned().chain(g.stats.values()).collect::<Vec<_>>().join("\t")
+        ))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// JSON array of objects which have key names and statistics
+pub fn groups_to_json(keys: &[GroupKey], groups: &[Group])->String {
+    let objects = groups.iter()
+        .map(|g| {
+            let mut obj = Map::new();
+            for (k, v) in keys.iter().zip(g.key.iter()) {
+                obj.insert(k.name().into(), Value::String(v.clone()));
+            }
+            if let Value::Object(stats) = serde_json::to_value(&g.stats).unwrap() {
+                obj.extend(stats);
+            }
+            Value::Object(obj)
+        })
+        .collect::<Vec<_>>();
+    serde_json::to_string(&objects).unwrap()
+}
+
+/// tab-separated with key names, rank and fields of the record
+pub fn rankings_to_tsv(keys: &[GroupKey], rankings: &[Ranking])->String {
+    let header = keys.iter()
+        .map(|k| k.name())
+        .chain(vec!["rank", "pref", "region", "temp", "date"])
+        .collect::<Vec<_>>()
+        .join("\t");
+    ::std::iter::once(header)
+        .chain(rankings.iter().flat_map(|ranking|
+            ranking.records.iter().enumerate().map(move |(idx, r)| {
+                ranking.key.iter()
+                    .cloned()
+                    .chain(vec![
+                        (idx + 1).to_string(),
+                        r.pref.clone(),
+                        r.region.clone(),
+                        r.temp.to_string(),
+                        r.date.to_string(),
+                    ])
+                    .collect::<Vec<_>>()
+                    .join("\t")
+            })
+        ))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// JSON array of objects which have key names and the records
+pub fn rankings_to_json(keys: &[GroupKey], rankings: &[Ranking])->String {
+    let objects = rankings.iter()
+        .map(|ranking| {
+            let mut obj = Map::new();
+            for (k, v) in keys.iter().zip(ranking.key.iter()) {
+                obj.insert(k.name().into(), Value::String(v.clone()));
+            }
+            obj.insert("records".into(), serde_json::to_value(&ranking.records).unwrap());
+            Value::Object(obj)
+        })
+        .collect::<Vec<_>>();
+    serde_json::to_string(&objects).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(pref: &str, region: &str, temp: f32, date: &str)->Record {
+        Record {pref: pref.into(), region: region.into(), temp, date: date.parse().unwrap()}
+    }
+
+    fn records()->Vec<Record> {
+        vec![
+            record("埼玉県", "熊谷", 40.9, "2007-08-16"),
+            record("高知県", "江川崎", 41.0, "2013-08-12"),
+            record("埼玉県", "越谷", 40.4, "2007-08-16"),
+            record("埼玉県", "鳩山", 39.9, "1997-07-05"),
+        ]
+    }
+
+    #[test]
+    fn test_stats() {
+        let stats = Stats::of(&[40.0, 39.0, 42.0, 41.0]);
+        assert_eq!((stats.count, stats.min, stats.max), (4, 39.0, 42.0));
+        assert_eq!(stats.mean, 40.5);
+        assert_eq!(stats.median, 40.5);
+        assert!((stats.stddev - (5.0f64 / 3.0).sqrt()).abs() < 1e-9);
+
+        let stats = Stats::of(&[40.0]);
+        assert_eq!((stats.median, stats.stddev), (40.0, 0.0));
+
+        let stats = Stats::of(&[40.0, f32::NAN, 39.0]);
+        assert_eq!((stats.min, stats.median), (39.0, 40.0));
+        assert!(stats.max.is_nan() && stats.mean.is_nan());
+    }
+
+    #[test]
+    fn test_group_by() {
+        let groups = group_by(&records(), &[GroupKey::Pref, GroupKey::Decade]);
+        assert_eq!(
+            groups.iter().map(|g| (g.key.join(" "), g.stats.count)).collect::<Vec<_>>(),
+            vec![("埼玉県 1990s".to_string(), 1), ("埼玉県 2000s".to_string(), 2), ("高知県 2010s".to_string(), 1)]
+        );
+        assert_eq!(groups[1].stats.max, 40.9);
+
+        let groups = group_by(&records(), &[GroupKey::Month]);
+        assert_eq!(groups[0].key, vec!["07"]);
+
+        let groups = group_by(&records(), &[GroupKey::Block]);
+        assert_eq!(
+            groups.iter().map(|g| (g.key[0].as_str(), g.stats.count)).collect::<Vec<_>>(),
+            vec![("四国", 1), ("関東", 3)]
+        );
+    }
+
+    #[test]
+    fn test_top_n_per_group() {
+        let rankings = top_n_per_group(&records(), &[GroupKey::Pref], 2);
+        assert_eq!(rankings[0].key, vec!["埼玉県"]);
+        assert_eq!(
+            rankings[0].records.iter().map(|r| r.region.as_str()).collect::<Vec<_>>(),
+            vec!["熊谷", "越谷"]
+        );
+        assert_eq!(rankings[1].records.len(), 1);
+
+        let mut rs = records();
+        rs[0].temp = f32::NAN;
+        let rankings = top_n_per_group(&rs, &[GroupKey::Pref], 3);
+        assert_eq!(
+            rankings[0].records.iter().map(|r| r.region.as_str()).collect::<Vec<_>>(),
+            vec!["越谷", "鳩山", "熊谷"]
+        );
+    }
+
+    #[test]
+    fn test_output() {
+        let keys = [GroupKey::Pref];
+        let groups = group_by(&records()[..2], &keys);
+        assert_eq!(
+            groups_to_tsv(&keys, &groups),
+            "pref\tcount\tmin\tmax\tmean\tmedian\tstddev\n\
+             埼玉県\t1\t40.9\t40.9\t40.90\t40.90\t0.00\n\
+             高知県\t1\t41\t41\t41.00\t41.00\t0.00"
+        );
+        let json: Value = serde_json::from_str(&groups_to_json(&keys, &groups)).unwrap();
+        assert_eq!(json[1]["pref"], "高知県");
+        assert_eq!(json[1]["count"], 1);
+
+        let rankings = top_n_per_group(&records(), &keys, 1);
+        assert_eq!(
+            rankings_to_tsv(&keys, &rankings).lines().nth(2).unwrap(),
+            "高知県\t1\t高知県\t江川崎\t41\t2013-08-12"
+        );
+        let json: Value = serde_json::from_str(&rankings_to_json(&keys, &rankings)).unwrap();
+        assert_eq!(json[0]["records"][0]["region"], "熊谷");
+    }
+}
@@ -4,52 +4,263 @@ extern crate serde;
 extern crate chrono;
 
 use std::path::Path;
-use std::fs::File;
-use self::chrono::NaiveDate;
-use std::io::{Read};
-use super::rw;
+use std::io::{self, Read, Cursor};
 use std::fmt::Display;
 use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::marker::PhantomData;
+use self::chrono::NaiveDate;
+use self::serde::Serialize;
+use self::serde::de::DeserializeOwned;
+use super::rw;
+use super::frequency::{self, Frequency};
+use super::paste;
+use super::aggregate::{self, Group, GroupKey, Ranking};
+use super::format::{self, Format};
+use super::query::Table;
+use super::dedup::{Dedup, Deduped};
+use super::grep::Grep;
+use super::prefecture::{self, Collation, Enriched};
+
+/// a row of hightemp.txt
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Record {
+    pub pref: String,
+    pub region: String,
+    pub temp: f32,
+    pub date: NaiveDate,
+}
 
-#[derive(Debug,Deserialize, Serialize)]
-struct Record {
-    pref: String,
-    region: String,
-    temp: f32,
-    date: NaiveDate,
+/// a row which fails to be read or deserialized
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedRow {
+    /// line number beginning with 1
+    pub line: u64,
+    pub raw: String,
+    /// column name if the header exists, otherwise column index beginning with 0
+    pub field: Option<String>,
+    pub reason: String,
 }
 
-pub struct CSVExtractor<'a> {path: &'a Path}
+/// a row in the result of grep
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowHit<T> {
+    /// line number beginning with 1
+    pub line: u64,
+    pub record: T,
+    /// false for a context row
+    pub matched: bool,
+}
 
+#[derive(Debug)]
+pub struct ValidationReport<T> {
+    pub records: Vec<T>,
+    pub rejected: Vec<RejectedRow>,
+}
 
-impl<'a> CSVExtractor<'a> {
-    pub fn new<P: AsRef<Path> + ? Sized>(path: &P) -> CSVExtractor {
-        CSVExtractor { path: path.as_ref() }
+impl<T> ValidationReport<T> {
+    /// ratio of rejected rows in all rows
+    pub fn error_ratio(&self) -> f64 {
+        let total = self.records.len() + self.rejected.len();
+        if total == 0 {0.0} else {self.rejected.len() as f64 / total as f64}
     }
+}
 
-    fn deserialize(&self) -> Vec<Record> {
-        csv::ReaderBuilder::new()
-            .delimiter(b'\t')
-            .has_headers(false) // By default, the first row is treated as a special header row,
-            .from_reader(File::open(self.path).unwrap())
-            .deserialize::<Record>()
-            .flat_map(|s| s)
-            .collect()
+/// upper limit of rejected rows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    Count(usize),
+    Ratio(f64),
+}
+
+impl Threshold {
+    fn exceeded<T>(&self, report: &ValidationReport<T>) -> bool {
+        match *self {
+            Threshold::Count(n) => report.rejected.len() > n,
+            Threshold::Ratio(r) => report.error_ratio() > r,
+        }
+    }
+}
+
+/// extractor of delimiter-separated file whose row is deserialized into `T`.
+pub struct CSVExtractor<'a, T=Record> {
+    path: &'a Path,
+    delimiter: u8,
+    quoting: bool,
+    has_headers: bool,
+    threshold: Threshold,
+    /// output format of records. `None` is the raw rows without header.
+    format: Option<Format>,
+    schema: PhantomData<T>,
+}
+
+impl<'a> CSVExtractor<'a, Record> {
+    /// extractor of hightemp.txt, which is tab-separated without header.
+    pub fn new<P: AsRef<Path> + ? Sized>(path: &P) -> CSVExtractor<Record> {
+        CSVExtractor::with_schema(path)
+    }
+}
+
+impl<'a, T: DeserializeOwned + Serialize> CSVExtractor<'a, T> {
+    /// tab-separated and no header by default.
+    pub fn with_schema<P: AsRef<Path> + ? Sized>(path: &P) -> CSVExtractor<T> {
+        CSVExtractor {
+            path: path.as_ref(),
+            delimiter: b'\t',
+            quoting: true,
+            has_headers: false,
+            threshold: Threshold::Count(usize::max_value()),
+            format: None,
+            schema: PhantomData,
+        }
+    }
+
+    pub fn delimiter(mut self, delimiter: u8) -> CSVExtractor<'a, T> {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// whether `"` quotes a field. tsv without quotes may contain `"` as it is.
+    pub fn quoting(mut self, yes: bool) -> CSVExtractor<'a, T> {
+        self.quoting = yes;
+        self
+    }
+
+    /// whether the first row is the header
+    pub fn has_headers(mut self, yes: bool) -> CSVExtractor<'a, T> {
+        self.has_headers = yes;
+        self
+    }
+
+    /// output format of methods returning rendered records
+    pub fn format(mut self, format: Format) -> CSVExtractor<'a, T> {
+        self.format = Some(format);
+        self
+    }
+
+    /// render records in the format, or tab-separated rows without header.
+    fn render(&self, records: &[T]) -> String {
+        match self.format {
+            Some(format) => format::records(records, format),
+            None => self::serialize(records, '\t'),
+        }
+    }
+
+    /// render values of a column in the format, or a value per line.
+    fn render_column(&self, values: Vec<String>) -> String {
+        match self.format {
+            Some(format) => format::rows(None, &values.into_iter().map(|v| vec![v]).collect::<Vec<_>>(), format),
+            None => values.join("\n"),
+        }
+    }
+
+    /// upper limit of rejected rows in `validate`. unlimited by default.
+    pub fn error_threshold(mut self, threshold: Threshold) -> CSVExtractor<'a, T> {
+        self.threshold = threshold;
+        self
+    }
+
+    fn builder(&self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder.delimiter(self.delimiter)
+            .quoting(self.quoting)
+            .has_headers(self.has_headers);
+        builder
+    }
+
+    /// reader of the content decoded into UTF-8
+    fn reader(&self) -> csv::Reader<Cursor<Vec<u8>>> {
+        let content = rw::read(self.path).unwrap();
+        self.builder()
+            .from_reader(Cursor::new(content.into_bytes()))
+    }
+
+    /// parse all rows, and collect rejected rows with the reason.
+    /// Err if rejected rows exceed the threshold.
+    pub fn validate(&self) -> Result<ValidationReport<T>, ValidationReport<T>> {
+        let mut records = Vec::new();
+        let rejected = self.scan(|_, _, r| records.push(r));
+        let report = ValidationReport {records, rejected};
+        if self.threshold.exceeded(&report) {Err(report)} else {Ok(report)}
+    }
+
+    /// helper for validate and grep; pass each parsed row to `f` with its line number and fields,
+    /// and return the rejected rows.
+    fn scan<F: FnMut(u64, &csv::StringRecord, T)>(&self, mut f: F)->Vec<RejectedRow> {
+        let content = rw::read(self.path).unwrap();
+        let mut reader = self.builder().from_reader(content.as_bytes());
+        let headers = if self.has_headers {
+            Some(reader.headers().unwrap().clone())
+        } else {None};
+
+        let mut rejected = Vec::new();
+        let mut record = csv::StringRecord::new();
+        loop {
+            // the line where the record begins, since a quoted field may span lines
+            let start = reader.position().clone();
+            let res = reader.read_record(&mut record);
+            let raw = content[start.byte() as usize..reader.position().byte() as usize]
+                .trim_end_matches(|c| c == '\r' || c == '\n')
+                .to_string();
+            let line = start.line();
+            let err = match res {
+                Ok(false) => break,
+                Ok(true) => match record.deserialize::<T>(headers.as_ref()) {
+                    Ok(r) => {
+                        f(line, &record, r);
+                        continue;
+                    },
+                    Err(e) => e,
+                },
+                Err(e) => e,
+            };
+            let (field, reason) = match *err.kind() {
+                csv::ErrorKind::Deserialize {ref err, ..} => (
+                    err.field().map(|idx| {
+                        headers.as_ref()
+                            .and_then(|h| h.get(idx as usize))
+                            .map_or(idx.to_string(), |name| name.to_string())
+                    }),
+                    err.kind().to_string()
+                ),
+                _ => (None, err.to_string()),
+            };
+            info!("rejected line {}: {}", line, reason);
+            rejected.push(RejectedRow {line, raw, field, reason});
+        }
+        rejected
+    }
+
+    /// helper for the methods which skip rejected rows. panic if they exceed the threshold.
+    fn accept<R>(&self, report: ValidationReport<R>)->Vec<R> {
+        if self.threshold.exceeded(&report) {
+            panic!(
+                "{} of {} rows are rejected: {:?}",
+                report.rejected.len(),
+                report.rejected.len() + report.records.len(),
+                report.rejected.first()
+            );
+        }
+        report.records
+    }
+
+    /// rows which are parsed successfully. panic if rejected rows exceed the threshold.
+    fn deserialize(&self) -> Vec<T> {
+        let mut records = Vec::new();
+        let rejected = self.scan(|_, _, r| records.push(r));
+        self.accept(ValidationReport {records, rejected})
     }
 
     /// ch02.10 count lines
     pub fn count_lines(&self) -> usize {
-        csv::Reader::from_reader(File::open(self.path).unwrap())
+        self.reader()
             .records()
             .count()
     }
 
-    /// ch02.11 replace a tab-character to a space
+    /// ch02.11 replace a delimiter to a space
     pub fn replace_tab_to_space(&self) -> String {
-        csv::ReaderBuilder::new()
-            .delimiter(b'\t') // The default is b','.
-            .has_headers(false) // By default, the first row is treated as a special header row,
-            .from_reader(File::open(self.path).unwrap())
+        self.reader()
             .into_records()
             .map(|s|
                      s.unwrap()
@@ -61,19 +272,106 @@ impl<'a> CSVExtractor<'a> {
             .join("\n")
     }
 
-    /// helper for ch02.12; extract first & second row and return (String, String)
-    fn extract_first_second_row(&self) -> (String, String) {
-        let first_row = self.deserialize()
+    /// values of the field selected by `field` in each row
+    pub fn column<K, F: Fn(&T) -> K>(&self, field: F) -> Vec<K> {
+        self.deserialize()
+            .iter()
+            .map(field)
+            .collect()
+    }
+
+    /// unique values of the field in ascending order.
+    pub fn uniq<K: Ord, F: Fn(&T) -> K>(&self, field: F) -> Vec<K> {
+        let mut keys = self.column(field);
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    }
+
+    /// rows sorted by `compare`. equal rows keep the original order.
+    pub fn sort_by<F: Fn(&T, &T) -> Ordering>(&self, compare: F) -> Vec<T> {
+        let mut records = self.deserialize();
+        records.sort_by(compare);
+        records
+    }
+
+    /// frequency of values of the field. see also `frequency::count`.
+    pub fn frequency<K: ToString, F: Fn(&T) -> K>(&self, field: F) -> Vec<Frequency> {
+        frequency::count(
+            self.column(|r| field(r).to_string())
+        )
+    }
+
+    /// rows sorted by the frequency of the field in descending.
+    pub fn sort_by_frequency<K: ToString, F: Fn(&T) -> K>(&self, field: F) -> Vec<T> {
+        let precedence = self.frequency(&field)
             .into_iter()
-            .map(|s| s.pref)
-            .collect::<Vec<_>>()
-            .join("\n");
+            .enumerate()
+            .map(|(idx, f)| (f.key, idx))
+            .collect::<HashMap<String, usize>>();
 
+        let mut records = self.deserialize();
+        records.sort_by_key(|s| precedence[&field(s).to_string()]);
+        records
+    }
+
+    /// helper for grep; parsed rows with their line numbers and whether they match.
+    /// rejected rows never match as in `validate`, and panic if they exceed the threshold.
+    fn grep_rows(&self, grep: &Grep)->Vec<(u64, bool, T)> {
+        let mut rows = Vec::new();
+        let rejected = self.scan(|line, fields, r| {
+            rows.push((line, grep.is_match(&fields.iter().collect::<Vec<_>>()), r));
+        });
+        self.accept(ValidationReport {records: rows, rejected})
+    }
 
-        let second_row = self.deserialize()
+    /// rows whose columns match the conditions. the context of `grep` is ignored.
+    pub fn grep(&self, grep: &Grep)->Vec<T> {
+        self.grep_rows(grep)
             .into_iter()
-            .map(|s| s.region)
-            .collect::<Vec<_>>()
+            .filter(|&(_, matched, _)| matched)
+            .map(|(_, _, r)| r)
+            .collect()
+    }
+
+    /// `grep -c`; the number of rows which match
+    pub fn grep_count(&self, grep: &Grep)->usize {
+        self.grep_rows(grep)
+            .iter()
+            .filter(|&&(_, matched, _)| matched)
+            .count()
+    }
+
+    /// rows which match and the rows in their context, like `grep -B -A`
+    pub fn grep_hits(&self, grep: &Grep)->Vec<RowHit<T>> {
+        let rows = self.grep_rows(grep);
+        let shown = grep.with_context(&rows.iter().map(|&(_, matched, _)| matched).collect::<Vec<_>>());
+        rows.into_iter()
+            .enumerate()
+            .filter(|(idx, _)| shown.contains(idx))
+            .map(|(_, (line, matched, record))| RowHit {line, record, matched})
+            .collect()
+    }
+
+    /// rows without duplicates of the field, in the original order.
+    pub fn dedup_by<K: ToString, F: Fn(&T) -> K>(&self, field: F, dedup: &Dedup) -> io::Result<Deduped<T>> {
+        dedup.run(self.deserialize(), |r| field(r).to_string())
+    }
+
+    /// table named `name` for queries. field names are the columns, and their types are inferred.
+    /// columns of a tuple schema are named `c1`, `c2`, ...
+    pub fn table(&self, name: &str) -> Table {
+        Table::from_records(name, &self.deserialize())
+    }
+}
+
+impl<'a> CSVExtractor<'a, Record> {
+    /// helper for ch02.12; extract first & second row and return (String, String)
+    fn extract_first_second_row(&self) -> (String, String) {
+        let first_row = self.column(|s| s.pref.clone())
+            .join("\n");
+
+        let second_row = self.column(|s| s.region.clone())
             .join("\n");
 
         (first_row, second_row)
@@ -81,10 +379,11 @@ impl<'a> CSVExtractor<'a> {
 
     /// ch02.12; save first and second row in each file
     pub fn save_first_second_row<P: AsRef<Path>>(&self, pref_file: P, region_file: P) {
-        let (prefs, regions) = self.extract_first_second_row();
-
-        rw::write(prefs, pref_file);
-        rw::write(regions, region_file);
+        let format = rw::LineFormat::default();
+        let mut tx = rw::Transaction::new();
+        tx.write_lines(&self.column(|s| s.pref.clone()), pref_file, &format).unwrap();
+        tx.write_lines(&self.column(|s| s.region.clone()), region_file, &format).unwrap();
+        tx.commit().unwrap();
     }
 
     /// ch03.13; merge col1.txt and col2.txt and save on file
@@ -100,63 +399,58 @@ impl<'a> CSVExtractor<'a> {
 
     /// ch02.17 collect unique items in first row.
     pub fn uniq_first_row(&self)->String {
-        let mut prefs = self.deserialize()
-            .into_iter()
-            .map(|s| s.pref)
-            .collect::<Vec<_>>();
-        prefs.sort_unstable();
-        prefs.dedup();
-        prefs.join("\n")
+        self.render_column(self.uniq(|s| s.pref.clone()))
+    }
+
+    /// unique prefectures in the collation, e.g. from Hokkaido to Okinawa
+    pub fn uniq_first_row_by(&self, collation: Collation)->String {
+        self.render_column(collation.uniq(self.column(|s| s.pref.clone())))
     }
 
     /// ch02.18 sort by third columns in descending
     pub fn sort_temp_in_descending(&self)->String {
-        let mut records = self.deserialize();
-        records.sort_by(|s1, s2|
+        let records = self.sort_by(|s1, s2|
             s2.temp.partial_cmp(&(s1.temp)).unwrap()
         );
 
-        self::serialize(&records, '\t')
+        self.render(&records)
     }
 
     /// ch02.19 sort by the number of prefectures in descending.
     pub fn sort_by_frequent_item(&self)->String {
-        let mut hashmap = HashMap::new();
-        self.deserialize()
-            .into_iter()
-            .for_each(|key| {
-                *hashmap.entry(key.pref).or_insert(0) += 1;
-            });
-        let mut ordering = hashmap
-            .into_iter().collect::<Vec<(String, i32)>>();
-        ordering.sort_by_key(|&(_, count)| -count);
-
-        let precedence = ordering
-            .into_iter()
-            .enumerate()
-            .map(|(idx, (elem, _))| (elem, idx))
-            .collect::<HashMap<String, usize>>();
+        let records = self.sort_by_frequency(|s| s.pref.clone());
 
-        let mut records = self.deserialize();
+        self.render(&records)
+    }
 
-        records.sort_by_key(|s| precedence[&s.pref]);
+    /// statistics of temperature grouped by keys
+    pub fn group_by(&self, keys: &[GroupKey])->Vec<Group> {
+        aggregate::group_by(&self.deserialize(), keys)
+    }
 
-        self::serialize(&records, '\t')
+    /// the hottest n records in each group
+    pub fn top_n_per_group(&self, keys: &[GroupKey], n: usize)->Vec<Ranking> {
+        aggregate::top_n_per_group(&self.deserialize(), keys, n)
+    }
+
+    /// records joined with the JIS code and the region block of the prefecture
+    pub fn enrich(&self)->Vec<Enriched> {
+        prefecture::enrich(&self.deserialize())
     }
 }
 
 /// helper for ch03.13; merge col1.txt and col2.txt
 fn merge<S1: ToString, S2: ToString>(row1: &[S1], row2: &[S2], delimiter: char)->String {
-    row1.into_iter()
-        .zip(row2.into_iter())
-        .map(|(s1, s2)|
-            format!("{}{}{}", s1.to_string(), delimiter, s2.to_string()))
-        .collect::<Vec<String>>()
+    let inputs = vec![
+        row1.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+        row2.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+    ];
+    paste::paste(&inputs, &delimiter.to_string())
         .join("\n")
 }
 
 /// serialize
-fn serialize(records: &[Record], delimiter: char)->String {
+fn serialize<T: Serialize>(records: &[T], delimiter: char)->String {
     let mut wtr = csv::WriterBuilder::new()
         .delimiter(delimiter as u8)
         .has_headers(false)
@@ -175,14 +469,16 @@ fn serialize(records: &[Record], delimiter: char)->String {
 mod tests {
     use super::*;
     use ch02::command::Commander;
+    use dataset;
     #[test]
     fn test_new() {
-        let csvor = CSVExtractor::new("./data/ch02/hightemp.txt");
+        let path = dataset::resolve("hightemp");
+        let csvor = CSVExtractor::new(&path);
     }
 
     #[test]
     fn test_count_lines() {
-        let path = "./data/ch02/hightemp.txt";
+        let path = &dataset::resolve("hightemp");
         let csvor = CSVExtractor::new(path);
 
         assert_eq!(
@@ -193,7 +489,7 @@ mod tests {
 
     #[test]
     fn test_replace_tab_to_space() {
-        let path = "./data/ch02/hightemp.txt";
+        let path = &dataset::resolve("hightemp");
         let csvor = CSVExtractor::new(path);
 
         let commander = Commander::new(path);
@@ -206,7 +502,7 @@ mod tests {
 
     #[test]
     fn test_extract_first_second_row() {
-        let path = "./data/ch02/hightemp.txt";
+        let path = &dataset::resolve("hightemp");
         let csvor = CSVExtractor::new(path);
 
         let (prefs, regions) = csvor.extract_first_second_row();
@@ -226,7 +522,7 @@ mod tests {
 
     #[test]
     fn test_save_first_second_row() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
         let parent = load_path.parent().unwrap();
 
         let file1 = parent.join("col1.txt");
@@ -255,12 +551,18 @@ mod tests {
         assert_eq!(
             res,
             "aa\t11\nbb\t12\ncc\t13"
+        );
+
+        // shorter row is not truncated
+        assert_eq!(
+            merge(&row1, &row2[..2], '\t'),
+            "aa\t11\nbb\t12\ncc\t"
         )
     }
 
     #[test]
     fn test_save_merge() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
         let parent = load_path.parent().unwrap();
 
         let file1 = parent.join("col1.txt");
@@ -278,7 +580,7 @@ mod tests {
 
     #[test]
     fn test_uniq_first_row() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
         let csvor = CSVExtractor::new(&load_path);
 
         let commander = Commander::new(&load_path);
@@ -291,25 +593,307 @@ mod tests {
 
     #[test]
     fn test_sort_temp_in_descending() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
         let csvor = CSVExtractor::new(&load_path);
 
         let res = csvor.sort_temp_in_descending();
 
+        // temperatures are written by the serializer of f32, which keeps `.0` as other formats do.
         assert_eq!(
             res.lines().take(3).collect::<Vec<&str>>(),
-            vec!["高知県\t江川崎\t41\t2013-08-12", "埼玉県\t熊谷\t40.9\t2007-08-16", "岐阜県\t多治見\t40.9\t2007-08-16"]
+            vec!["高知県\t江川崎\t41.0\t2013-08-12", "埼玉県\t熊谷\t40.9\t2007-08-16", "岐阜県\t多治見\t40.9\t2007-08-16"]
         )
     }
 
     #[test]
     fn test_sort_by_frequent_item() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
         let csvor = CSVExtractor::new(&load_path);
 
         let res = csvor.sort_by_frequent_item();
+        let lines = res.lines().collect::<Vec<_>>();
+
+        // prefectures appearing 3 times come first in byte order, and rows of a prefecture keep the original order.
+        assert_eq!(
+            &lines[..4],
+            &["埼玉県\t熊谷\t40.9\t2007-08-16",
+              "埼玉県\t越谷\t40.4\t2007-08-16",
+              "埼玉県\t鳩山\t39.9\t1997-07-05",
+              "山形県\t山形\t40.8\t1933-07-25"]
+        );
+        assert_eq!(lines.len(), 24);
+        assert_eq!(lines.last(), Some(&"高知県\t江川崎\t41.0\t2013-08-12"));
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Station {
+        name: String,
+        pref: String,
+        height: u32,
+    }
+
+    #[test]
+    fn test_with_schema() {
+        let path = "./data/ch02/stations.csv";
+        rw::write(
+            "name,pref,height\n江川崎,高知県,90\n熊谷,埼玉県,30\n\"多治見, 岐阜\",岐阜県,95\n越谷,埼玉県,5\n",
+            path
+        ).unwrap();
+
+        let csvor = CSVExtractor::<Station>::with_schema(path)
+            .delimiter(b',')
+            .has_headers(true);
+
+        assert_eq!(csvor.column(|s| s.height), vec![90, 30, 95, 5]);
+        assert_eq!(csvor.uniq(|s| s.pref.clone()), vec!["埼玉県", "岐阜県", "高知県"]);
+        assert_eq!(
+            csvor.sort_by(|s1, s2| s2.height.cmp(&s1.height))[0].name,
+            "多治見, 岐阜"
+        );
+        assert_eq!(
+            csvor.sort_by_frequency(|s| s.pref.clone())
+                .into_iter()
+                .map(|s| s.name)
+                .collect::<Vec<_>>(),
+            vec!["熊谷", "越谷", "多治見, 岐阜", "江川崎"]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let path = "./data/ch02/hightemp_broken.txt";
+        rw::write(
+            "高知県\t江川崎\t41\t2013-08-12\n埼玉県\t熊谷\t高い\t2007-08-16\n\
+            岐阜県\t多治見\t40.9\t2007-08-16\n山形県\t山形\t40.8\t1933-07-32\n山梨県\t甲府\n",
+            path
+        ).unwrap();
+
+        let csvor = CSVExtractor::new(path);
+        assert_eq!(csvor.count_lines(), 5);
+
+        let report = csvor.validate().unwrap();
+        assert_eq!(report.records.len(), 2);
+        assert_eq!(
+            report.rejected.iter().map(|r| (r.line, r.field.clone())).collect::<Vec<_>>(),
+            // chrono doesn't tell which field is invalid
+            vec![(2, Some("2".to_string())), (4, None), (5, None)]
+        );
+        assert_eq!(report.rejected[0].raw, "埼玉県\t熊谷\t高い\t2007-08-16");
+        assert_eq!(report.rejected[1].reason, "input is out of range");
+
+        assert!(csvor.error_threshold(Threshold::Ratio(0.5)).validate().is_err());
+        assert!(CSVExtractor::new(path).error_threshold(Threshold::Count(3)).validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_with_headers() {
+        let path = "./data/ch02/stations_broken.csv";
+        rw::write("name,pref,height\n江川崎,高知県,90\n熊谷,埼玉県,-30\n", path).unwrap();
+
+        let report = CSVExtractor::<Station>::with_schema(path)
+            .delimiter(b',')
+            .has_headers(true)
+            .validate()
+            .unwrap();
+        assert_eq!(report.records.len(), 1);
+        assert_eq!(report.rejected[0].line, 3);
+        assert_eq!(report.rejected[0].field, Some("height".to_string()));
+    }
+
+    #[test]
+    fn test_validate_multiline_field() {
+        let path = "./data/ch02/stations_multiline.csv";
+        rw::write("name,pref,height\n\"熊谷\n(埼玉)\",埼玉県,-30\n江川崎,高知県,90\n館林,群馬県,x\n", path).unwrap();
+
+        let report = CSVExtractor::<Station>::with_schema(path)
+            .delimiter(b',')
+            .has_headers(true)
+            .validate()
+            .unwrap();
+        assert_eq!(report.records.len(), 1);
+        assert_eq!(report.rejected.iter().map(|r| r.line).collect::<Vec<_>>(), vec![2, 5]);
+        assert_eq!(report.rejected[0].raw, "\"熊谷\n(埼玉)\",埼玉県,-30");
+    }
+
+    #[test]
+    fn test_group_by() {
+        let path = dataset::resolve("hightemp");
+        let csvor = CSVExtractor::new(&path);
+
+        let groups = csvor.group_by(&[GroupKey::Pref]);
+        assert_eq!(groups.len(), 12);
+        let saitama = groups.iter().find(|g| g.key == vec!["埼玉県"]).unwrap();
+        assert_eq!((saitama.stats.count, saitama.stats.max, saitama.stats.min), (3, 40.9, 39.9));
+
+        // the hottest record per prefecture per decade
+        let rankings = csvor.top_n_per_group(&[GroupKey::Pref, GroupKey::Decade], 1);
+        let yamagata = rankings.iter()
+            .filter(|r| r.key[0] == "山形県")
+            .map(|r| (r.key[1].as_str(), r.records[0].region.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(yamagata, vec![("1930s", "山形"), ("1970s", "酒田")]);
+    }
+
+    #[test]
+    fn test_prefecture() {
+        let path = dataset::resolve("hightemp");
+        let csvor = CSVExtractor::new(&path);
+
+        assert_eq!(
+            csvor.uniq_first_row_by(Collation::Jis).lines().collect::<Vec<_>>(),
+            vec!["山形県", "群馬県", "埼玉県", "千葉県", "山梨県", "岐阜県", "静岡県", "愛知県", "大阪府", "和歌山県", "愛媛県", "高知県"]
+        );
+
+        let enriched = csvor.enrich();
+        assert_eq!(enriched.len(), 24);
+        assert!(enriched.iter().all(|r| r.code.is_some()));
+        assert_eq!(
+            format::records(&enriched[..1], Format::Tsv).lines().collect::<Vec<_>>(),
+            vec!["pref\tregion\ttemp\tdate\tcode\tblock", "高知県\t江川崎\t41.0\t2013-08-12\t39\t四国"]
+        );
+
+        // hightemp by region block
+        let groups = csvor.group_by(&[GroupKey::Block]);
+        assert_eq!(groups.iter().map(|g| g.stats.count).sum::<usize>(), 24);
+        let kanto = groups.iter().find(|g| g.key == vec!["関東"]).unwrap();
+        assert_eq!(kanto.stats.count, enriched.iter().filter(|r| r.block == Some(prefecture::Block::Kanto)).count());
+    }
+
+    #[test]
+    fn test_grep() {
+        let path = dataset::resolve("hightemp");
+        let csvor = CSVExtractor::new(&path);
+
+        let grep = Grep::new()
+            .range(2, Some(40.5), None)
+            .dates(3, "2000-01-01".parse().ok(), None);
+        let records = csvor.grep(&grep);
+        assert_eq!(
+            records,
+            csvor.column(|r| r.clone())
+                .into_iter()
+                .filter(|r| r.temp >= 40.5 && r.date >= NaiveDate::from_ymd_opt(2000, 1, 1).unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(csvor.grep_count(&grep), records.len());
+        assert_eq!(csvor.grep(&grep.clone().invert(true)).len(), csvor.count_lines() - records.len());
+
+        // context rows around 熊谷, which is the second line
+        let hits = csvor.grep_hits(&Grep::new().fixed(1, "熊谷").context(1, 1));
+        assert_eq!(
+            hits.iter().map(|h| (h.line, h.record.region.as_str(), h.matched)).collect::<Vec<_>>(),
+            vec![(1, "江川崎", false), (2, "熊谷", true), (3, "多治見", false)]
+        );
+    }
+
+    #[test]
+    fn test_grep_rejected() {
+        let path = "./data/ch02/hightemp_grep_broken.txt";
+        rw::write("高知県\t江川崎\t41\t2013-08-12\n埼玉県\t熊谷\tabc\t2007-08-16\n山形県\t山形\t40.8\t1933-07-25\n", path).unwrap();
+        let grep = Grep::new().regex(0, "県$").unwrap();
+
+        // the broken row is skipped within the threshold
+        let csvor = CSVExtractor::new(path).error_threshold(Threshold::Count(1));
+        assert_eq!(csvor.grep_count(&grep), 2);
+        assert_eq!(csvor.grep_hits(&grep).iter().map(|h| h.line).collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_grep_rejected_over_threshold() {
+        let path = "./data/ch02/hightemp_grep_broken_strict.txt";
+        rw::write("高知県\t江川崎\t41\t2013-08-12\n埼玉県\t熊谷\tabc\t2007-08-16\n", path).unwrap();
+        let csvor = CSVExtractor::new(path).error_threshold(Threshold::Count(0));
+        csvor.grep(&Grep::new().regex(0, "県$").unwrap());
+    }
+
+    #[test]
+    fn test_dedup_by() {
+        use ch02::dedup::Keep;
+
+        let path = dataset::resolve("hightemp");
+        let csvor = CSVExtractor::new(&path);
+
+        // the latest record of each date
+        let res = csvor.dedup_by(|r| r.date, &Dedup::new().keep(Keep::Last)).unwrap();
+        assert_eq!(res.rows.len(), csvor.uniq(|r| r.date).len());
+        let date = res.dropped.keys[0].key.parse::<NaiveDate>().unwrap();
+        assert_eq!(
+            res.rows.iter().find(|r| r.date == date),
+            csvor.column(|r| r.clone()).iter().filter(|r| r.date == date).last()
+        );
+    }
+
+    #[test]
+    fn test_query() {
+        use ch02::query::Database;
+
+        let path = dataset::resolve("hightemp");
+        let csvor = CSVExtractor::new(&path);
+        let db = Database::new().register(csvor.table("hightemp"));
+
+        let res = db.query("SELECT pref, max(temp) FROM hightemp GROUP BY pref ORDER BY 2 DESC").unwrap();
+        assert_eq!(res.columns, vec!["pref", "max(temp)"]);
+        assert_eq!(res.rows.len(), csvor.uniq(|s| s.pref.clone()).len());
+        assert_eq!(res.render(Format::Tsv).lines().nth(1).unwrap(), "高知県\t41");
+
+        // same as group_by
+        let res = db.query("SELECT pref, count(*), min(temp) FROM hightemp GROUP BY pref ORDER BY pref").unwrap();
+        let groups = csvor.group_by(&[GroupKey::Pref]);
+        assert_eq!(
+            res.rows.iter().map(|r| (r[0].to_string(), r[1].to_string(), r[2].to_string())).collect::<Vec<_>>(),
+            groups.iter().map(|g| (g.key[0].clone(), g.stats.count.to_string(), g.stats.min.to_string())).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_cp932() {
+        let path = "./data/ch02/hightemp_cp932.txt";
+        let content = rw::read(dataset::resolve("hightemp")).unwrap();
+        rw::write_with_charset(&content, path, rw::Charset::ShiftJis).unwrap();
+
+        let records = CSVExtractor::new(path).deserialize();
+        assert_eq!(records.len(), 24);
+        assert_eq!(records[0].region, "江川崎");
+    }
+
+    #[test]
+    fn test_format() {
+        let path = dataset::resolve("hightemp");
+        let csvor = CSVExtractor::new(&path).format(Format::Json);
+        assert!(
+            csvor.sort_temp_in_descending()
+                .starts_with(r#"[{"pref":"高知県","region":"江川崎","temp":41.0,"date":"2013-08-12"},"#)
+        );
+
+        let csvor = csvor.format(Format::Markdown);
+        let res = csvor.sort_by_frequent_item();
+        let lines = res.lines().collect::<Vec<_>>();
+        assert_eq!(lines[..2].to_vec(), vec!["| pref | region | temp | date |", "| --- | --- | --- | --- |"]);
+        assert_eq!(lines.len(), 2 + 24);
+
+        let csvor = csvor.format(Format::JsonLines);
+        assert_eq!(csvor.sort_by_frequent_item().lines().count(), 24);
+        assert_eq!(csvor.uniq_first_row().lines().next(), Some(r#"["千葉県"]"#));
+    }
+
+    #[test]
+    fn test_tuple_schema() {
+        // a tuple fits any file without defining a struct
+        let path = dataset::resolve("hightemp");
+        let csvor = CSVExtractor::<(String, String, f32, String)>::with_schema(&path);
+
+        let freqs = csvor.frequency(|r| r.0.clone());
+        assert_eq!((freqs[0].key.as_str(), freqs[0].count), ("埼玉県", 3));
+        assert_eq!(
+            csvor.uniq(|r| r.0.clone()).join("\n"),
+            CSVExtractor::new(&path).uniq_first_row()
+        );
 
-        eprintln!("{:?}", res.lines().collect::<Vec<_>>());
+        // columns are named by position, and the first row is not a header
+        let table = csvor.table("hightemp");
+        assert_eq!(table.columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["c1", "c2", "c3", "c4"]);
+        assert_eq!(table.rows.len(), csvor.count_lines());
     }
 
 }
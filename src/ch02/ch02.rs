@@ -1,16 +1,69 @@
-use std::io::{BufReader, BufRead, Read, BufWriter, Write};
+use std::io::{BufReader, Read, BufWriter, Write};
 use std::fs::File;
 use std::io::{self, Result as ioResult};
 use std::path::Path;
 use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
 
 use super::{rw, util};
-
-struct FileExtractor<'a> {path: &'a Path}
+use super::sort::SortSpec;
+use super::extsort::ExternalSort;
+use super::frequency::{self, Frequency};
+use super::cut::{Cutter, Fields};
+use super::paste;
+use super::join::Join;
+use super::tail::{self, Follow};
+use super::format::{self, Format};
+use super::wc::Wc;
+use super::expand::{self, Tabs, TabStops};
+use super::sample::{self, Rng};
+use super::dedup::{Dedup, Deduped, Dropped};
+use super::grep::{self, Grep, Hit};
+use super::index::{IndexCache, LineIndex};
+use super::partition::{Partitioner, Part};
+use super::prefecture::Collation;
+
+struct FileExtractor<'a> {
+    path: &'a Path,
+    format: Format,
+    indexed: bool,
+    /// kept while the extractor lives, and reopened when the file changes
+    index: IndexCache,
+}
 
 impl<'a> FileExtractor<'a> {
     pub fn new<P: AsRef<Path>+?Sized>(path: &P)-> FileExtractor {
-        FileExtractor {path: path.as_ref()}
+        let path = path.as_ref();
+        FileExtractor {path, format: Format::default(), indexed: false, index: IndexCache::new(path)}
+    }
+
+    /// count_lines, head, tail, line and lines_range use the sidecar line index, for files queried repeatedly.
+    /// compressed and UTF-16 files are read without the index.
+    pub fn indexed(mut self, yes: bool)->FileExtractor<'a> {
+        self.indexed = yes;
+        self
+    }
+
+    /// helper for the line index if `indexed`. None if not indexed or the file cannot be indexed.
+    fn index(&self)->Option<Rc<LineIndex>> {
+        if !self.indexed {
+            return None;
+        }
+        self.index
+            .get()
+            .unwrap_or_else(|e| panic!("fail to index {:?}: {}", self.path, e))
+    }
+
+    /// output format of the methods returning String. tab-separated lines by default.
+    pub fn format(mut self, format: Format)->FileExtractor<'a> {
+        self.format = format;
+        self
+    }
+
+    /// render tab-separated lines in the format, e.g. the result of `cut` or `sort_by_spec`
+    pub fn render<S: AsRef<str>>(&self, lines: &[S])->String {
+        format::lines(lines, self.format)
     }
 
     /// helper for read designated file. ignore error
@@ -25,9 +78,13 @@ impl<'a> FileExtractor<'a> {
 
     /// ch02.10 count lines
     pub fn count_lines(&self)->usize {
-        self.read_lines()
+        if let Some(index) = self.index() {
+            return index.newlines();
+        }
+        Wc::new()
+            .count(self.path)
             .unwrap()
-            .len()
+            .lines
     }
 
     /// ch02.11 replace a tab-character to a space
@@ -37,45 +94,78 @@ impl<'a> FileExtractor<'a> {
             .replace("\t", " ")
     }
 
+    /// `expand` of each line. columns are aligned by the display width.
+    pub fn expand(&self, tabs: &Tabs)->Vec<String> {
+        self.read_lines()
+            .unwrap()
+            .iter()
+            .map(|line| tabs.expand(line))
+            .collect()
+    }
+
+    /// `unexpand` of each line
+    pub fn unexpand(&self, tabs: &Tabs)->Vec<String> {
+        self.read_lines()
+            .unwrap()
+            .iter()
+            .map(|line| tabs.unexpand(line))
+            .collect()
+    }
+
+    /// `cut -f`; see also `Cutter`
+    pub fn cut(&self, cutter: &Cutter)->Result<Vec<String>, String> {
+        cutter.cut(&self.read_lines().unwrap())
+    }
+
     /// helper for ch02.12
     /// n: col index beginning with 0.
     fn extract_row(&self, n: usize)->Vec<String> {
-        self.read_lines()
+        self.cut(&Cutter::new(Fields::single(n + 1)))
             .unwrap()
-            .into_iter()
-            .map(|line| {
-                line.split('\t')
-                    .nth(n)
-                    .unwrap()
-                    .to_string()
-            })
-            .collect()
+    }
+
+    /// save columns selected by each cutter in each file.
+    /// files are replaced all together, so none of them is changed on error.
+    pub fn save_columns<P: AsRef<Path>>(&self, outputs: &[(Cutter, P)])->ioResult<()> {
+        let lines = self.read_lines()?;
+        let mut tx = rw::Transaction::new();
+        for &(ref cutter, ref file) in outputs {
+            let v = cutter.cut(&lines)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            tx.write_lines(&v, file, &rw::LineFormat::default())?;
+        }
+        tx.commit().map(|_| ())
     }
 
     /// ch02.12; save first and second row in each file
     pub fn save_first_second_row<T: AsRef<Path>>(&self, file1: &T, file2: &T) {
-        vec![file1, file2]
-            .into_iter()
-            .enumerate()
-            .map(|(idx, file)| {
-                let v = self.extract_row(idx);
-                let _ = rw::write_lines(&v, file)
-                    .unwrap();
-            })
-            .collect::<Vec<_>>();
+        self.save_columns(&[
+            (Cutter::new(Fields::single(1)), file1),
+            (Cutter::new(Fields::single(2)), file2),
+        ]).unwrap();
     }
 
     /// helper for ch03.13; merge col1.txt and col2.txt
     fn merge<S: AsRef<str>>(row1: &Vec<S>, row2: &Vec<S>)-> String {
-        row1.iter()
-            .zip(row2.iter())
-            .map(|(item1, item2)|
-                format!("{}{}{}", item1.as_ref(), '\t', item2.as_ref())
-            )
-            .collect::<Vec<_>>()
+        paste::paste(&[row1, row2], "\t")
             .join("\n")
     }
 
+    /// `paste -d ${delimiters} ${files}`, or `paste -s` if serial.
+    pub fn paste<P: AsRef<Path>>(files: &[P], delimiters: &str, serial: bool)->ioResult<Vec<String>> {
+        let inputs = files.iter()
+            .map(|file| rw::read_lines(file))
+            .collect::<ioResult<Vec<_>>>()?;
+        Ok(
+            if serial {paste::paste_serial(&inputs, delimiters)} else {paste::paste(&inputs, delimiters)}
+        )
+    }
+
+    /// join rows with those of other file; see also `Join`
+    pub fn join<P: AsRef<Path>>(&self, other: P, join: &Join)->ioResult<Vec<String>> {
+        Ok(join.join(&self.read_lines()?, &rw::read_lines(other)?))
+    }
+
     /// ch03.13; save result of merge method.
     pub fn save_merge<T1: AsRef<Path>, T2: AsRef<Path>>(file1: T1, file2: T1, save_file: T2) {
         let lines = vec![file1, file2]
@@ -93,24 +183,100 @@ impl<'a> FileExtractor<'a> {
 
     /// ch02.14 take first ${num} lines
     pub fn head(&self, n: usize)->String {
-        self.read_lines()
+        if let Some(index) = self.index() {
+            return self.render(&index.head(n).unwrap());
+        }
+        let lines = self.read_lines()
             .unwrap()
             .into_iter()
             .take(n)
-            .collect::<Vec<_>>()
-            .join("\n")
+            .collect::<Vec<_>>();
+        self.render(&lines)
     }
 
     /// ch02.15 tail last ${num} lines
+    /// seek backward from the end, so the file is not read entirely.
     pub fn tail(&self, n: usize)->String {
-        let mut v = self.read_lines()
+        if let Some(index) = self.index() {
+            return self.render(&index.tail(n).unwrap());
+        }
+        self.render(&tail::last_lines(self.path, n).unwrap())
+    }
+
+    /// the n-th line beginning with 0 by the line index, or by reading through if not indexed
+    pub fn line(&self, n: usize)->Option<String> {
+        match self.index() {
+            Some(index) => index.line(n).unwrap(),
+            None => self.read_lines().unwrap().into_iter().nth(n),
+        }
+    }
+
+    /// lines in the range beginning with 0 by the line index, or by reading through if not indexed
+    pub fn lines_range(&self, range: Range<usize>)->Vec<String> {
+        match self.index() {
+            Some(index) => index.lines(range).unwrap(),
+            None => self.read_lines()
+                .unwrap()
+                .into_iter()
+                .skip(range.start)
+                .take(range.end.saturating_sub(range.start))
+                .collect(),
+        }
+    }
+
+    /// `tail -c ${n}`
+    pub fn tail_bytes(&self, n: u64)->Vec<u8> {
+        tail::last_bytes(self.path, n)
             .unwrap()
-            .into_iter()
-            .rev()
-            .take(n)
-            .collect::<Vec<_>>();
-        v.reverse();
-        v.join("\n")
+    }
+
+    /// `tail -n +${n}`; lines from the n-th line beginning with 1.
+    pub fn tail_from(&self, n: usize)->String {
+        self.render(&tail::from_line(self.path, n).unwrap())
+    }
+
+    /// `shuf`; all lines in random order
+    pub fn shuffle<R: Rng>(&self, rng: &mut R)->String {
+        self.render(&sample::shuffle(self.read_lines().unwrap(), rng))
+    }
+
+    /// `shuf -n ${k}` by reservoir sampling, so lines are read one at a time without holding all of them.
+    /// lines keep their order.
+    pub fn sample<R: Rng>(&self, k: usize, rng: &mut R)->String {
+        let lines = rw::open_lines(self.path)
+            .unwrap()
+            .map(|line| line.unwrap());
+        self.render(&sample::reservoir(lines, k, rng))
+    }
+
+    /// each line with the probability
+    pub fn sample_by_rate<R: Rng>(&self, rate: f64, rng: &mut R)->String {
+        let lines = rw::open_lines(self.path)
+            .unwrap()
+            .map(|line| line.unwrap());
+        self.render(&sample::bernoulli(lines, rate, rng))
+    }
+
+    /// the rate of lines for each value of the column beginning with 0
+    pub fn sample_stratified<R: Rng>(&self, column: usize, rate: f64, rng: &mut R)->String {
+        let lines = sample::stratified(
+            self.read_lines().unwrap(),
+            |line| line.split('\t').nth(column).unwrap_or("").to_string(),
+            rate,
+            rng
+        );
+        self.render(&lines)
+    }
+
+    /// shuffled lines split by the ratios, e.g. train, dev and test sets by `[8, 1, 1]`.
+    /// Err if no ratio is positive.
+    pub fn split_random<R: Rng>(&self, ratios: &[usize], rng: &mut R)->Result<Vec<Vec<String>>, String> {
+        sample::split(self.read_lines().unwrap(), ratios, rng)
+    }
+
+    /// `tail -F -n 0`; iterator of appended lines
+    pub fn follow(&self)->ioResult<Follow> {
+        Follow::new(self.path)
     }
 
     /// helper for ch02.16 return String
@@ -129,7 +295,7 @@ impl<'a> FileExtractor<'a> {
     }
 
     /// ch02.16 split ${n} files
-    /// return is success count of saving files.
+    /// return is success count of saving files. no file is saved if any of them fails.
     pub fn save_split<P: AsRef<Path>>(&self, n: usize, dst: P)->usize {
         let vs = self.split(n);
         let save_path = dst.as_ref();
@@ -151,16 +317,21 @@ impl<'a> FileExtractor<'a> {
                 .take(n)
                 .collect::<Vec<_>>();
 
-
-        // write files
+        // write files with the trailing newline like `split -l`
+        let mut tx = rw::Transaction::new();
         vs.iter()
             .zip(filenames.iter())
-            .map(|(s, file)|
-                     rw::write(s, file)
-            )
-            .collect::<Result<Vec<_>,_>>()
-            .unwrap()
-            .len()
+            .for_each(|(s, file)|
+                tx.write(format!("{}\n", s), file).unwrap()
+            );
+        tx.commit().unwrap()
+    }
+
+    /// write each value of the key column to its own file, like `awk '{print > $1}'`.
+    /// lines are decoded in the detected charset, and the partitions are written in UTF-8.
+    /// return the manifest of partitions and their row counts.
+    pub fn save_partition(&self, partitioner: &Partitioner)->ioResult<Vec<Part>> {
+        partitioner.run_results(rw::open_lines(self.path)?)
     }
 
     /// ch02.17 collect unique items in first row.
@@ -168,77 +339,126 @@ impl<'a> FileExtractor<'a> {
         let mut lines = self.extract_row(0);
         lines.sort_unstable();
         lines.dedup();
-        lines.join("\n")
+        self.render(&lines)
+    }
+
+    /// unique items in first row in the collation, e.g. prefectures from Hokkaido to Okinawa
+    pub fn uniq_first_row_by(&self, collation: Collation)->String {
+        self.render(&collation.uniq(self.extract_row(0)))
+    }
+
+    /// lines selected by the conditions on columns, with their context
+    pub fn grep(&self, grep: &Grep)->Vec<Hit> {
+        grep.lines(&self.read_lines().unwrap())
+    }
+
+    /// result of `grep` in the format. groups of context are separated by `--` in TSV like grep.
+    pub fn grep_text(&self, grep: &Grep)->String {
+        let hits = self.grep(grep);
+        match self.format {
+            Format::Tsv => grep::render(&hits, grep.has_context()),
+            _ => self.render(&hits.iter().map(|h| h.text.as_str()).collect::<Vec<_>>()),
+        }
+    }
+
+    /// `grep -c`
+    pub fn grep_count(&self, grep: &Grep)->usize {
+        grep.count(&self.read_lines().unwrap())
+    }
+
+    /// lines without duplicates of the columns beginning with 0, in the original order.
+    pub fn dedup(&self, cols: &[usize], dedup: &Dedup)->ioResult<Deduped<String>> {
+        dedup.lines(self.read_lines()?, cols)
+    }
+
+    /// save lines without duplicates of the columns, reading a line at a time.
+    /// with Keep::First a kept line is written as soon as it is read,
+    /// but Keep::Last buffers the last line of each key until the input ends.
+    pub fn save_dedup<P: AsRef<Path>>(&self, cols: &[usize], dedup: &Dedup, save_path: P)->ioResult<Dropped> {
+        let mut w = rw::create(save_path)?;
+        let mut error = None;
+        let lines = rw::open_lines(self.path)?
+            .map_while(|line| line.map_err(|e| error = Some(e)).ok());
+        let dropped = dedup.stream(lines, |line| frequency::key_of(line, cols), |line| writeln!(w, "{}", line))?;
+        if let Some(e) = error {
+            return Err(e);
+        }
+        w.finish()?;
+        Ok(dropped)
     }
 
     /// ch02.18 sort by third columns in descending
+    /// n: col index beginning with 1. equal rows keep the original order.
     pub fn sort_in_descending(&self, n: usize)->Vec<String> {
-        let s = self.read().unwrap();
-        let delimiter = '\t';
-        let mut lines = s.lines()
-            .map(|line|
-                     line.split(delimiter)
-                         .collect::<Vec<&str>>())
-        .collect::<Vec<Vec<&str>>>();
-
-        lines.sort_by(|line1, line2| {
-            let f1 = line1[n-1].parse::<f32>().unwrap();
-            let f2 = line2[n-1].parse::<f32>().unwrap();
-            f2.partial_cmp(&f1).unwrap() // in descending
-        });
+        let spec = format!("-s -k{0},{0}nr", n)
+            .parse::<SortSpec>()
+            .unwrap();
+        self.sort_by_spec(&spec)
+    }
+
+    /// sort lines by the key specification like `-k3,3nr -k4,4M -k1,1`
+    pub fn sort_by_spec(&self, spec: &SortSpec)->Vec<String> {
+        let mut lines = self.read_lines().unwrap();
+        spec.sort(&mut lines);
+        lines
+    }
 
-        lines.iter().map(|line| line.join(&format!("{}",delimiter))).collect()
+    /// sort lines like `sort_by_spec` within the memory, and save them. return the number of lines.
+    /// a big file is sorted with temporary files in the directory of `save_path`.
+    pub fn sort_external<P: AsRef<Path>>(&self, spec: &SortSpec, save_path: P, memory: usize)->ioResult<usize> {
+        let tmp_dir = save_path.as_ref()
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."))
+            .to_path_buf();
+        ExternalSort::new(spec.clone())
+            .memory(memory)
+            .tmp_dir(tmp_dir)
+            .sort_file(self.path, save_path)
+    }
+
+    /// frequency of items in designated columns beginning with 0.
+    pub fn frequency(&self, cols: &[usize])->Vec<Frequency> {
+        frequency::count(
+            self.read_lines()
+                .unwrap()
+                .iter()
+                .map(|line| frequency::key_of(line, cols))
+        )
     }
 
     /// ch02.19 sort by the number of prefectures listing first columns.
+    /// ties are ordered by prefecture in byte order, and rows of the same prefecture keep the original order.
     pub fn sort_by_frequent_item(&self)->Vec<String> {
-        // generate ordering key
-        let mut hashmap = HashMap::new();
-        for key in self.extract_row(0) {
-            let counter = hashmap.entry(key).or_insert(0);
-            *counter += 1;
-        }
-        let mut partial_ordering = hashmap.into_iter()
-            .collect::<Vec<(String, i32)>>();
-
-        partial_ordering.sort_by_key(|&(_, count)| -count);
-        // parfect ordering
-        let ordering = partial_ordering.into_iter()
+        let ordering = self.frequency(&[0])
+            .into_iter()
             .enumerate()
-            .map(|(idx, (s, _))| (s, idx))
+            .map(|(idx, f)| (f.key, idx))
             .collect::<HashMap<String, usize>>();
         info!("{:?}", ordering);
 
-        let delimiter = "\t";
-        let s = self.read().unwrap();
-        let mut lines = s.lines()
-            .map(|line|
-                line.split(delimiter)
-                    .collect::<Vec<_>>()
-            )
-            .collect::<Vec<_>>();
-        info!("{:?}", lines);
-        (&mut lines).sort_by_key(|line| {
-            ordering[line[0]]
+        let mut lines = self.read_lines().unwrap();
+        lines.sort_by_key(|line| {
+            ordering[&frequency::key_of(line, &[0])]
         });
-
-        lines.into_iter()
-            .map(|s| s.join(delimiter)).collect()
-
+        lines
     }
-
-
 }
 
 #[cfg(test)]
 mod test {
     use ch02::command::Commander;
+    use ch02::join::JoinKind;
+    use dataset;
+    use ch02::index;
+    use std::fs;
     use super::*;
     extern crate glob;
 
     #[test]
     fn test_read() {
-        let fext = FileExtractor::new("./data/ch02/hightemp.txt");
+        let path = dataset::resolve("hightemp");
+        let fext = FileExtractor::new(&path);
         let buf = fext.read().unwrap();
 
         assert_eq!(
@@ -249,7 +469,8 @@ mod test {
 
     #[test]
     fn test_read_lines() {
-        let fxt = FileExtractor::new("./data/ch02/hightemp.txt");
+        let path = dataset::resolve("hightemp");
+        let fxt = FileExtractor::new(&path);
 
         let res = fxt.read_lines();
 
@@ -261,7 +482,7 @@ mod test {
 
     #[test]
     fn test_ch02_10_count_lines() {
-        let path = "./data/ch02/hightemp.txt";
+        let path = &dataset::resolve("hightemp");
         let fxt = FileExtractor::new(path);
 
         let commander = Commander::new(path);
@@ -270,7 +491,7 @@ mod test {
 
     #[test]
     fn test_ch02_11_replace_tab_to_space() {
-        let path = "./data/ch02/hightemp.txt";
+        let path = &dataset::resolve("hightemp");
         let fxt = FileExtractor::new(path);
 
         let commander = Commander::new(path);
@@ -281,9 +502,27 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_expand() {
+        let path = dataset::resolve("hightemp");
+        let fxt = FileExtractor::new(&path);
+        let tabs = Tabs::new().stops(TabStops::Every(4));
+
+        let lines = fxt.read_lines().unwrap();
+        let expanded = fxt.expand(&tabs);
+        for (line, ex) in lines.iter().zip(expanded.iter()) {
+            // the second column starts at the next tab stop of the first column
+            let pref = line.split('\t').next().unwrap();
+            let pad = (expand::width(pref) / 4 + 1) * 4 - expand::width(pref);
+            assert!(ex.starts_with(&format!("{}{}", pref, " ".repeat(pad))));
+            assert!(!ex.contains('\t'));
+        }
+        assert_eq!(FileExtractor::new(&path).unexpand(&tabs).len(), lines.len());
+    }
+
     #[test]
     fn test_ch02_12_helper_extract_row() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
         let fxt = FileExtractor::new(load_path);
 
         let commander = Commander::new(load_path);
@@ -296,7 +535,7 @@ mod test {
 
     #[test]
     fn test_ch02_12_extract_first_second_row() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
         let parent = load_path.parent().unwrap();
 
         let file1 = parent.join("col1.txt");
@@ -314,19 +553,63 @@ mod test {
         assert!(file1.exists());
         assert!(file2.exists());
 
-        // confirm that content of file2 is equivalent to cut command.
+        // confirm that content of file2 is equivalent to cut command including the trailing newline.
         let mut line = String::new();
         let mut reader = BufReader::new(File::open(file2).unwrap());
         let _ = reader.read_to_string(&mut line);
 
         let commander = Commander::new(load_path);
 
-        assert_eq!(commander.extract_row(1), line);
+        assert_eq!(format!("{}\n", commander.extract_row(1)), line);
+    }
+
+    #[test]
+    fn test_cut() {
+        let load_path = &dataset::resolve("hightemp");
+        let fxt = FileExtractor::new(load_path);
+
+        let commander = Commander::new(load_path);
+
+        vec![
+            (Cutter::new("1,3-".parse().unwrap()), vec!["-f", "1,3-"]),
+            (Cutter::new("2".parse().unwrap()).complement(true), vec!["--complement", "-f", "2"]),
+            (Cutter::new("-2,4".parse().unwrap()).output_delimiter(","), vec!["-f", "-2,4", "--output-delimiter=,"]),
+        ].into_iter()
+            .for_each(|(cutter, args)| {
+                assert_eq!(
+                    fxt.cut(&cutter).unwrap().join("\n"),
+                    commander.cut(&args)
+                )
+            });
+    }
+
+    #[test]
+    fn test_save_columns() {
+        let load_path = &dataset::resolve("hightemp");
+        let parent = load_path.parent().unwrap();
+
+        let file1 = parent.join("col_24.txt");
+        let file2 = parent.join("col_3.txt");
+
+        let fxt = FileExtractor::new(load_path);
+        fxt.save_columns(&[
+            (Cutter::new("2,4".parse().unwrap()), &file1),
+            (Cutter::new(Fields::single(3)), &file2),
+        ]).unwrap();
+
+        assert_eq!(
+            rw::read_lines(&file1).unwrap()[0],
+            "江川崎\t2013-08-12"
+        );
+        assert_eq!(
+            rw::read_lines(&file2).unwrap()[0],
+            "41"
+        );
     }
 
     #[test]
     fn test_merge() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
         let fxt = FileExtractor::new(load_path);
 
         let lines1 = fxt.extract_row(0);
@@ -343,9 +626,53 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_paste() {
+        let load_path = &dataset::resolve("hightemp");
+        let parent = load_path.parent().unwrap();
+
+        let fxt = FileExtractor::new(load_path);
+        let head = parent.join("head_3.txt");
+        rw::write(fxt.head(3), &head).unwrap();
+        let files = [load_path.to_path_buf(), head.clone(), load_path.to_path_buf()];
+
+        assert_eq!(
+            FileExtractor::paste(&files, ",;", false).unwrap().join("\n"),
+            Commander::new(&files[0]).paste(&files[1..], &["-d", ",;"])
+        );
+        assert_eq!(
+            FileExtractor::paste(&files, "\t", true).unwrap().join("\n"),
+            Commander::new(&files[0]).paste(&files[1..], &["-s"])
+        );
+    }
+
+    #[test]
+    fn test_join() {
+        let load_path = &dataset::resolve("hightemp");
+        let parent = load_path.parent().unwrap();
+
+        // join command requires inputs sorted by keys
+        let left = parent.join("hightemp_sorted.txt");
+        let right = parent.join("pref_block.txt");
+        rw::write(Commander::new(load_path).sort_by_spec("-s -k1,1"), &left).unwrap();
+        rw::write("千葉県\t関東\n埼玉県\t関東\n山形県\t東北\n東京都\t関東\n高知県\t四国", &right).unwrap();
+
+        let fxt = FileExtractor::new(&left);
+        vec![
+            (JoinKind::Inner, vec![]),
+            (JoinKind::Left, vec!["-a1", "-e", "", "-o", "auto"]),
+        ].into_iter()
+            .for_each(|(kind, args)| {
+                assert_eq!(
+                    fxt.join(&right, &Join::new(1, 1).kind(kind)).unwrap().join("\n"),
+                    Commander::new(&left).join(&right, &args)
+                )
+            });
+    }
+
     #[test]
     fn test_save_merge() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
         let parent = load_path.parent().unwrap();
 
         let file1 = parent.join("col1.txt");
@@ -364,7 +691,7 @@ mod test {
 
     #[test]
     fn test_head() {
-        let load_path = "./data/ch02/hightemp.txt";
+        let load_path = &dataset::resolve("hightemp");
         let fxt = FileExtractor::new(load_path);
         let n = 5;
 
@@ -375,9 +702,46 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_sample() {
+        let path = dataset::resolve("hightemp");
+        let fxt = FileExtractor::new(&path);
+        let lines = fxt.read_lines().unwrap();
+
+        let shuffled = fxt.shuffle(&mut sample::seeded(1));
+        assert_eq!(shuffled, fxt.shuffle(&mut sample::seeded(1)));
+        let mut sorted = shuffled.lines().collect::<Vec<_>>();
+        sorted.sort();
+        let mut expected = lines.clone();
+        expected.sort();
+        assert_eq!(sorted, expected);
+
+        let sampled = fxt.sample(5, &mut sample::seeded(1));
+        assert_eq!(sampled.lines().count(), 5);
+        assert!(sampled.lines().all(|line| lines.contains(&line.to_string())));
+        assert_eq!(fxt.sample_by_rate(1.0, &mut sample::seeded(1)).lines().count(), lines.len());
+
+        // one line per prefecture at least
+        let stratified = fxt.sample_stratified(0, 0.01, &mut sample::seeded(1));
+        let prefs = lines.iter().map(|line| line.split('\t').next().unwrap()).collect::<::std::collections::HashSet<_>>();
+        assert_eq!(stratified.lines().count(), prefs.len());
+        assert_eq!(fxt.sample_stratified(0, 0.0, &mut sample::seeded(1)), "");
+
+        let splits = fxt.split_random(&[8, 1, 1], &mut sample::seeded(1)).unwrap();
+        assert_eq!(splits.iter().map(|s| s.len()).sum::<usize>(), lines.len());
+        assert!(fxt.split_random(&[], &mut sample::seeded(1)).is_err());
+
+        // Shift_JIS file is decoded line by line
+        let sjis_path = "./data/ch02/hightemp_sample_sjis.txt";
+        rw::write_with_charset(&rw::read(&path).unwrap(), sjis_path, rw::Charset::ShiftJis).unwrap();
+        let sjis = FileExtractor::new(sjis_path);
+        assert_eq!(sjis.sample(5, &mut sample::seeded(1)), sampled);
+        assert_eq!(sjis.sample_by_rate(1.0, &mut sample::seeded(1)), fxt.sample_by_rate(1.0, &mut sample::seeded(1)));
+    }
+
     #[test]
     fn test_tail() {
-        let load_path = "./data/ch02/hightemp.txt";
+        let load_path = &dataset::resolve("hightemp");
         let fxt = FileExtractor::new(load_path);
         let n = 5;
 
@@ -388,9 +752,97 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_indexed() {
+        let load_path = &dataset::resolve("hightemp");
+        let fxt = FileExtractor::new(load_path).indexed(true);
+        let commander = Commander::new(load_path);
+
+        assert_eq!(fxt.count_lines(), commander.count_lines().unwrap());
+        assert_eq!(fxt.head(5), commander.head(5));
+        assert_eq!(fxt.tail(5), commander.tail(5));
+        assert_eq!(fxt.line(2).unwrap(), commander.head(3).lines().nth(2).unwrap());
+        assert_eq!(fxt.line(24), None);
+        assert_eq!(
+            fxt.lines_range(3..6),
+            commander.head(6).lines().skip(3).collect::<Vec<_>>()
+        );
+
+        // the index is kept for the extractor while the file is not modified
+        let copy_path = "./data/ch02/hightemp_indexed.txt";
+        rw::write(rw::read(load_path).unwrap(), copy_path).unwrap();
+        let copy = FileExtractor::new(copy_path).indexed(true);
+        assert_eq!(copy.line(0), fxt.line(0));
+        fs::remove_file(index::sidecar(copy_path)).unwrap();
+        assert_eq!(copy.tail(5), fxt.tail(5));
+        assert!(!index::sidecar(copy_path).exists());
+
+        // and reopened after it is modified. the last line without newline is not counted like `wc -l`
+        rw::write("高知県\t江川崎\n埼玉県\t熊谷", copy_path).unwrap();
+        assert_eq!(copy.count_lines(), 1);
+        assert_eq!(copy.count_lines(), Commander::new(copy_path).count_lines().unwrap());
+        assert_eq!(copy.tail(1), "埼玉県\t熊谷");
+        assert_eq!(copy.line(1).unwrap(), "埼玉県\t熊谷");
+        assert!(index::sidecar(copy_path).exists());
+
+        // line and lines_range read through without `indexed`
+        let plain = FileExtractor::new(copy_path);
+        fs::remove_file(index::sidecar(copy_path)).unwrap();
+        assert_eq!(plain.line(1), copy.line(1));
+        assert_eq!(plain.lines_range(0..2), copy.lines_range(0..2));
+        assert!(!index::sidecar(copy_path).exists());
+
+        // compressed files are read without the index
+        let gz_path = "./data/ch02/hightemp_indexed.txt.gz";
+        rw::write(rw::read(load_path).unwrap(), gz_path).unwrap();
+        let gz = FileExtractor::new(gz_path).indexed(true);
+        assert_eq!(gz.count_lines(), fxt.count_lines());
+        assert_eq!(gz.head(5), fxt.head(5));
+        assert_eq!(gz.tail(5), fxt.tail(5));
+        assert_eq!(gz.line(2), fxt.line(2));
+        assert_eq!(gz.lines_range(3..6), fxt.lines_range(3..6));
+    }
+
+    #[test]
+    fn test_format() {
+        let load_path = &dataset::resolve("hightemp");
+        let fxt = FileExtractor::new(load_path).format(Format::Csv);
+        assert_eq!(fxt.head(1), "高知県,江川崎,41,2013-08-12");
+
+        let fxt = fxt.format(Format::JsonLines);
+        assert_eq!(fxt.tail(1), r#"["愛知県","名古屋","39.9","1942-08-02"]"#);
+
+        let fxt = fxt.format(Format::Table);
+        assert_eq!(fxt.tail_from(24).split("  ").filter(|s| !s.is_empty()).count(), 4);
+
+        let fxt = fxt.format(Format::Markdown);
+        assert_eq!(fxt.uniq_first_row().lines().nth(2), Some("| 千葉県 |"));
+        assert_eq!(fxt.render(&fxt.cut(&Cutter::new("1,3".parse().unwrap())).unwrap()).lines().nth(2), Some("| 高知県 | 41 |"));
+        assert_eq!(
+            fxt.grep_text(&Grep::new().fixed(1, "熊谷")),
+            "| 1 | 2 | 3 | 4 |\n| --- | --- | --- | --- |\n| 埼玉県 | 熊谷 | 40.9 | 2007-08-16 |"
+        );
+    }
+
+    #[test]
+    fn test_tail_bytes_and_offsets() {
+        let load_path = &dataset::resolve("hightemp");
+        let fxt = FileExtractor::new(load_path);
+
+        let commander = Commander::new(load_path);
+        assert_eq!(
+            fxt.tail_bytes(50),
+            commander.tail_with(&["-c", "50"]).as_bytes()
+        );
+        assert_eq!(
+            fxt.tail_from(20),
+            commander.tail_with(&["-n", "+20"]).trim_end()
+        );
+    }
+
     #[test]
     fn test_split() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
 
         let fxt = FileExtractor::new(&load_path);
 
@@ -425,9 +877,9 @@ mod test {
 
     #[test]
     fn test_save_split() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
 
-        let fxt = FileExtractor::new("./data/ch02/hightemp.txt");
+        let fxt = FileExtractor::new(load_path);
 
         let n = 3;
         // check number of success in files must be equal to n
@@ -438,9 +890,39 @@ mod test {
 
     }
 
+    #[test]
+    fn test_save_partition() {
+        let load_path = &dataset::resolve("hightemp");
+        let fxt = FileExtractor::new(load_path);
+        let commander = Commander::new(load_path);
+
+        let partitioner = Partitioner::new(0)
+            .dir("./data/ch02/partition_hightemp")
+            .template("hightemp-{key}.txt")
+            .max_open(3);
+        let manifest = fxt.save_partition(&partitioner).unwrap();
+        assert_eq!(manifest.iter().map(|p| p.rows).sum::<usize>(), fxt.count_lines());
+        for part in &manifest {
+            assert_eq!(
+                rw::read(&part.path).unwrap().trim_end(),
+                commander.grep(&[&format!("^{}\t", part.key)])
+            );
+        }
+
+        // keys of a CP932 file are decoded
+        let cp932_path = "./data/ch02/hightemp_partition_cp932.txt";
+        rw::write_with_charset(&rw::read(load_path).unwrap(), cp932_path, rw::Charset::ShiftJis).unwrap();
+        let cp932 = FileExtractor::new(cp932_path).save_partition(&partitioner.clone().dir("./data/ch02/partition_cp932")).unwrap();
+        assert_eq!(cp932, manifest.iter().map(|p| Part {
+            path: Path::new("./data/ch02/partition_cp932").join(p.path.file_name().unwrap()),
+            ..p.clone()
+        }).collect::<Vec<_>>());
+        assert_eq!(rw::read(&cp932[0].path).unwrap(), rw::read(&manifest[0].path).unwrap());
+    }
+
     #[test]
     fn test_uniq_first_row() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
 
         let fxt = FileExtractor::new(load_path);
 
@@ -451,9 +933,71 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_uniq_first_row_by() {
+        let load_path = &dataset::resolve("hightemp");
+        let fxt = FileExtractor::new(load_path);
+
+        assert_eq!(fxt.uniq_first_row_by(Collation::Bytes), fxt.uniq_first_row());
+        let prefs = fxt.uniq_first_row_by(Collation::Jis);
+        assert_eq!(prefs.lines().next(), Some("山形県"));
+        assert_eq!(prefs.lines().last(), Some("高知県"));
+        assert_eq!(prefs.lines().count(), fxt.uniq_first_row().lines().count());
+    }
+
+    #[test]
+    fn test_grep() {
+        let load_path = &dataset::resolve("hightemp");
+        let fxt = FileExtractor::new(load_path);
+        let commander = Commander::new(load_path);
+
+        // prefecture names appear only in the first column
+        let hits = fxt.grep(&Grep::new().fixed(0, "埼玉").context(1, 2));
+        assert_eq!(grep::render(&hits, true), commander.grep(&["-B", "1", "-A", "2", "埼玉"]));
+        assert_eq!(fxt.grep_text(&Grep::new().fixed(0, "埼玉").context(1, 2)), grep::render(&hits, true));
+        let inverted = Grep::new().regex(0, "^(埼玉|山形)").unwrap().invert(true);
+        assert_eq!(grep::render(&fxt.grep(&inverted), false), commander.grep(&["-v", "-E", "埼玉|山形"]));
+        assert_eq!(fxt.grep_count(&inverted).to_string(), commander.grep(&["-c", "-v", "-E", "埼玉|山形"]));
+
+        // rows where temp >= 40.5 and date after 2000
+        let hot = Grep::new()
+            .range(2, Some(40.5), None)
+            .dates(3, "2000-01-01".parse().ok(), None);
+        assert!(fxt.grep(&hot).iter().all(|h| h.matched && h.text.split('\t').nth(3).unwrap() >= "2000"));
+        assert_eq!(fxt.grep_count(&hot), 5);
+    }
+
+    #[test]
+    fn test_dedup() {
+        use ch02::dedup::Keep;
+
+        let load_path = &dataset::resolve("hightemp");
+        let fxt = FileExtractor::new(load_path);
+        let lines = fxt.read_lines().unwrap();
+
+        // the same prefectures as uniq_first_row, in order of appearance
+        let res = fxt.dedup(&[0], &Dedup::new()).unwrap();
+        let mut prefs = res.rows.iter().map(|line| line.split('\t').next().unwrap()).collect::<Vec<_>>();
+        assert_eq!(res.rows[0], lines[0]);
+        prefs.sort();
+        assert_eq!(prefs.join("\n"), fxt.uniq_first_row());
+        assert_eq!(res.dropped.total, lines.len() - res.rows.len());
+        let saitama = res.dropped.keys.iter().find(|f| f.key == "埼玉県").unwrap();
+        assert_eq!(saitama.count, 2);
+
+        let last = fxt.dedup(&[0], &Dedup::new().keep(Keep::Last)).unwrap();
+        assert_eq!(last.rows.last(), lines.last());
+        assert_eq!(last.rows.len(), res.rows.len());
+
+        let save_path = "./data/ch02/hightemp_dedup.txt.gz";
+        let dropped = fxt.save_dedup(&[0], &Dedup::new().bloom(100, 0.01).unwrap(), save_path).unwrap();
+        assert_eq!(rw::read_lines(save_path).unwrap(), res.rows);
+        assert_eq!(dropped.total, res.dropped.total);
+    }
+
     #[test]
     fn test_sort_in_descending() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
 
         let fxt = FileExtractor::new(load_path);
         let commander = Commander::new(load_path);
@@ -472,14 +1016,78 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_sort_by_spec() {
+        let load_path = &dataset::resolve("hightemp");
+
+        let fxt = FileExtractor::new(load_path);
+        let commander = Commander::new(load_path);
+
+        vec!["-s -k3,3nr", "-k3,3nr -k4,4r", "-s -k1,1 -k3,3n", "-u -k1,1", "-r"]
+            .into_iter()
+            .for_each(|spec| {
+                assert_eq!(
+                    fxt.sort_by_spec(&spec.parse().unwrap()).join("\n"),
+                    commander.sort_by_spec(spec),
+                    "{}", spec
+                )
+            });
+    }
+
+    #[test]
+    fn test_sort_external() {
+        let load_path = &dataset::resolve("hightemp");
+        let save_path = "./data/ch02/sorted_external.txt";
+
+        let fxt = FileExtractor::new(load_path);
+        let commander = Commander::new(load_path);
+
+        vec!["-s -k3,3nr", "-k3,3nr -k4,4r", "-u -k1,1", "-r"]
+            .into_iter()
+            .for_each(|spec| {
+                let count = fxt.sort_external(&spec.parse().unwrap(), save_path, 128).unwrap();
+                let expected = commander.sort_by_spec(spec);
+                assert_eq!(rw::read(save_path).unwrap().trim_end(), expected, "{}", spec);
+                assert_eq!(count, expected.lines().count());
+            });
+    }
+
+    #[test]
+    fn test_frequency() {
+        let load_path = &dataset::resolve("hightemp");
+
+        let fxt = FileExtractor::new(load_path);
+        let commander = Commander::new(load_path);
+
+        // the padding of counts depends on the flavor of `uniq -c`
+        let res = commander.frequency(0);
+        let parsed = res.lines()
+            .map(|line| {
+                let (count, key) = line.trim_start().split_once(' ').unwrap();
+                (count.parse::<usize>().unwrap(), key)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fxt.frequency(&[0]).iter().map(|f| (f.count, f.key.as_str())).collect::<Vec<_>>(),
+            parsed
+        );
+    }
+
     #[test]
     fn test_sort_by_frequent_item() {
-        let fxt = FileExtractor::new("./data/ch02/hightemp.txt");
+        let path = dataset::resolve("hightemp");
+        let fxt = FileExtractor::new(&path);
 
         let res = fxt.sort_by_frequent_item();
 
-        // confirm manually
-        // hashmap takes values at random!
-        eprintln!("{:?}", res)
+        // 埼玉県, 山形県, 山梨県 and 群馬県 appear 3 times, and they are ordered in byte order.
+        assert_eq!(
+            &res[..4],
+            &["埼玉県\t熊谷\t40.9\t2007-08-16",
+              "埼玉県\t越谷\t40.4\t2007-08-16",
+              "埼玉県\t鳩山\t39.9\t1997-07-05",
+              "山形県\t山形\t40.8\t1933-07-25"]
+        );
+        assert_eq!(res.last().unwrap(), "高知県\t江川崎\t41\t2013-08-12");
     }
 }
\ No newline at end of file
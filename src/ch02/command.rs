@@ -1,100 +1,170 @@
-use std::process::{Command, Stdio};
-use std::io::{BufReader, BufRead, Read, Write}; // Read is used for read_to_string
-use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-pub struct Commander {path: String}
+use dataset;
+use super::runner::{Flavor, Invocation, Outcome, Runner, SystemRunner};
+
+/// default limit of each command
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+fn system_runner()->SystemRunner {
+    SystemRunner::new().timeout(TIMEOUT)
+}
+
+/// run the invocation, and panic unless it succeeds.
+fn run_checked(runner: &dyn Runner, invocation: Invocation)->Outcome {
+    runner.run(&invocation)
+        .and_then(|outcome| outcome.check(&invocation))
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// reference implementation with command line tools
+pub struct Commander {path: PathBuf, runner: Arc<dyn Runner>, flavor: Flavor}
 
 impl Commander {
     pub fn new<P: AsRef<Path>>(save_path: P) -> Commander {
+        Commander::with_runner(save_path, Arc::new(system_runner()))
+    }
+
+    /// run commands with the runner, e.g. `Replay` in tests
+    pub fn with_runner<P: AsRef<Path>>(save_path: P, runner: Arc<dyn Runner>) -> Commander {
         Commander {
-            path: save_path.as_ref().to_str().expect("contains invalid utf-8 character").to_owned()
+            path: save_path.as_ref().to_path_buf(),
+            runner,
+            flavor: Flavor::platform(),
         }
     }
 
-    /// preparation for ch02; save the tab-splited file, hightemp.txt
-    /// exec `curl http://www.cl.ecei.tohoku.ac.jp/nlp100/data/hightemp.txt" -o ${save_path}`
+    /// override the flavor of the platform
+    pub fn flavor(mut self, flavor: Flavor) -> Commander {
+        self.flavor = flavor;
+        self
+    }
+
+    /// invocation with the options in the flavor, given in GNU style. panic if not supported.
+    fn command(&self, program: &str, args: &[&str]) -> Invocation {
+        let args = self.flavor.args(program, args)
+            .unwrap_or_else(|e| panic!("{}", e));
+        Invocation::new(program).args(args)
+    }
+
+    fn run(&self, invocation: Invocation) -> Outcome {
+        run_checked(&*self.runner, invocation)
+    }
+
+    /// run commands connecting stdout to stdin of the next, like `cmd1 | cmd2`.
+    /// the system runner streams between commands without buffering each output.
+    fn pipe(&self, invocations: Vec<Invocation>) -> Outcome {
+        self.runner.run_pipeline(&invocations)
+            .and_then(|outcome| outcome.check_pipeline(&invocations))
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// preparation for ch02; save the tab-splited file, hightemp.txt, unless it exists.
+    /// the file is imported from the mirror or downloaded through the dataset registry.
     /// The file is the record of maximum temperature in Japan.
     /// these data are composed of prefecture, location, temperature and date.
     pub fn prepare(&self) {
-        let path = "http://www.cl.ecei.tohoku.ac.jp/nlp100/data/hightemp.txt";
-        let output = Command::new("curl")
-            .arg(path)
-            .args(&["-o", &self.path])
-            .output().expect("fail to execute process");
-
-        debug!("status: {:?}", output.status);
-        debug!("stdout: {}", String::from_utf8_lossy(&output.stdout));
-        debug!("stderr: \n{}", String::from_utf8_lossy(&output.stderr));
+        if self.path.exists() {
+            debug!("{:?} already exists", self.path);
+            return;
+        }
+        dataset::registry()
+            .fetch_to("hightemp", &self.path)
+            .unwrap_or_else(|e| panic!("{}", e));
     }
 
     /// test ch02_10; count lines in the designated file.
+    /// the count is the first word, because BSD `wc` pads it with spaces.
     pub fn count_lines(&self) -> Result<usize, ::std::num::ParseIntError> {
-        let output = Command::new("wc")
-            .arg("-l")
-            .arg(&self.path)
-            .output().expect("fail to execute process");
+        let outcome = self.run(self.command("wc", &["-l"]).arg(&self.path));
 
-        String::from_utf8_lossy(&output.stdout)
-            .as_ref()
-            .trim()
-            .split(" ")
-            .take(1)
-            .collect::<String>()
+        outcome.stdout_lossy()
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
             .parse::<usize>()
     }
 
     /// ch02_11; replace tab to space
     pub fn replace_tab_to_space(&self) -> String {
-        let mut cat = Command::new("cat")
-            .arg(&self.path)
-            .stdout(Stdio::piped())
-            .spawn().expect("fail to execute cat command");
-        let mut tr = Command::new("tr")
-            .arg("[:blank:]")
-            .arg(" ")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn().expect("fail to execute tr command");
-        // see https://www.reddit.com/r/rust/comments/3azfie/how_to_pipe_one_process_into_another/
-        if let Some(ref mut stdout) = cat.stdout {
-            if let Some(ref mut stdin) = tr.stdin {
-                let mut buf: Vec<u8> = Vec::new();
-                stdout.read_to_end(&mut buf).unwrap();
-                stdin.write_all(&buf).unwrap();
-            }
-        }
-        let res = tr.wait_with_output().unwrap().stdout;
-        String::from_utf8(res).expect("contain invalid utf-8 character")
+        let outcome = self.pipe(vec![
+            Invocation::new("cat").arg(&self.path),
+            self.command("tr", &["[:blank:]", " "]),
+        ]);
+        String::from_utf8(outcome.stdout).expect("contain invalid utf-8 character")
     }
 
     /// preparation to ch02_12
     pub fn extract_row(&self, n: usize) -> String {
-        let res = Command::new("cut")
-            .args(&["-f", &format!("{}", n + 1)]) // start at 0
-            .arg(&self.path)
-            .output().expect("fail to execute cut command");
+        let outcome = self.run(
+            self.command("cut", &["-f", &format!("{}", n + 1)]) // start at 0
+                .arg(&self.path)
+        );
+
+        outcome.stdout_lossy().trim().to_string()
+    }
+
+    /// `cut ${args} ${file}`. GNU extensions like `--complement` panic on BSD.
+    pub fn cut(&self, args: &[&str])->String {
+        let outcome = self.run(self.command("cut", args).arg(&self.path));
 
-        String::from_utf8_lossy(&res.stdout).trim().to_string()
+        outcome.stdout_lossy().trim_end().to_string()
     }
 
-    /// ch02.13 merge 2 files
+    /// `grep ${args} ${file}`. no match is not a failure, and results in an empty string.
+    pub fn grep(&self, args: &[&str])->String {
+        let invocation = self.command("grep", args).arg(&self.path);
+        let outcome = self.runner.run(&invocation).unwrap_or_else(|e| panic!("{}", e));
+        if outcome.code == Some(1) {
+            return String::new();
+        }
+        outcome.check(&invocation)
+            .unwrap_or_else(|e| panic!("{}", e))
+            .stdout_lossy()
+            .trim_end()
+            .to_string()
+    }
+
+    /// ch02.13 merge two files, `paste ${file1} ${file2}`
     pub fn merge<P: AsRef<Path>>(file1: &P, file2: &P)->String {
-        let res = Command::new("paste")
-            .args(&[file1.as_ref(), file2.as_ref()])
-            .output().expect("fail to execute paste command");
+        Commander::new(file1)
+            .paste(&[file2], &[])
+            .trim()
+            .to_string()
+    }
 
-        String::from_utf8_lossy(&res.stdout).trim().to_string()
+    /// `paste ${args} ${file} ${others}`
+    pub fn paste<P: AsRef<Path>>(&self, others: &[P], args: &[&str])->String {
+        let outcome = self.run(
+            self.command("paste", args)
+                .arg(&self.path)
+                .args(others.iter().map(|f| f.as_ref()))
+        );
+
+        outcome.stdout_lossy().trim_end().to_string()
+    }
+
+    /// `join -t '\t' ${args} ${file} ${other}` in the C locale. inputs must be sorted by keys.
+    pub fn join<P: AsRef<Path>>(&self, other: &P, args: &[&str])->String {
+        let outcome = self.run(
+            self.command("join", &[&["-t", "\t"], args].concat())
+                .env("LC_ALL", "C")
+                .args([self.path.as_path(), other.as_ref()])
+        );
+
+        outcome.stdout_lossy().trim_end().to_string()
     }
 
     /// helper for ch02. 14&15
     fn take(&self, n: usize, pos: &str)->String {
-        let res = Command::new(pos)
-            .args(&["-n", format!("{}", n).as_str()])
-            .arg(&self.path)
-            .output().expect("fail to execute head command");
+        let outcome = self.run(
+            self.command(pos, &["-n", format!("{}", n).as_str()])
+                .arg(&self.path)
+        );
 
-        String::from_utf8_lossy(&res.stdout).trim().to_string()
+        outcome.stdout_lossy().trim().to_string()
     }
 
     /// ch02.14 `head -n ${file}`
@@ -107,65 +177,82 @@ impl Commander {
         self.take(n, "tail")
     }
 
-    /// ch02.16 split n files.
+    /// `tail ${args} ${file}` without trimming
+    pub fn tail_with(&self, args: &[&str])->String {
+        self.run(self.command("tail", args).arg(&self.path))
+            .stdout_lossy()
+    }
+
+    /// ch02.16 split n files. panic if `split` fails.
     pub fn split<P: AsRef<Path>>(&self, n: usize, dst: &P) {
         let size = self.count_lines().unwrap();
         use ch02::util;
         let lines = util::get_split_line_count(size, n);
         debug!("split per {} lines", lines);
         assert!(lines >0);
-        let res = Command::new("split")
-            .args(&["-l", &format!("{}", lines)])
-            .arg(&self.path) // src
-            .arg(dst.as_ref().to_str().unwrap()) // dst
-            .output()
-            .expect("fail to execute split command");
+        self.run(
+            self.command("split", &["-l", &format!("{}", lines)])
+                .arg(&self.path) // src
+                .arg(dst.as_ref()) // dst
+        );
     }
 
     /// ch02.17 take unique items of first row.
     pub fn uniq_first_row(&self)->String {
-        let cutf1 = Command::new("cut")
-            .args(&["-f", "1"])
-            .arg(&self.path)
-            .stdout(Stdio::piped())
-            .spawn().unwrap();
-
-        let sort = Command::new("sort")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn().unwrap();
-
         // note that sort and uniq cannot be swapped.
         // uniq command makes duplicated items in sequence single!
-        let mut uniq = Command::new("uniq")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn().unwrap();
-
-        let mut buf: Vec<u8> = Vec::new();
-        cutf1.stdout.unwrap().read_to_end(&mut buf).unwrap();
-        sort.stdin.unwrap().write_all(&buf).unwrap();
-        let mut buf: Vec<u8> = Vec::new();
-        sort.stdout.unwrap().read_to_end(&mut buf).unwrap();
-        if let Some(ref mut stdin) = uniq.stdin {
-            stdin.write_all(&buf).unwrap();
-        }
-        // wait_with_output(self) -> Result<Output>
-        let res = uniq.wait_with_output().unwrap().stdout;
-        String::from_utf8_lossy(&res).trim().to_string()
+        let outcome = self.pipe(vec![
+            self.command("cut", &["-f", "1"]).arg(&self.path),
+            self.command("sort", &[]).env("LC_ALL", "C"),
+            self.command("uniq", &[]).env("LC_ALL", "C"),
+        ]);
+        outcome.stdout_lossy().trim().to_string()
+    }
 
+    /// ch02.19 count items in the designated column beginning with 0.
+    /// that means `cut -f ${n} | sort | uniq -c | sort -k1,1nr -k2,2` in the C locale.
+    /// counts are padded to the width of `uniq -c` in the flavor.
+    pub fn frequency(&self, n: usize)->String {
+        let outcome = self.pipe(vec![
+            self.command("cut", &["-f", &format!("{}", n + 1)]).arg(&self.path),
+            self.command("sort", &[]).env("LC_ALL", "C"),
+            self.command("uniq", &["-c"]).env("LC_ALL", "C"),
+            // order by count, and ties by item
+            self.command("sort", &["-k1,1nr", "-k2,2"]).env("LC_ALL", "C"),
+        ]);
+        let width = self.flavor.uniq_count_width();
+        outcome.stdout_lossy()
+            .lines()
+            .map(|line| {
+                let line = line.trim_start();
+                let idx = line.find(' ').unwrap_or(line.len());
+                format!("{:>width$}{}", &line[..idx], &line[idx..], width = width)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 
     /// ch02.18 sort by third columns descendingly
     /// that means `sort -r -k 3 ./data/ch02/hightemp.txt`
     pub fn sort_in_descending(&self, key: usize)->String {
-        let res = Command::new("sort")
-            .arg("-r")
-            .args(&["-k", &format!("{}", key)])
-            .arg(&self.path)
-            .output().unwrap();
+        let outcome = self.run(
+            self.command("sort", &["-r", "-k", &format!("{}", key)])
+                .arg(&self.path)
+        );
+
+        outcome.stdout_lossy().trim().to_string()
+    }
+
+    /// sort tab-separated lines with options like `-s -k3,3nr -k1,1` in the C locale.
+    /// note that `M` means month names in GNU sort.
+    pub fn sort_by_spec(&self, spec: &str)->String {
+        let outcome = self.run(
+            self.command("sort", &[&["-t", "\t"], &spec.split_whitespace().collect::<Vec<_>>()[..]].concat())
+                .env("LC_ALL", "C")
+                .arg(&self.path)
+        );
 
-        String::from_utf8_lossy(&res.stdout).trim().to_string()
+        outcome.stdout_lossy().trim().to_string()
     }
 }
 
@@ -175,6 +262,7 @@ mod tests {
     extern crate env_logger;
     extern crate getopts;
     extern crate glob;
+    use dataset;
 
     use self::getopts::Options;
     /// env_logger output is controlled by RUST_LOG environmental variable
@@ -198,14 +286,76 @@ mod tests {
 
     #[test]
     fn test_count_lines() {
-        let save_path = Path::new("./data/ch02/hightemp.txt");
+        let save_path = &dataset::resolve("hightemp");
         let commander = Commander::new(save_path);
         assert_eq!(commander.count_lines().unwrap(), 24);
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_path() {
+        use std::ffi::OsStr;
+        use std::fs;
+        use std::os::unix::ffi::OsStrExt;
+        // `高温.txt` in Shift_JIS
+        let path = Path::new("./data/ch02").join(OsStr::from_bytes(b"\x8d\x82\x89\xb7.txt"));
+        fs::copy(dataset::resolve("hightemp"), &path).unwrap();
+
+        let commander = Commander::new(&path);
+        assert_eq!(commander.count_lines().unwrap(), 24);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_with_replay() {
+        use super::super::runner::Replay;
+        use std::panic::{self, AssertUnwindSafe};
+        let path = "./data/ch02/hightemp.txt";
+        let outcome = |code: i32, stdout: &str| Outcome {code: Some(code), stdout: stdout.into(), stderr: b"error".to_vec()};
+
+        // BSD wc pads the count
+        let replay = Replay::new()
+            .with(&Invocation::new("wc").args(["-l", path]), outcome(0, "      24 ./data/ch02/hightemp.txt\n"))
+            .with(&Invocation::new("split").args(["-l", "8", path, "./data/ch02/split_"]), outcome(1, ""))
+            .with(&Invocation::new("cut").args(["-f", "1", path]), outcome(0, "b\na\nb\n"))
+            .with(&Invocation::new("sort").env("LC_ALL", "C").stdin("b\na\nb\n"), outcome(0, "a\nb\nb\n"))
+            .with(&Invocation::new("uniq").env("LC_ALL", "C").arg("-c").stdin("a\nb\nb\n"), outcome(0, "   1 a\n   2 b\n"))
+            .with(
+                &Invocation::new("sort").env("LC_ALL", "C").args(["-k1,1nr", "-k2,2"]).stdin("   1 a\n   2 b\n"),
+                outcome(0, "   2 b\n   1 a\n")
+            )
+            .with(&Invocation::new("paste").args([path, "./data/ch02/col2.txt"]), outcome(0, "b\tx\n"))
+            .with_pipeline(
+                &[
+                    Invocation::new("cut").args(["-f", "1", path]),
+                    Invocation::new("sort").env("LC_ALL", "C"),
+                    Invocation::new("uniq").env("LC_ALL", "C"),
+                ],
+                outcome(0, "a\nb\n")
+            );
+        let commander = Commander::with_runner(path, Arc::new(replay)).flavor(Flavor::Bsd);
+
+        assert_eq!(commander.count_lines().unwrap(), 24);
+        let failed = panic::catch_unwind(AssertUnwindSafe(|| commander.split(3, &"./data/ch02/split_")));
+        let message = failed.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.ends_with("failed (code: Some(1)): error"), "{}", message);
+        // counts are padded in the flavor
+        assert_eq!(commander.frequency(0), "   2 b\n   1 a");
+        assert_eq!(commander.paste(&["./data/ch02/col2.txt"], &[]), "b\tx");
+        assert_eq!(commander.uniq_first_row(), "a\nb");
+    }
+
+    #[test]
+    #[should_panic(expected = "not supported in Bsd")]
+    fn test_bsd_unsupported() {
+        let commander = Commander::with_runner("./data/ch02/hightemp.txt", Arc::new(super::super::runner::Replay::new()))
+            .flavor(Flavor::Bsd);
+        commander.join(&"./data/ch02/pref_block.txt", &["-a1", "-o", "auto"]);
+    }
+
     #[test]
     fn test_replace_tab_to_space() {
-        let save_path = Path::new("./data/ch02/hightemp.txt");
+        let save_path = &dataset::resolve("hightemp");
         let commander = Commander::new(save_path);
         let res = commander.replace_tab_to_space();
 
@@ -217,7 +367,7 @@ mod tests {
 
     #[test]
     fn test_extract_row() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
         let commander = Commander::new(load_path);
         assert_eq!(
             commander.extract_row(0).lines().next().unwrap(), // take first line
@@ -227,7 +377,7 @@ mod tests {
 
     #[test]
     fn test_merge() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
 
         let parent = load_path.parent().unwrap();
 
@@ -237,7 +387,7 @@ mod tests {
         let res = Commander::merge(&file1, &file2);
         debug!("{:?}", res);
         assert_eq!(
-            (&mut res.lines()).next().unwrap(),
+            res.lines().next().unwrap(),
             "高知県\t江川崎"
         )
     }
@@ -252,9 +402,10 @@ mod tests {
     #[test]
     fn test_head() {
         // let args = env::args()::collect::<Vec<String>>();
-        let args = vec!["program", "-n", "5", "./data/ch02/hightemp.txt"];
+        let path = dataset::resolve("hightemp");
+        let args = ["program", "-n", "5", path.to_str().unwrap()];
 
-        let program = args[0].clone();
+        let program = args[0];
 
         let mut opts = Options::new();
         opts.optopt("n", "num", "set first ${num} rows", "NUMBER");
@@ -263,7 +414,7 @@ mod tests {
         let matches = opts.parse(&args[1..]).unwrap();
 
         if matches.opt_present("h") {
-            print_usage(&program, opts);
+            print_usage(program, opts);
             return;
         }
 
@@ -288,9 +439,10 @@ mod tests {
     #[test]
     fn test_tail() {
         // let args = env::args()::collect::<Vec<String>>();
-        let args = vec!["program", "-n", "5", "./data/ch02/hightemp.txt"];
+        let path = dataset::resolve("hightemp");
+        let args = ["program", "-n", "5", path.to_str().unwrap()];
 
-        let program = args[0].clone();
+        let program = args[0];
 
         let mut opts = Options::new();
         opts.optopt("n", "num", "set first ${num} rows", "NUMBER");
@@ -299,7 +451,7 @@ mod tests {
         let matches = opts.parse(&args[1..]).unwrap();
 
         if matches.opt_present("h") {
-            print_usage(&program, opts);
+            print_usage(program, opts);
             return;
         }
 
@@ -326,9 +478,10 @@ mod tests {
 
     #[test]
     fn test_split() {
-        let args = vec!["program", "--line", "3", "./data/ch02/hightemp.txt", "./data/ch02/split_"];
+        let path = dataset::resolve("hightemp");
+        let args = ["program", "--line", "3", path.to_str().unwrap(), "./data/ch02/split_"];
 
-        let program = args[0].clone();
+        let program = args[0];
 
 
         let mut opts = Options::new();
@@ -338,7 +491,7 @@ mod tests {
         let matches = opts.parse(&args[1..]).unwrap();
 
         if matches.opt_present("h") {
-            print_usage(&program, opts);
+            print_usage(program, opts);
             return;
         }
 
@@ -371,12 +524,10 @@ mod tests {
 
     #[test]
     fn test_uniq_first_row() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
 
         let commander = Commander::new(load_path);
 
-        let res = commander.uniq_first_row();
-
         assert_eq!(
             commander.uniq_first_row(),
             "千葉県\n和歌山県\n埼玉県\n大阪府\n山形県\n山梨県\n岐阜県\n愛媛県\n愛知県\n群馬県\n静岡県\n高知県"
@@ -385,7 +536,7 @@ mod tests {
 
     #[test]
     fn test_sort_in_descending() {
-        let load_path = Path::new("./data/ch02/hightemp.txt");
+        let load_path = &dataset::resolve("hightemp");
 
         let commander = Commander::new(load_path);
 
@@ -401,4 +552,20 @@ mod tests {
         )
 
     }
+
+    #[test]
+    fn test_sort_by_spec() {
+        let load_path = &dataset::resolve("hightemp");
+
+        let commander = Commander::new(load_path);
+
+        let res = commander.sort_by_spec("-s -k3,3nr");
+
+        assert_eq!(
+            res.lines().take(3).collect::<Vec<&str>>(),
+            vec!["高知県\t江川崎\t41\t2013-08-12",
+                 "埼玉県\t熊谷\t40.9\t2007-08-16",
+                 "岐阜県\t多治見\t40.9\t2007-08-16"]
+        )
+    }
 }
\ No newline at end of file
@@ -0,0 +1,193 @@
+//! `cut -f` with field lists, complement, delimiters and column names.
+use std::str::FromStr;
+
+/// selected fields
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fields {
+    /// ranges like `1,3-5,7-`, which begin with 1. None means the last field.
+    List(Vec<(usize, Option<usize>)>),
+    /// column names in the header, which is the first line.
+    Names(Vec<String>),
+}
+
+impl Fields {
+    /// a single field beginning with 1
+    pub fn single(n: usize)->Fields {
+        Fields::List(vec![(n, Some(n))])
+    }
+
+    pub fn names<S: AsRef<str>>(names: &[S])->Fields {
+        Fields::Names(names.iter().map(|s| s.as_ref().to_string()).collect())
+    }
+
+    /// convert names into field numbers with the header.
+    fn resolve(&self, header: &str, delimiter: char)->Result<Vec<(usize, Option<usize>)>, String> {
+        match *self {
+            Fields::List(ref ranges) => Ok(ranges.clone()),
+            Fields::Names(ref names) => {
+                let columns = header.split(delimiter).collect::<Vec<_>>();
+                names.iter()
+                    .map(|name|
+                        columns.iter()
+                            .position(|col| col == name)
+                            .map(|idx| (idx + 1, Some(idx + 1)))
+                            .ok_or(format!("no such column: {}", name))
+                    )
+                    .collect()
+            },
+        }
+    }
+}
+
+impl FromStr for Fields {
+    type Err = String;
+
+    /// parse the list of `cut -f`, e.g. `1,3-5,7-` or `-3`
+    fn from_str(s: &str)->Result<Fields, String> {
+        let parse = |n: &str| match n.parse::<usize>() {
+            Ok(0) | Err(_) => Err(format!("invalid field: {}", s)),
+            Ok(n) => Ok(n),
+        };
+        if s.is_empty() {
+            return Err("fields are numbered from 1".into());
+        }
+        s.split(',')
+            .map(|range| {
+                if range == "-" {
+                    return Err(format!("invalid range with no endpoint: {}", range));
+                }
+                let mut it = range.splitn(2, '-');
+                let start = it.next().unwrap();
+                let start = if start.is_empty() {1} else {parse(start)?};
+                let end = match it.next() {
+                    None => Some(start),
+                    Some("") => None,
+                    Some(end) => Some(parse(end)?),
+                };
+                if end.is_some_and(|e| e < start) {
+                    return Err(format!("invalid decreasing range: {}", range));
+                }
+                Ok((start, end))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Fields::List)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cutter {
+    fields: Fields,
+    complement: bool,
+    delimiter: char,
+    output_delimiter: Option<String>,
+    only_delimited: bool,
+}
+
+impl Cutter {
+    pub fn new(fields: Fields)->Cutter {
+        Cutter {
+            fields,
+            complement: false,
+            delimiter: '\t',
+            output_delimiter: None,
+            only_delimited: false,
+        }
+    }
+
+    /// `--complement`; select fields not in the list
+    pub fn complement(mut self, yes: bool)->Cutter {
+        self.complement = yes;
+        self
+    }
+
+    /// `-d`; tab by default
+    pub fn delimiter(mut self, delimiter: char)->Cutter {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// `--output-delimiter`; same as the input delimiter by default
+    pub fn output_delimiter(mut self, delimiter: &str)->Cutter {
+        self.output_delimiter = Some(delimiter.into());
+        self
+    }
+
+    /// `-s`; drop lines without delimiter. otherwise they are printed as they are.
+    pub fn only_delimited(mut self, yes: bool)->Cutter {
+        self.only_delimited = yes;
+        self
+    }
+
+    fn cut_line(&self, line: &str, ranges: &[(usize, Option<usize>)])->Option<String> {
+        if !line.contains(self.delimiter) {
+            return if self.only_delimited {None} else {Some(line.to_string())};
+        }
+        let selected = |n: usize| ranges.iter()
+            .any(|&(start, end)| start <= n && end.is_none_or(|e| n <= e));
+        let output_delimiter = self.output_delimiter
+            .clone()
+            .unwrap_or_else(|| self.delimiter.to_string());
+        // like cut, fields are printed in the order of the line, not of the list.
+        Some(
+            line.split(self.delimiter)
+                .enumerate()
+                .filter(|&(idx, _)| selected(idx + 1) != self.complement)
+                .map(|(_, s)| s)
+                .collect::<Vec<_>>()
+                .join(&output_delimiter)
+        )
+    }
+
+    /// cut each line. names of fields are resolved with the first line.
+    pub fn cut<S: AsRef<str>>(&self, lines: &[S])->Result<Vec<String>, String> {
+        let header = lines.first().map_or("", |s| s.as_ref());
+        let ranges = self.fields.resolve(header, self.delimiter)?;
+        Ok(
+            lines.iter()
+                .filter_map(|line| self.cut_line(line.as_ref(), &ranges))
+                .collect()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fields() {
+        assert_eq!(
+            "1,3-5,7-".parse::<Fields>().unwrap(),
+            Fields::List(vec![(1, Some(1)), (3, Some(5)), (7, None)])
+        );
+        assert_eq!("-2".parse::<Fields>().unwrap(), Fields::List(vec![(1, Some(2))]));
+        assert!("0".parse::<Fields>().is_err());
+        assert!("3-1".parse::<Fields>().is_err());
+        assert!("-".parse::<Fields>().is_err());
+        assert!("".parse::<Fields>().is_err());
+        assert!("a".parse::<Fields>().is_err());
+    }
+
+    #[test]
+    fn test_cut() {
+        let lines = ["a\tb\tc\td", "no delimiter", "e\tf"];
+        let cutter = Cutter::new("1,3-".parse().unwrap());
+        assert_eq!(cutter.cut(&lines).unwrap(), vec!["a\tc\td", "no delimiter", "e"]);
+
+        let cutter = cutter.only_delimited(true).output_delimiter(",");
+        assert_eq!(cutter.cut(&lines).unwrap(), vec!["a,c,d", "e"]);
+
+        let cutter = Cutter::new(Fields::single(2)).complement(true);
+        assert_eq!(cutter.cut(&lines).unwrap(), vec!["a\tc\td", "no delimiter", "e"]);
+    }
+
+    #[test]
+    fn test_cut_by_names() {
+        let lines = ["pref,region,temp", "高知県,江川崎,41"];
+        let cutter = Cutter::new(Fields::names(&["temp", "pref"])).delimiter(',');
+        assert_eq!(cutter.cut(&lines).unwrap(), vec!["pref,temp", "高知県,41"]);
+
+        let cutter = Cutter::new(Fields::names(&["date"])).delimiter(',');
+        assert!(cutter.cut(&lines).is_err());
+    }
+}
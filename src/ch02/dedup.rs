@@ -0,0 +1,269 @@
+//! order-preserving dedup on key columns, unlike `sort -u`.
+//! keys are remembered in a hash map, or in a Bloom filter for inputs whose keys do not fit in memory.
+//! kept rows are streamed, so memory in Bloom mode is bounded by the filter.
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Result as ioResult};
+
+use super::frequency::{self, Frequency};
+
+/// which of the rows having the same key is kept
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Keep {
+    #[default]
+    First,
+    Last,
+}
+
+/// a rate out of (0, 1) makes the size of the filter negative or infinite, which saturates to usize::MAX.
+fn check_rate(fp_rate: f64)->ioResult<()> {
+    if fp_rate > 0.0 && fp_rate < 1.0 {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("false positive rate {} is not in (0, 1)", fp_rate)))
+    }
+}
+
+/// Bloom filter of strings
+pub struct Bloom {
+    bits: Vec<u64>,
+    /// number of bits
+    size: usize,
+    hashes: usize,
+}
+
+impl Bloom {
+    /// filter sized for the number of items and the false positive rate, which must be in (0, 1).
+    pub fn new(expected: usize, fp_rate: f64)->ioResult<Bloom> {
+        check_rate(fp_rate)?;
+        let ln2 = ::std::f64::consts::LN_2;
+        let size = ((-(expected.max(1) as f64) * fp_rate.ln() / (ln2 * ln2)).ceil() as usize).max(64);
+        let hashes = ((size as f64 / expected.max(1) as f64 * ln2).round() as usize).max(1);
+        Ok(Bloom {bits: vec![0; size.div_ceil(64)], size, hashes})
+    }
+
+    /// bit positions by double hashing
+    fn positions(&self, key: &str)->Vec<usize> {
+        let hash = |seed: u64| {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            key.hash(&mut hasher);
+            hasher.finish()
+        };
+        let (h1, h2) = (hash(0), hash(1) | 1);
+        (0..self.hashes as u64)
+            .map(|i| (h1.wrapping_add(i.wrapping_mul(h2)) % self.size as u64) as usize)
+            .collect()
+    }
+
+    /// add the key, and return whether it may have been added before
+    pub fn insert(&mut self, key: &str)->bool {
+        let mut seen = true;
+        for pos in self.positions(key) {
+            let (word, bit) = (pos / 64, 1 << (pos % 64));
+            if self.bits[word] & bit == 0 {
+                seen = false;
+                self.bits[word] |= bit;
+            }
+        }
+        seen
+    }
+}
+
+/// rows dropped as duplicates
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Dropped {
+    pub total: usize,
+    /// count per key in descending order, and the ratio is in dropped rows.
+    /// empty in Bloom mode, where keys are not remembered.
+    pub keys: Vec<Frequency>,
+}
+
+/// rows without duplicates and the dropped rows
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deduped<T> {
+    pub rows: Vec<T>,
+    pub dropped: Dropped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Dedup {
+    keep: Keep,
+    /// expected number of keys and the false positive rate
+    bloom: Option<(usize, f64)>,
+}
+
+impl Dedup {
+    /// keep the first rows, with a hash set
+    pub fn new()->Dedup {
+        Dedup::default()
+    }
+
+    pub fn keep(mut self, keep: Keep)->Dedup {
+        self.keep = keep;
+        self
+    }
+
+    /// remember keys in a Bloom filter instead of a hash map. memory is bounded,
+    /// but a unique row is dropped by the false positive rate.
+    /// the error kind is InvalidInput if the rate is not in (0, 1), or with Keep::Last,
+    /// which holds the last row of each key and can't bound memory.
+    pub fn bloom(mut self, expected: usize, fp_rate: f64)->ioResult<Dedup> {
+        check_rate(fp_rate)?;
+        self.check_keep(self.keep)?;
+        self.bloom = Some((expected, fp_rate));
+        Ok(self)
+    }
+
+    fn check_keep(&self, keep: Keep)->ioResult<()> {
+        match keep {
+            Keep::Last => Err(io::Error::new(io::ErrorKind::InvalidInput, "Keep::Last can't use a Bloom filter")),
+            Keep::First => Ok(()),
+        }
+    }
+
+    /// dedup rows by the key, and pass the kept rows to `emit` in the original order.
+    /// with Keep::First, a row is emitted as soon as it is read.
+    /// the error kind is InvalidInput if Keep::Last is set after the Bloom filter.
+    pub fn stream<T, I, F, E>(&self, rows: I, key: F, mut emit: E)->ioResult<Dropped>
+        where I: IntoIterator<Item=T>, F: Fn(&T)->String, E: FnMut(T)->ioResult<()>
+    {
+        // count of duplicates per key
+        let mut seen = HashMap::new();
+        match (self.keep, self.bloom) {
+            (Keep::First, Some((expected, fp_rate))) => {
+                let mut bloom = Bloom::new(expected, fp_rate)?;
+                let mut total = 0;
+                for row in rows {
+                    if bloom.insert(&key(&row)) {
+                        total += 1;
+                    } else {
+                        emit(row)?;
+                    }
+                }
+                return Ok(Dropped {total, keys: Vec::new()});
+            },
+            (Keep::First, None) => {
+                for row in rows {
+                    let k = key(&row);
+                    if let Some(count) = seen.get_mut(&k) {
+                        *count += 1;
+                    } else {
+                        seen.insert(k, 0);
+                        emit(row)?;
+                    }
+                }
+            },
+            (Keep::Last, Some(_)) => self.check_keep(Keep::Last)?,
+            (Keep::Last, None) => {
+                // the last row of each key and its position
+                let mut last = HashMap::new();
+                for (idx, row) in rows.into_iter().enumerate() {
+                    let k = key(&row);
+                    if last.insert(k.clone(), (idx, row)).is_some() {
+                        *seen.entry(k).or_insert(0) += 1;
+                    }
+                }
+                let mut kept = last.into_values().collect::<Vec<_>>();
+                kept.sort_by_key(|&(idx, _)| idx);
+                for (_, row) in kept {
+                    emit(row)?;
+                }
+            },
+        }
+        let keys = frequency::from_counts(seen.into_iter().filter(|&(_, count)| count > 0));
+        Ok(Dropped {total: keys.iter().map(|f| f.count).sum(), keys})
+    }
+
+    /// dedup rows by the key. the kept rows stay in the original order.
+    /// the error is the same as `stream`.
+    pub fn run<T, I, F>(&self, rows: I, key: F)->ioResult<Deduped<T>>
+        where I: IntoIterator<Item=T>, F: Fn(&T)->String
+    {
+        let mut kept = Vec::new();
+        let dropped = self.stream(rows, key, |row| {
+            kept.push(row);
+            Ok(())
+        })?;
+        Ok(Deduped {rows: kept, dropped})
+    }
+
+    /// dedup tab-separated lines by columns beginning with 0
+    pub fn lines<S: AsRef<str>>(&self, lines: Vec<S>, cols: &[usize])->ioResult<Deduped<S>> {
+        self.run(lines, |line| frequency::key_of(line.as_ref(), cols))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    const LINES: [&str; 6] = ["a\t1\tx", "b\t2\tx", "a\t1\ty", "c\t1\tx", "a\t2\tz", "b\t2\tw"];
+
+    #[test]
+    fn test_keep() {
+        let res = Dedup::new().lines(LINES.to_vec(), &[0]).unwrap();
+        assert_eq!(res.rows, vec!["a\t1\tx", "b\t2\tx", "c\t1\tx"]);
+        assert_eq!(
+            res.dropped.keys.iter().map(|f| (f.key.as_str(), f.count)).collect::<Vec<_>>(),
+            vec![("a", 2), ("b", 1)]
+        );
+        assert_eq!(res.dropped.total, 3);
+
+        let res = Dedup::new().keep(Keep::Last).lines(LINES.to_vec(), &[0]).unwrap();
+        assert_eq!(res.rows, vec!["c\t1\tx", "a\t2\tz", "b\t2\tw"]);
+        assert_eq!(res.dropped, Dedup::new().lines(LINES.to_vec(), &[0]).unwrap().dropped);
+
+        // multiple columns
+        let res = Dedup::new().lines(LINES.to_vec(), &[0, 1]).unwrap();
+        assert_eq!(res.rows, vec!["a\t1\tx", "b\t2\tx", "c\t1\tx", "a\t2\tz"]);
+        assert_eq!(res.dropped.keys.len(), 2);
+    }
+
+    #[test]
+    fn test_bloom() {
+        let mut bloom = Bloom::new(1000, 0.01).unwrap();
+        assert!(!bloom.insert("a"));
+        assert!(bloom.insert("a"));
+
+        // false positives are rare
+        let lines = (0..1000).chain(0..1000).map(|i| i.to_string()).collect::<Vec<_>>();
+        let res = Dedup::new().bloom(1000, 0.01).unwrap().lines(lines, &[0]).unwrap();
+        assert!(990 <= res.rows.len() && res.rows.len() <= 1000, "{}", res.rows.len());
+        assert_eq!(res.rows[..10], ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"]);
+        assert_eq!(res.dropped.total, 2000 - res.rows.len());
+        assert!(res.dropped.keys.is_empty());
+
+        for &fp_rate in &[0.0, 1.0, -0.5, 2.0, f64::NAN] {
+            assert_eq!(Bloom::new(1000, fp_rate).err().unwrap().kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(Dedup::new().bloom(1000, fp_rate).err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        }
+
+        // Keep::Last can't bound memory with the filter, in either order of the builder
+        assert!(Dedup::new().keep(Keep::Last).bloom(1000, 0.01).is_err());
+        let dedup = Dedup::new().bloom(1000, 0.01).unwrap().keep(Keep::Last);
+        assert_eq!(dedup.lines(LINES.to_vec(), &[0]).err().unwrap().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_stream() {
+        // rows are emitted before the input ends
+        let count = Cell::new(0);
+        let mut emitted = Vec::new();
+        let rows = LINES.iter().enumerate().inspect(|&(idx, _)| assert!(idx < 4 || count.get() == 3));
+        let dropped = Dedup::new().bloom(100, 0.01).unwrap().stream(rows, |&(_, line)| frequency::key_of(line, &[0]), |(_, line)| {
+            count.set(count.get() + 1);
+            emitted.push(*line);
+            Ok(())
+        }).unwrap();
+        assert_eq!(emitted, vec!["a\t1\tx", "b\t2\tx", "c\t1\tx"]);
+        assert_eq!(dropped.total, 3);
+
+        let res = Dedup::new().stream(LINES.iter(), |line| line.to_string(), |_| {
+            Err(io::Error::other("closed"))
+        });
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,255 @@
+//! `expand` and `unexpand` with tab stops. columns are counted by East Asian Width,
+//! so full-width characters like `高知県` take two columns each.
+extern crate unicode_width;
+
+use std::str::FromStr;
+use self::unicode_width::UnicodeWidthChar;
+
+/// tab stops like `expand -t`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TabStops {
+    /// every n columns
+    Every(usize),
+    /// columns of the stops, and the interval after the last one if any
+    List(Vec<usize>, Option<usize>),
+}
+
+impl TabStops {
+    /// the first stop after the column. None beyond the list.
+    pub fn next(&self, col: usize)->Option<usize> {
+        match *self {
+            TabStops::Every(n) => Some((col / n + 1) * n),
+            TabStops::List(ref stops, repeat) => stops.iter()
+                .cloned()
+                .find(|&stop| stop > col)
+                .or_else(|| repeat.map(|n| (col / n + 1) * n)),
+        }
+    }
+}
+
+impl Default for TabStops {
+    fn default()->TabStops {
+        TabStops::Every(8)
+    }
+}
+
+/// `4`, `2,5` or `2,5,/4` as the argument of `expand -t`
+impl FromStr for TabStops {
+    type Err = String;
+
+    fn from_str(s: &str)->Result<TabStops, String> {
+        let parse = |s: &str| match s.parse::<usize>() {
+            Ok(0) | Err(_) => Err(format!("invalid tab stop: {}", s)),
+            Ok(n) => Ok(n),
+        };
+        let items = s.split([',', ' ']).filter(|s| !s.is_empty()).collect::<Vec<_>>();
+        let (last, init) = match items.split_last() {
+            Some(x) => x,
+            None => return Err("empty tab stops".into()),
+        };
+        let repeat = match last.strip_prefix('/') {
+            Some(n) => Some(parse(n)?),
+            None => None,
+        };
+        let mut stops = init.iter().map(|s| parse(s)).collect::<Result<Vec<_>, _>>()?;
+        if repeat.is_none() {
+            stops.push(parse(last)?);
+        }
+        if stops.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(format!("tab stops must be ascending: {}", s));
+        }
+        match (&stops[..], repeat) {
+            (&[n], None) => Ok(TabStops::Every(n)),
+            _ => Ok(TabStops::List(stops, repeat)),
+        }
+    }
+}
+
+fn is_blank(c: char)->bool {
+    c == ' ' || c == '\t'
+}
+
+/// display width of a character. control characters take no column.
+pub fn char_width(c: char)->usize {
+    c.width().unwrap_or(0)
+}
+
+/// display width of a string
+pub fn width(s: &str)->usize {
+    s.chars().map(char_width).sum()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Tabs {
+    stops: TabStops,
+    initial: bool,
+}
+
+impl Tabs {
+    /// tab stops every 8 columns
+    pub fn new()->Tabs {
+        Tabs::default()
+    }
+
+    pub fn stops(mut self, stops: TabStops)->Tabs {
+        self.stops = stops;
+        self
+    }
+
+    /// convert only blanks before the first non-blank character, like `expand -i` and `unexpand` without `-a`
+    pub fn initial(mut self, yes: bool)->Tabs {
+        self.initial = yes;
+        self
+    }
+
+    /// column after the blank at the column. a tab beyond the stops takes a column.
+    fn advance(&self, c: char, col: usize)->usize {
+        match c {
+            '\t' => self.stops.next(col).unwrap_or(col + 1),
+            _ => col + 1,
+        }
+    }
+
+    /// replace tabs with spaces like `expand`
+    pub fn expand(&self, line: &str)->String {
+        let mut out = String::with_capacity(line.len());
+        let mut col = 0;
+        for (idx, c) in line.char_indices() {
+            if self.initial && !is_blank(c) {
+                out.push_str(&line[idx..]);
+                break;
+            }
+            if c == '\t' {
+                let next = self.advance(c, col);
+                out.extend(::std::iter::repeat_n(' ', next - col));
+                col = next;
+            } else {
+                out.push(c);
+                col += char_width(c);
+            }
+        }
+        out
+    }
+
+    /// replace blanks reaching tab stops with tabs like `unexpand -a`, or `unexpand` in the initial mode.
+    pub fn unexpand(&self, line: &str)->String {
+        let mut out = String::with_capacity(line.len());
+        // pending blanks with their columns after them
+        let mut blanks: Vec<(char, usize)> = Vec::new();
+        let mut start = 0;
+        let mut col = 0;
+        for (idx, c) in line.char_indices() {
+            if is_blank(c) {
+                if blanks.is_empty() {
+                    start = col;
+                }
+                col = self.advance(c, col);
+                blanks.push((c, col));
+                continue;
+            }
+            self.flush(&mut out, start, &blanks);
+            blanks.clear();
+            if self.initial {
+                out.push_str(&line[idx..]);
+                return out;
+            }
+            out.push(c);
+            col += char_width(c);
+        }
+        self.flush(&mut out, start, &blanks);
+        out
+    }
+
+    /// write a run of blanks from the column. each segment of the run up to a tab stop becomes a tab,
+    /// except a single space just before the stop at the end of the run, as GNU unexpand does.
+    fn flush(&self, out: &mut String, start: usize, blanks: &[(char, usize)]) {
+        let mut from = start;
+        let mut rest = blanks;
+        while let Some(stop) = self.stops.next(from) {
+            let n = match rest.iter().position(|&(_, col)| col >= stop) {
+                Some(pos) if rest[pos].1 == stop => pos + 1,
+                _ => break,
+            };
+            if n == 1 && rest[0].0 == ' ' && rest.len() == 1 {
+                out.push(' ');
+            } else {
+                out.push('\t');
+            }
+            from = stop;
+            rest = &rest[n..];
+        }
+        out.extend(rest.iter().map(|&(c, _)| c));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    fn gnu(program: &str, args: &[&str], input: &str)->String {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(format!("{}\n", input).as_bytes()).unwrap();
+        let out = String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap();
+        out.trim_end_matches('\n').to_string()
+    }
+
+    const LINES: [&str; 9] = [
+        "a\tb\tc\td", "ab      c", "abcdefg h", "abcdefg  h", "        x", " \tx", "a       \tb", "   a   b", "\t a \t",
+    ];
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("4".parse::<TabStops>().unwrap(), TabStops::Every(4));
+        assert_eq!("2,5".parse::<TabStops>().unwrap(), TabStops::List(vec![2, 5], None));
+        assert_eq!("2 5,/4".parse::<TabStops>().unwrap(), TabStops::List(vec![2, 5], Some(4)));
+        assert!("5,2".parse::<TabStops>().is_err());
+        assert!("0".parse::<TabStops>().is_err());
+        assert!("".parse::<TabStops>().is_err());
+    }
+
+    #[test]
+    fn test_expand() {
+        for stops in &["8", "4", "2,5", "2,5,/4"] {
+            let tabs = Tabs::new().stops(stops.parse().unwrap());
+            for line in LINES.iter() {
+                assert_eq!(tabs.expand(line), gnu("expand", &["-t", stops], line), "{:?} by {}", line, stops);
+                assert_eq!(
+                    tabs.clone().initial(true).expand(line),
+                    gnu("expand", &["-i", "-t", stops], line),
+                    "{:?} by {}", line, stops
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_unexpand() {
+        for stops in &["8", "2", "2,4", "2,5,/4"] {
+            let tabs = Tabs::new().stops(stops.parse().unwrap());
+            for line in LINES.iter() {
+                // `-t` implies `-a`
+                assert_eq!(tabs.unexpand(line), gnu("unexpand", &["-t", stops], line), "{:?} by {}", line, stops);
+            }
+        }
+        let tabs = Tabs::new().initial(true);
+        for line in LINES.iter() {
+            assert_eq!(tabs.unexpand(line), gnu("unexpand", &[], line), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_east_asian_width() {
+        assert_eq!(width("高知県ｱ"), 7);
+        let tabs = Tabs::new().stops(TabStops::Every(4));
+        assert_eq!(tabs.expand("高知県\t江川崎\t41"), "高知県  江川崎  41");
+        assert_eq!(tabs.expand("ab\t41"), "ab  41");
+        assert_eq!(tabs.unexpand("高知県  江川崎  41"), "高知県\t江川崎\t41");
+    }
+}
@@ -0,0 +1,286 @@
+//! external merge sort for files larger than memory. lines are sorted into runs under the memory cap,
+//! and the runs saved in temporary files are merged. too many runs are merged in passes
+//! into longer runs, not to open more files than the fan-in at once.
+//! lines are kept in bytes and written back unchanged, so any encoding compatible with ASCII
+//! (e.g. CP932) and `\r` of CRLF pass through.
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Split, Write};
+use std::io::Result as ioResult;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+use super::rw;
+use super::sort::SortSpec;
+
+static RUN_ID: AtomicUsize = AtomicUsize::new(0);
+
+type Line = Vec<u8>;
+
+/// sorted lines in a temporary file, which is removed when dropped
+struct Run {
+    path: PathBuf,
+}
+
+impl Run {
+    /// an empty run and its writer
+    fn create(dir: &Path)->ioResult<(Run, BufWriter<File>)> {
+        let n = RUN_ID.fetch_add(1, AtomicOrdering::SeqCst);
+        let run = Run {path: dir.join(format!(".extsort.{}.{}.run", process::id(), n))};
+        let w = BufWriter::new(File::create(&run.path)?);
+        Ok((run, w))
+    }
+
+    fn save(dir: &Path, lines: &[Line])->ioResult<Run> {
+        let (run, mut w) = Run::create(dir)?;
+        for line in lines {
+            write_line(&mut w, line)?;
+        }
+        w.flush()?;
+        Ok(run)
+    }
+
+    fn lines(&self)->ioResult<Split<BufReader<File>>> {
+        Ok(BufReader::new(File::open(&self.path)?).split(b'\n'))
+    }
+}
+
+fn write_line<W: Write>(writer: &mut W, line: &[u8])->ioResult<()> {
+    writer.write_all(line)?;
+    writer.write_all(b"\n")
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// the smallest line of a run. runs from earlier input win ties to keep sorting stable.
+struct Head<'a> {
+    line: Line,
+    run: usize,
+    spec: &'a SortSpec,
+}
+
+impl<'a> Ord for Head<'a> {
+    fn cmp(&self, other: &Head)->Ordering {
+        // BinaryHeap is a max-heap
+        self.spec.compare_bytes(&self.line, &other.line)
+            .then(self.run.cmp(&other.run))
+            .reverse()
+    }
+}
+
+impl<'a> PartialOrd for Head<'a> {
+    fn partial_cmp(&self, other: &Head)->Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> PartialEq for Head<'a> {
+    fn eq(&self, other: &Head)->bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a> Eq for Head<'a> {}
+
+pub struct ExternalSort {
+    spec: SortSpec,
+    memory: usize,
+    tmp_dir: PathBuf,
+    fan_in: usize,
+}
+
+impl ExternalSort {
+    /// 64MiB of memory, temporary files in the system temporary directory, and 64 runs merged at once
+    pub fn new(spec: SortSpec)->ExternalSort {
+        ExternalSort {spec, memory: 64 << 20, tmp_dir: env::temp_dir(), fan_in: 64}
+    }
+
+    /// approximate bytes of lines held in memory at once. a run has a line at least.
+    pub fn memory(mut self, bytes: usize)->ExternalSort {
+        self.memory = bytes;
+        self
+    }
+
+    /// runs merged at once like `sort --batch-size`. 2 at least.
+    pub fn fan_in(mut self, n: usize)->ExternalSort {
+        self.fan_in = n.max(2);
+        self
+    }
+
+    /// directory for the runs like `sort -T`
+    pub fn tmp_dir<P: AsRef<Path>>(mut self, dir: P)->ExternalSort {
+        self.tmp_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// sort lines of the reader into the writer. return the number of lines written.
+    pub fn sort<R: BufRead, W: Write>(&self, reader: R, mut writer: W)->ioResult<usize> {
+        let mut runs = Vec::new();
+        let mut lines = Vec::new();
+        let mut size = 0;
+        for line in reader.split(b'\n') {
+            let line = line?;
+            size += line.len() + mem::size_of::<Line>();
+            lines.push(line);
+            if size >= self.memory {
+                self.spec.sort(&mut lines);
+                runs.push(Run::save(&self.tmp_dir, &lines)?);
+                lines.clear();
+                size = 0;
+            }
+        }
+        self.spec.sort(&mut lines);
+        debug!("external sort: {} runs and {} lines in memory", runs.len(), lines.len());
+
+        if runs.is_empty() {
+            for line in &lines {
+                write_line(&mut writer, line)?;
+            }
+            writer.flush()?;
+            return Ok(lines.len());
+        }
+        if !lines.is_empty() {
+            runs.push(Run::save(&self.tmp_dir, &lines)?);
+        }
+        self.merge(runs, writer)
+    }
+
+    /// merge the runs in passes of the fan-in
+    fn merge<W: Write>(&self, mut runs: Vec<Run>, writer: W)->ioResult<usize> {
+        while runs.len() > self.fan_in {
+            let mut merged = Vec::with_capacity(runs.len().div_ceil(self.fan_in));
+            for group in runs.chunks(self.fan_in) {
+                let (run, mut w) = Run::create(&self.tmp_dir)?;
+                // runs keep their order, so `-u` applied at last still keeps the first of equal keys.
+                self.merge_pass(group, &mut w, false)?;
+                merged.push(run);
+            }
+            debug!("external sort: merged {} runs into {}", runs.len(), merged.len());
+            runs = merged;
+        }
+        self.merge_pass(&runs, writer, self.spec.unique)
+    }
+
+    /// k-way merge of the runs
+    fn merge_pass<W: Write>(&self, runs: &[Run], mut writer: W, unique: bool)->ioResult<usize> {
+        let mut readers = runs.iter()
+            .map(|run| run.lines())
+            .collect::<ioResult<Vec<_>>>()?;
+        let mut heap = BinaryHeap::new();
+        for (idx, reader) in readers.iter_mut().enumerate() {
+            if let Some(line) = reader.next() {
+                heap.push(Head {line: line?, run: idx, spec: &self.spec});
+            }
+        }
+
+        let mut count = 0;
+        let mut last: Option<Line> = None;
+        while let Some(Head {line, run, ..}) = heap.pop() {
+            if let Some(next) = readers[run].next() {
+                heap.push(Head {line: next?, run, spec: &self.spec});
+            }
+            // `-u`; the first of equal keys comes from the earliest run
+            let dup = unique && last.as_ref()
+                .is_some_and(|l| self.spec.compare_keys_bytes(l, &line) == Ordering::Equal);
+            if !dup {
+                write_line(&mut writer, &line)?;
+                count += 1;
+                last = Some(line);
+            }
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// sort the file, which may be compressed, and save the result atomically.
+    pub fn sort_file<P: AsRef<Path>, Q: AsRef<Path>>(&self, load_path: P, save_path: Q)->ioResult<usize> {
+        let mut w = rw::create(save_path)?;
+        let count = self.sort(rw::open(load_path)?, &mut w)?;
+        w.finish()?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use ch02::sample::{self, Rng};
+
+    fn gnu_sort(spec: &str, path: &str)->Vec<u8> {
+        let output = Command::new("sort")
+            .env("LC_ALL", "C")
+            .args(["-t", "\t"])
+            .args(spec.split_whitespace())
+            .arg(path)
+            .output()
+            .unwrap();
+        output.stdout
+    }
+
+    #[test]
+    fn test_sort() {
+        let dir = "./data/ch02/extsort";
+        fs::create_dir_all(dir).unwrap();
+        let mut rng = sample::seeded(1);
+        let lines = (0..500)
+            .map(|_| format!(
+                "{}\t{}\t{}",
+                ["高知県", "埼玉県", "山形県", "b", "B"][rng.gen_range(0, 5)],
+                rng.gen_range(-50, 50),
+                rng.gen_range(0, 20) as f64 / 4.0
+            ))
+            .collect::<Vec<_>>();
+        let text = lines.join("\n") + "\n";
+        // the same lines in CP932 and with CRLF are sorted in bytes, and written back unchanged
+        let inputs = vec![
+            ("input.txt", text.clone().into_bytes()),
+            ("input_cp932.txt", rw::encode(&text, rw::Charset::ShiftJis).unwrap()),
+            ("input_crlf.txt", text.replace('\n', "\r\n").into_bytes()),
+        ];
+
+        for (name, bytes) in &inputs {
+            let path = format!("{}/{}", dir, name);
+            rw::write(bytes, &path).unwrap();
+            for spec in &["-k1,1", "-s -k2,2n", "-k3,3nr -k1,1", "-u -k1,1", "-u -k2,2n -k3,3n", "-r", "-s -k3,3nr"] {
+                let out = format!("{}/output.txt", dir);
+                let expected = gnu_sort(spec, &path);
+                for memory in &[0, 100, 4096, 1 << 20] {
+                    // a few passes with 2 or 3 runs merged at once
+                    for fan_in in &[2, 3, 64] {
+                        let sorter = ExternalSort::new(spec.parse().unwrap()).memory(*memory).tmp_dir(dir).fan_in(*fan_in);
+                        let count = sorter.sort_file(&path, &out).unwrap();
+                        assert!(fs::read(&out).unwrap() == expected, "{} of {} in {} bytes by {}", spec, name, memory, fan_in);
+                        assert_eq!(count, expected.iter().filter(|&&b| b == b'\n').count());
+                    }
+                }
+            }
+        }
+
+        // no runs are left
+        let left = fs::read_dir(dir).unwrap()
+            .filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().ends_with(".run"))
+            .count();
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut out = Vec::new();
+        let sorter = ExternalSort::new("-k1,1".parse().unwrap()).memory(0);
+        assert_eq!(sorter.sort(&b""[..], &mut out).unwrap(), 0);
+        assert!(out.is_empty());
+
+        // the last line without newline
+        sorter.sort(&b"b\na"[..], &mut out).unwrap();
+        assert_eq!(out, b"a\nb\n");
+    }
+}
@@ -0,0 +1,285 @@
+//! output formats of rows; TSV, CSV, JSON, JSON Lines, Markdown table and aligned table.
+extern crate csv;
+extern crate serde;
+extern crate serde_json;
+
+use std::str::FromStr;
+use self::serde::Serialize;
+
+use super::expand::width;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Format {
+    #[default]
+    Tsv,
+    /// quoted only if necessary
+    Csv,
+    /// array of objects if the header exists, otherwise array of arrays
+    Json,
+    /// an object or array per line
+    JsonLines,
+    Markdown,
+    /// columns aligned with spaces like `column -t`
+    Table,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str)->Result<Format, String> {
+        match s.to_lowercase().as_str() {
+            "tsv" => Ok(Format::Tsv),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "jsonl" | "jsonlines" => Ok(Format::JsonLines),
+            "md" | "markdown" => Ok(Format::Markdown),
+            "table" => Ok(Format::Table),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+/// JSON of a row. keys keep the order of the header.
+fn json_row(header: Option<&[String]>, row: &[String])->String {
+    let quote = |s: &str| serde_json::to_string(s).unwrap();
+    match header {
+        Some(header) => format!(
+            "{{{}}}",
+            header.iter()
+                .zip(row.iter())
+                .map(|(k, v)| format!("{}:{}", quote(k), quote(v)))
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        None => serde_json::to_string(row).unwrap(),
+    }
+}
+
+/// `\t`, `\n` and `\r` in a field of TSV or table, which would break the row otherwise.
+/// `\` is escaped first, so a field having `\` and `t` is not read back as a tab.
+fn escape(field: &str)->String {
+    field.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// render rows with an optional header.
+/// separators in fields are escaped in TSV and table, and newlines are `<br>` in markdown.
+pub fn rows(header: Option<&[String]>, rows: &[Vec<String>], format: Format)->String {
+    let all = || header.into_iter().map(|h| h.to_vec()).chain(rows.iter().cloned());
+    match format {
+        Format::Tsv => all()
+            .map(|row| row.iter().map(|s| escape(s)).collect::<Vec<_>>().join("\t"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Format::Csv => {
+            let mut wtr = csv::WriterBuilder::new()
+                .quote_style(csv::QuoteStyle::Necessary)
+                .from_writer(vec![]);
+            for row in all() {
+                wtr.write_record(&row).unwrap();
+            }
+            let s = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+            s.trim_end_matches('\n').to_string()
+        },
+        Format::Json => format!(
+            "[{}]",
+            rows.iter()
+                .map(|row| json_row(header, row))
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        Format::JsonLines => rows.iter()
+            .map(|row| json_row(header, row))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Format::Markdown => {
+            let ncols = all().map(|row| row.len()).max().unwrap_or(0);
+            // markdown table requires a header, so column numbers are used without it.
+            let header = header.map(|h| h.to_vec())
+                .unwrap_or_else(|| (1..ncols + 1).map(|i| i.to_string()).collect());
+            let line = |row: &[String]| format!(
+                "| {} |",
+                (0..ncols)
+                    .map(|i| row.get(i).map_or(String::new(), |s| {
+                        s.replace("|", "\\|")
+                            .replace("\r\n", "<br>")
+                            .replace(['\n', '\r'], "<br>")
+                    }))
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            );
+            ::std::iter::once(line(&header))
+                .chain(::std::iter::once(line(&vec!["---".to_string(); ncols])))
+                .chain(rows.iter().map(|row| line(row)))
+                .collect::<Vec<_>>()
+                .join("\n")
+        },
+        Format::Table => {
+            let all = || all().map(|row| row.iter().map(|s| escape(s)).collect::<Vec<_>>());
+            let mut widths = Vec::new();
+            for row in all() {
+                for (idx, cell) in row.iter().enumerate() {
+                    if widths.len() <= idx {
+                        widths.push(0);
+                    }
+                    widths[idx] = widths[idx].max(width(cell));
+                }
+            }
+            // like `column -t`, two spaces between columns and no padding after the last column.
+            all()
+                .map(|row| {
+                    row.iter()
+                        .enumerate()
+                        .map(|(idx, cell)|
+                            if idx + 1 == row.len() {
+                                cell.clone()
+                            } else {
+                                format!("{}{}", cell, " ".repeat(widths[idx] - width(cell) + 2))
+                            }
+                        )
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        },
+    }
+}
+
+/// render tab-separated lines without header
+pub fn lines<S: AsRef<str>>(lines: &[S], format: Format)->String {
+    let rs = lines.iter()
+        .map(|line| line.as_ref().split('\t').map(|s| s.to_string()).collect())
+        .collect::<Vec<_>>();
+    rows(None, &rs, format)
+}
+
+/// flatten records into rows by their serde derives. field names become the header,
+/// and records without them like tuples have no header.
+pub fn flatten<T: Serialize>(records: &[T])->(Option<Vec<String>>, Vec<Vec<String>>) {
+    if records.is_empty() {
+        return (None, Vec::new());
+    }
+    // csv writer writes field names as the header only for structs and maps.
+    // fields are quoted if necessary to read back tabs and newlines in them.
+    let named = serde_json::to_value(&records[0]).is_ok_and(|v| v.is_object());
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .quote_style(csv::QuoteStyle::Necessary)
+        .from_writer(vec![]);
+    for record in records {
+        wtr.serialize(record).unwrap();
+    }
+    let buf = wtr.into_inner().unwrap();
+    let mut all = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(&buf[..])
+        .records()
+        .map(|r| r.unwrap().iter().map(|s| s.to_string()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let header = if named {Some(all.remove(0))} else {None};
+    (header, all)
+}
+
+/// render records by their serde derives. field names become the header.
+pub fn records<T: Serialize>(records: &[T], format: Format)->String {
+    match format {
+        Format::Json => serde_json::to_string(records).unwrap(),
+        Format::JsonLines => records.iter()
+            .map(|r| serde_json::to_string(r).unwrap())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => {
+            let (header, all) = flatten(records);
+            rows(header.as_ref().map(|h| &h[..]), &all, format)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample()->(Vec<String>, Vec<Vec<String>>) {
+        let header = vec!["pref".to_string(), "region".to_string()];
+        let rows = vec![
+            vec!["高知県".to_string(), "江川崎".to_string()],
+            vec!["a,b".to_string(), "c|\"d\"".to_string()],
+        ];
+        (header, rows)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("JSONL".parse::<Format>().unwrap(), Format::JsonLines);
+        assert_eq!("md".parse::<Format>().unwrap(), Format::Markdown);
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn test_rows() {
+        let (header, rs) = sample();
+        let header = Some(&header[..]);
+
+        assert_eq!(rows(header, &rs, Format::Tsv), "pref\tregion\n高知県\t江川崎\na,b\tc|\"d\"");
+        assert_eq!(rows(header, &rs, Format::Csv), "pref,region\n高知県,江川崎\n\"a,b\",\"c|\"\"d\"\"\"");
+        assert_eq!(
+            rows(header, &rs, Format::Json),
+            r#"[{"pref":"高知県","region":"江川崎"},{"pref":"a,b","region":"c|\"d\""}]"#
+        );
+        assert_eq!(rows(None, &rs[..1], Format::JsonLines), r#"["高知県","江川崎"]"#);
+        assert_eq!(
+            rows(header, &rs, Format::Markdown),
+            "| pref | region |\n| --- | --- |\n| 高知県 | 江川崎 |\n| a,b | c\\|\"d\" |"
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        let rs = vec![
+            vec!["a".to_string(), "bb".to_string(), "c".to_string()],
+            vec!["ddd".to_string(), "e".to_string(), "f".to_string()],
+        ];
+        assert_eq!(rows(None, &rs, Format::Table), "a    bb  c\nddd  e   f");
+
+        // full-width characters take two columns
+        let rs = vec![
+            vec!["高知県".to_string(), "江川崎".to_string()],
+            vec!["abc".to_string(), "d".to_string()],
+        ];
+        assert_eq!(rows(None, &rs, Format::Table), "高知県  江川崎\nabc     d");
+    }
+
+    #[derive(Serialize)]
+    struct Row {
+        name: String,
+        temp: f32,
+    }
+
+    #[test]
+    fn test_records() {
+        let rs = vec![Row {name: "江川崎".into(), temp: 41.5}, Row {name: "熊谷".into(), temp: 40.9}];
+        assert_eq!(records(&rs, Format::Json), r#"[{"name":"江川崎","temp":41.5},{"name":"熊谷","temp":40.9}]"#);
+        assert_eq!(records(&rs, Format::JsonLines).lines().count(), 2);
+        assert_eq!(records(&rs, Format::Tsv), "name\ttemp\n江川崎\t41.5\n熊谷\t40.9");
+        assert_eq!(records::<Row>(&[], Format::Csv), "");
+
+        // separators in fields
+        let rs = vec![Row {name: "a\tb,c\nd".into(), temp: 1.0}];
+        assert_eq!(records(&rs, Format::Csv), "name,temp\n\"a\tb,c\nd\",1.0");
+        assert_eq!(records(&rs, Format::Json), r#"[{"name":"a\tb,c\nd","temp":1.0}]"#);
+        assert_eq!(records(&rs, Format::Tsv), "name\ttemp\na\\tb,c\\nd\t1.0");
+        assert_eq!(records(&rs, Format::Markdown).lines().last(), Some("| a\tb,c<br>d | 1.0 |"));
+        assert_eq!(records(&rs, Format::Table).lines().count(), 2);
+        let rs = vec![Row {name: "a\\tb\\".into(), temp: 1.0}, Row {name: "a\tb".into(), temp: 1.0}];
+        assert_eq!(records(&rs, Format::Tsv), "name\ttemp\na\\\\tb\\\\\t1.0\na\\tb\t1.0");
+
+        // tuples have no header
+        let rs = vec![("高知県", 41.0), ("埼玉県", 40.9)];
+        assert_eq!(records(&rs, Format::Tsv), "高知県\t41.0\n埼玉県\t40.9");
+        assert_eq!(records(&rs[..1], Format::Markdown), "| 1 | 2 |\n| --- | --- |\n| 高知県 | 41.0 |");
+    }
+}
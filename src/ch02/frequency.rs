@@ -0,0 +1,101 @@
+//! frequency of items, equivalent to `cut -f ${cols} | sort | uniq -c | sort -rn`
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frequency {
+    /// selected columns joined with a tab
+    pub key: String,
+    pub count: usize,
+    /// relative frequency, count / total
+    pub ratio: f64,
+    /// sum of ratio up to this key in percent
+    pub cumulative_percent: f64,
+}
+
+/// same format as `uniq -c`
+impl fmt::Display for Frequency {
+    fn fmt(&self, f: &mut fmt::Formatter)->fmt::Result {
+        write!(f, "{:>7} {}", self.count, self.key)
+    }
+}
+
+/// extract key of selected columns beginning with 0. missing column is regarded as empty.
+pub fn key_of(line: &str, cols: &[usize])->String {
+    let fields = line.split('\t').collect::<Vec<_>>();
+    cols.iter()
+        .map(|&col| fields.get(col).cloned().unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+/// count keys in descending order of count. ties are ordered by key in byte order.
+pub fn count<I, S>(keys: I)->Vec<Frequency>
+    where I: IntoIterator<Item=S>, S: Into<String>
+{
+    let mut counter = BTreeMap::new();
+    for key in keys {
+        *counter.entry(key.into()).or_insert(0) += 1;
+    }
+    from_counts(counter)
+}
+
+/// frequencies of keys counted already, in the same order as `count`
+pub fn from_counts<I: IntoIterator<Item=(String, usize)>>(counts: I)->Vec<Frequency> {
+    let mut ordering = counts.into_iter()
+        .collect::<Vec<(String, usize)>>();
+    ordering.sort_by(|(k1, c1), (k2, c2)| c2.cmp(c1).then(k1.cmp(k2)));
+
+    let total = ordering.iter().map(|&(_, count)| count).sum::<usize>() as f64;
+    let mut cumulative = 0;
+    ordering.into_iter()
+        .map(|(key, count)| {
+            cumulative += count;
+            Frequency {
+                key,
+                count,
+                ratio: count as f64 / total,
+                cumulative_percent: 100.0 * cumulative as f64 / total,
+            }
+        })
+        .collect()
+}
+
+/// `uniq -c` compatible output
+pub fn to_uniq_c(freqs: &[Frequency])->String {
+    freqs.iter()
+        .map(|f| f.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_of() {
+        assert_eq!(key_of("a\tb\tc", &[0]), "a");
+        assert_eq!(key_of("a\tb\tc", &[2, 0]), "c\ta");
+        assert_eq!(key_of("a\tb", &[0, 3]), "a\t");
+    }
+
+    #[test]
+    fn test_count() {
+        let res = count(vec!["b", "a", "c", "a", "b", "d", "a"]);
+
+        assert_eq!(
+            res.iter().map(|f| (f.key.as_str(), f.count)).collect::<Vec<_>>(),
+            vec![("a", 3), ("b", 2), ("c", 1), ("d", 1)]
+        );
+        assert_eq!(res[0].ratio, 3.0 / 7.0);
+        assert_eq!(res[1].cumulative_percent, 100.0 * 5.0 / 7.0);
+        assert_eq!(res[3].cumulative_percent, 100.0);
+    }
+
+    #[test]
+    fn test_to_uniq_c() {
+        let res = count(vec!["b", "a", "a"]);
+        assert_eq!(to_uniq_c(&res), "      2 a\n      1 b");
+    }
+}
@@ -0,0 +1,204 @@
+//! `grep` scoped to columns of tab-separated lines. a line matches if all conditions hold,
+//! e.g. the third column is 40 or more and the fourth column is in 2000 or later.
+extern crate chrono;
+extern crate regex;
+
+use std::collections::BTreeSet;
+use self::chrono::NaiveDate;
+use self::regex::Regex;
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Regex(Regex),
+    /// substring
+    Fixed(String),
+    /// inclusive bounds of a number. a value which is not a number never matches.
+    Range(Option<f64>, Option<f64>),
+    /// inclusive bounds of a `%Y-%m-%d` date
+    Dates(Option<NaiveDate>, Option<NaiveDate>),
+}
+
+fn within<T: PartialOrd>(v: T, min: &Option<T>, max: &Option<T>)->bool {
+    min.as_ref().is_none_or(|m| *m <= v) && max.as_ref().is_none_or(|m| v <= *m)
+}
+
+impl Pattern {
+    fn is_match(&self, field: &str)->bool {
+        match *self {
+            Pattern::Regex(ref re) => re.is_match(field),
+            Pattern::Fixed(ref s) => field.contains(s.as_str()),
+            Pattern::Range(ref min, ref max) => field.trim()
+                .parse::<f64>()
+                .map(|v| within(v, min, max))
+                .unwrap_or(false),
+            Pattern::Dates(ref from, ref to) => NaiveDate::parse_from_str(field.trim(), "%Y-%m-%d")
+                .map(|d| within(d, from, to))
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// a line in the result
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    /// line number beginning with 1
+    pub line: usize,
+    pub text: String,
+    /// false for a context line
+    pub matched: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Grep {
+    /// column beginning with 0 and its pattern
+    conditions: Vec<(usize, Pattern)>,
+    invert: bool,
+    before: usize,
+    after: usize,
+}
+
+impl Grep {
+    /// every line matches without conditions
+    pub fn new()->Grep {
+        Grep::default()
+    }
+
+    pub fn pattern(mut self, column: usize, pattern: Pattern)->Grep {
+        self.conditions.push((column, pattern));
+        self
+    }
+
+    pub fn regex(self, column: usize, re: &str)->Result<Grep, regex::Error> {
+        Ok(self.pattern(column, Pattern::Regex(Regex::new(re)?)))
+    }
+
+    /// `grep -F`
+    pub fn fixed(self, column: usize, s: &str)->Grep {
+        self.pattern(column, Pattern::Fixed(s.into()))
+    }
+
+    pub fn range(self, column: usize, min: Option<f64>, max: Option<f64>)->Grep {
+        self.pattern(column, Pattern::Range(min, max))
+    }
+
+    pub fn dates(self, column: usize, from: Option<NaiveDate>, to: Option<NaiveDate>)->Grep {
+        self.pattern(column, Pattern::Dates(from, to))
+    }
+
+    /// `-v`; select lines which do not match
+    pub fn invert(mut self, yes: bool)->Grep {
+        self.invert = yes;
+        self
+    }
+
+    /// `-B` and `-A`; lines before and after each selected line
+    pub fn context(mut self, before: usize, after: usize)->Grep {
+        self.before = before;
+        self.after = after;
+        self
+    }
+
+    /// whether `-B` or `-A` is given
+    pub fn has_context(&self)->bool {
+        self.before > 0 || self.after > 0
+    }
+
+    /// whether the fields are selected. a missing column does not match.
+    pub fn is_match<S: AsRef<str>>(&self, fields: &[S])->bool {
+        let matched = self.conditions.iter()
+            .all(|&(col, ref pattern)| fields.get(col).is_some_and(|f| pattern.is_match(f.as_ref())));
+        matched != self.invert
+    }
+
+    fn is_match_line(&self, line: &str)->bool {
+        self.is_match(&line.split('\t').collect::<Vec<_>>())
+    }
+
+    /// `-c`; the number of selected lines
+    pub fn count<S: AsRef<str>>(&self, lines: &[S])->usize {
+        lines.iter().filter(|line| self.is_match_line(line.as_ref())).count()
+    }
+
+    /// indexes of the selected rows and their context in ascending order
+    pub fn with_context(&self, matched: &[bool])->BTreeSet<usize> {
+        matched.iter()
+            .enumerate()
+            .filter(|&(_, &m)| m)
+            .flat_map(|(idx, _)| idx.saturating_sub(self.before)..(idx + self.after + 1).min(matched.len()))
+            .collect()
+    }
+
+    /// selected lines with their context
+    pub fn lines<S: AsRef<str>>(&self, lines: &[S])->Vec<Hit> {
+        let matched = lines.iter()
+            .map(|line| self.is_match_line(line.as_ref()))
+            .collect::<Vec<_>>();
+        self.with_context(&matched)
+            .into_iter()
+            .map(|idx| Hit {line: idx + 1, text: lines[idx].as_ref().to_string(), matched: matched[idx]})
+            .collect()
+    }
+}
+
+/// lines like the output of `grep`; `--` separates non-adjacent groups when context is given.
+pub fn render(hits: &[Hit], context: bool)->String {
+    let mut out = Vec::new();
+    for (idx, hit) in hits.iter().enumerate() {
+        if context && idx > 0 && hits[idx - 1].line + 1 != hit.line {
+            out.push("--");
+        }
+        out.push(&hit.text);
+    }
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINES: [&str; 6] = [
+        "高知県\t江川崎\t41\t2013-08-12",
+        "埼玉県\t熊谷\t40.9\t2007-08-16",
+        "山形県\t山形\t40.8\t1933-07-25",
+        "山梨県\t甲府\t40.7\t2013-08-10",
+        "和歌山県\tかつらぎ\t40.6\t1994-08-08",
+        "静岡県\t天竜\t40.6\t1994-08-04",
+    ];
+
+    fn texts(hits: &[Hit])->Vec<&str> {
+        hits.iter().map(|h| h.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_patterns() {
+        let grep = Grep::new().regex(0, "^山").unwrap();
+        assert_eq!(texts(&grep.lines(&LINES)), vec![LINES[2], LINES[3]]);
+        // the column is scoped
+        assert_eq!(Grep::new().fixed(1, "山形").count(&LINES), 1);
+        assert_eq!(Grep::new().fixed(1, "県").count(&LINES), 0);
+
+        let grep = Grep::new()
+            .range(2, Some(40.7), None)
+            .dates(3, "2000-01-01".parse().ok(), None);
+        assert_eq!(texts(&grep.lines(&LINES)), vec![LINES[0], LINES[1], LINES[3]]);
+        assert_eq!(Grep::new().range(2, None, Some(40.6)).count(&LINES), 2);
+        assert_eq!(Grep::new().range(0, None, None).count(&LINES), 0);
+        assert_eq!(Grep::new().fixed(9, "").count(&LINES), 0);
+        assert!(Grep::new().regex(0, "(").is_err());
+    }
+
+    #[test]
+    fn test_invert_and_context() {
+        let grep = Grep::new().regex(0, "^山").unwrap().invert(true);
+        assert_eq!(grep.count(&LINES), 4);
+
+        let hits = Grep::new().fixed(1, "熊谷").context(1, 1).lines(&LINES);
+        assert_eq!(hits.iter().map(|h| (h.line, h.matched)).collect::<Vec<_>>(), vec![(1, false), (2, true), (3, false)]);
+
+        let hits = Grep::new().regex(1, "^(江川崎|かつらぎ)$").unwrap().context(0, 1).lines(&LINES);
+        assert_eq!(
+            render(&hits, true),
+            [LINES[0], LINES[1], "--", LINES[4], LINES[5]].join("\n")
+        );
+    }
+}
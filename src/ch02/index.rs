@@ -0,0 +1,381 @@
+//! sidecar index of line offsets for random access into a text file.
+//! the index is saved as `.{name}.idx` next to the file, and rebuilt when the size or mtime of the file changes.
+//! lines are decoded in the encoding detected by the beginning of the file.
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::io::Result as ioResult;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+use super::rw::{self, Charset, Compression, SNIFF_SIZE};
+
+const MAGIC: &[u8; 8] = b"LINEIDX1";
+
+/// size and mtime of the indexed file
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Stamp {
+    size: u64,
+    /// nanoseconds since the epoch
+    mtime: u128,
+}
+
+impl Stamp {
+    fn of(path: &Path)->ioResult<Stamp> {
+        let meta = fs::metadata(path)?;
+        let mtime = meta.modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        Ok(Stamp {size: meta.len(), mtime})
+    }
+}
+
+#[derive(Debug)]
+pub struct LineIndex {
+    path: PathBuf,
+    charset: Charset,
+    /// the file when the index was built
+    stamp: Stamp,
+    /// start of each line, and the end of the file at last
+    offsets: Vec<u64>,
+    /// whether the file is empty or ends with a newline
+    terminated: bool,
+}
+
+/// `dir/.name.idx` for `dir/name`
+pub fn sidecar<P: AsRef<Path>>(path: P)->PathBuf {
+    let path = path.as_ref();
+    let name = path.file_name().map_or("".into(), |s| s.to_string_lossy());
+    path.with_file_name(format!(".{}.idx", name))
+}
+
+fn read_u64<R: Read>(reader: &mut R)->ioResult<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// strip the line terminator like `str::lines`
+fn trim_newline(mut bytes: &[u8])->&[u8] {
+    if bytes.last() == Some(&b'\n') {
+        bytes = &bytes[..bytes.len() - 1];
+        if bytes.last() == Some(&b'\r') {
+            bytes = &bytes[..bytes.len() - 1];
+        }
+    }
+    bytes
+}
+
+/// encoding of the file. compressed files and UTF-16, whose newline is not a byte, cannot be indexed.
+fn charset_of(path: &Path)->ioResult<Charset> {
+    let compression = rw::compression(path)?;
+    if compression != Compression::Plain {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} file cannot be indexed", compression)));
+    }
+    let mut head = Vec::new();
+    File::open(path)?.take(SNIFF_SIZE).read_to_end(&mut head)?;
+    match rw::detect_prefix(&head) {
+        c @ Charset::Utf16Le | c @ Charset::Utf16Be =>
+            Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} file cannot be indexed", c))),
+        c => Ok(c),
+    }
+}
+
+impl LineIndex {
+    /// load the index of the file, or build and save it if missing or stale.
+    /// compressed and UTF-16 files cannot be indexed, and the error kind is InvalidInput.
+    pub fn open<P: AsRef<Path>>(path: P)->ioResult<LineIndex> {
+        let path = path.as_ref();
+        let charset = charset_of(path)?;
+        let stamp = Stamp::of(path)?;
+        match LineIndex::load(path, charset, stamp) {
+            Ok(Some(index)) => return Ok(index),
+            Ok(None) => debug!("index of {:?} is stale or broken", path),
+            Err(e) => debug!("fail to load index of {:?}: {}", path, e),
+        }
+        let index = LineIndex::scan(path, charset, stamp)?;
+        // the index is a cache, so the file is readable even if it cannot be saved.
+        if let Err(e) = index.save() {
+            debug!("fail to save index of {:?}: {}", path, e);
+        }
+        Ok(index)
+    }
+
+    /// same as `open`, but None if the file cannot be indexed, e.g. compressed
+    pub fn open_if_indexable<P: AsRef<Path>>(path: P)->ioResult<Option<LineIndex>> {
+        match LineIndex::open(path.as_ref()) {
+            Ok(index) => Ok(Some(index)),
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidInput => {
+                debug!("read {:?} without index: {}", path.as_ref(), e);
+                Ok(None)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// scan the file for newlines
+    pub fn build<P: AsRef<Path>>(path: P)->ioResult<LineIndex> {
+        let path = path.as_ref();
+        LineIndex::scan(path, charset_of(path)?, Stamp::of(path)?)
+    }
+
+    fn scan(path: &Path, charset: Charset, stamp: Stamp)->ioResult<LineIndex> {
+        let mut reader = BufReader::with_capacity(1 << 16, File::open(path)?);
+        let mut offsets = vec![0];
+        let mut pos = 0u64;
+        let mut buf = vec![0; 1 << 16];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            offsets.extend(buf[..n].iter()
+                .enumerate()
+                .filter(|&(_, &b)| b == b'\n')
+                .map(|(i, _)| pos + i as u64 + 1));
+            pos += n as u64;
+        }
+        // the last line without newline
+        let terminated = *offsets.last().unwrap() == pos;
+        if !terminated {
+            offsets.push(pos);
+        }
+        Ok(LineIndex {path: path.to_path_buf(), charset, stamp, offsets, terminated})
+    }
+
+    /// None if the index does not match the stamp, or the offsets are broken
+    fn load(path: &Path, charset: Charset, stamp: Stamp)->ioResult<Option<LineIndex>> {
+        let mut reader = BufReader::new(File::open(sidecar(path))?);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Ok(None);
+        }
+        let size = read_u64(&mut reader)?;
+        let mtime = (read_u64(&mut reader)? as u128) << 64 | read_u64(&mut reader)? as u128;
+        if (Stamp {size, mtime}) != stamp {
+            return Ok(None);
+        }
+        // a line has a byte at least, so there are no more offsets than bytes and the start.
+        let n = read_u64(&mut reader)?;
+        if n == 0 || n > stamp.size + 1 {
+            return Ok(None);
+        }
+        let offsets = (0..n)
+            .map(|_| read_u64(&mut reader))
+            .collect::<ioResult<Vec<_>>>()?;
+        if offsets[0] != 0 || offsets[offsets.len() - 1] != stamp.size || offsets.windows(2).any(|w| w[0] > w[1]) {
+            return Ok(None);
+        }
+        let terminated = stamp.size == 0 || {
+            let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(stamp.size - 1))?;
+            let mut last = [0];
+            file.read_exact(&mut last)?;
+            last[0] == b'\n'
+        };
+        Ok(Some(LineIndex {path: path.to_path_buf(), charset, stamp, offsets, terminated}))
+    }
+
+    fn save(&self)->ioResult<()> {
+        let stamp = self.stamp;
+        let mut buf = Vec::with_capacity(40 + 8 * self.offsets.len());
+        buf.extend_from_slice(MAGIC);
+        for n in &[stamp.size, (stamp.mtime >> 64) as u64, stamp.mtime as u64, self.offsets.len() as u64] {
+            buf.extend_from_slice(&n.to_le_bytes());
+        }
+        for offset in &self.offsets {
+            buf.extend_from_slice(&offset.to_le_bytes());
+        }
+        rw::write(buf, sidecar(&self.path))
+    }
+
+    /// number of lines. the last line without newline is counted.
+    pub fn count(&self)->usize {
+        self.offsets.len() - 1
+    }
+
+    /// number of newlines like `wc -l`, so the last line without newline is not counted.
+    pub fn newlines(&self)->usize {
+        self.count() - if self.terminated {0} else {1}
+    }
+
+    /// whether the file is not modified since the index was built
+    pub fn is_fresh(&self)->ioResult<bool> {
+        Ok(Stamp::of(&self.path)? == self.stamp)
+    }
+
+    /// byte range of the lines including their newlines
+    pub fn span(&self, range: Range<usize>)->Range<u64> {
+        let end = range.end.min(self.count());
+        let start = range.start.min(end);
+        self.offsets[start]..self.offsets[end]
+    }
+
+    /// lines in the range beginning with 0, read at once
+    pub fn lines(&self, range: Range<usize>)->ioResult<Vec<String>> {
+        let span = self.span(range);
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(span.start))?;
+        let mut buf = vec![0; (span.end - span.start) as usize];
+        file.read_exact(&mut buf)?;
+        buf.split_inclusive(|&b| b == b'\n')
+            .map(|line| rw::decode(trim_newline(line), self.charset))
+            .collect()
+    }
+
+    /// the n-th line beginning with 0. None if out of range.
+    pub fn line(&self, n: usize)->ioResult<Option<String>> {
+        if n >= self.count() {
+            return Ok(None);
+        }
+        Ok(self.lines(n..n + 1)?.pop())
+    }
+
+    pub fn head(&self, n: usize)->ioResult<Vec<String>> {
+        self.lines(0..n)
+    }
+
+    pub fn tail(&self, n: usize)->ioResult<Vec<String>> {
+        let count = self.count();
+        self.lines(count.saturating_sub(n)..count)
+    }
+}
+
+/// the line index kept between calls, which is reopened when the size or mtime of the file changes.
+/// None is also kept for a file which cannot be indexed.
+pub struct IndexCache {
+    path: PathBuf,
+    cached: RefCell<Option<(Stamp, Option<Rc<LineIndex>>)>>,
+}
+
+impl IndexCache {
+    pub fn new<P: AsRef<Path>>(path: P)->IndexCache {
+        IndexCache {path: path.as_ref().to_path_buf(), cached: RefCell::new(None)}
+    }
+
+    /// the index of the file as it is now. the file is stat-ed at each call.
+    pub fn get(&self)->ioResult<Option<Rc<LineIndex>>> {
+        let stamp = Stamp::of(&self.path)?;
+        if let Some((cached, ref index)) = *self.cached.borrow() {
+            if cached == stamp {
+                return Ok(index.clone());
+            }
+        }
+        let index = LineIndex::open_if_indexable(&self.path)?.map(Rc::new);
+        *self.cached.borrow_mut() = Some((stamp, index.clone()));
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::time::Duration;
+
+    #[test]
+    fn test_lines() {
+        let path = "./data/ch02/index.txt";
+        rw::write("a\nb\r\n\nd", path).unwrap();
+        let _ = fs::remove_file(sidecar(path));
+
+        let index = LineIndex::open(path).unwrap();
+        assert_eq!(index.count(), 4);
+        assert_eq!(index.newlines(), 3);
+        assert_eq!(index.line(1).unwrap(), Some("b".into()));
+        assert_eq!(index.line(2).unwrap(), Some("".into()));
+        assert_eq!(index.line(3).unwrap(), Some("d".into()));
+        assert_eq!(index.line(4).unwrap(), None);
+        assert_eq!(index.lines(1..3).unwrap(), vec!["b", ""]);
+        assert_eq!(index.head(10).unwrap(), vec!["a", "b", "", "d"]);
+        assert_eq!(index.tail(2).unwrap(), vec!["", "d"]);
+        assert_eq!(index.lines(5..9).unwrap(), Vec::<String>::new());
+        assert!(sidecar(path).exists());
+        // loaded from the sidecar
+        assert_eq!(LineIndex::open(path).unwrap().newlines(), 3);
+
+        rw::write("a\nb\n", path).unwrap();
+        assert_eq!(LineIndex::open(path).unwrap().newlines(), 2);
+        rw::write("", path).unwrap();
+        assert_eq!(LineIndex::open(path).unwrap().count(), 0);
+        assert_eq!(LineIndex::open(path).unwrap().newlines(), 0);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let path = "./data/ch02/index_stale.txt";
+        rw::write("a\nb\n", path).unwrap();
+        assert_eq!(LineIndex::open(path).unwrap().count(), 2);
+
+        // loaded from the sidecar, which is not the content of the file
+        let stamp = Stamp::of(Path::new(path)).unwrap();
+        let fake = |offsets: Vec<u64>| LineIndex {path: path.into(), charset: Charset::Utf8, stamp, offsets, terminated: true};
+        fake(vec![0, 4]).save().unwrap();
+        assert_eq!(LineIndex::open(path).unwrap().count(), 1);
+
+        // broken offsets are rebuilt
+        for offsets in [vec![], vec![2, 4], vec![0, 3, 2, 4], vec![0, 2]] {
+            fake(offsets).save().unwrap();
+            assert_eq!(LineIndex::open(path).unwrap().count(), 2);
+        }
+
+        // same size but modified later
+        rw::write("c\nd\n", path).unwrap();
+        let modified = fs::metadata(path).unwrap().modified().unwrap() + Duration::from_secs(10);
+        OpenOptions::new().write(true).open(path).unwrap().set_modified(modified).unwrap();
+        let index = LineIndex::open(path).unwrap();
+        assert_eq!(index.count(), 2);
+        assert_eq!(index.line(1).unwrap(), Some("d".into()));
+
+        // different size
+        rw::append("e\n", path).unwrap();
+        assert_eq!(LineIndex::open(path).unwrap().tail(1).unwrap(), vec!["e"]);
+    }
+
+    #[test]
+    fn test_cache() {
+        let path = "./data/ch02/index_cache.txt";
+        rw::write("a\nb\n", path).unwrap();
+        let cache = IndexCache::new(path);
+        let index = cache.get().unwrap().unwrap();
+        assert!(Rc::ptr_eq(&index, &cache.get().unwrap().unwrap()));
+        assert!(index.is_fresh().unwrap());
+
+        // reopened after the file changes
+        rw::append("c", path).unwrap();
+        assert!(!index.is_fresh().unwrap());
+        let index = cache.get().unwrap().unwrap();
+        assert_eq!((index.count(), index.newlines()), (3, 2));
+
+        let path = "./data/ch02/index_cache.txt.gz";
+        rw::write("a\n", path).unwrap();
+        assert!(IndexCache::new(path).get().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_compressed() {
+        let path = "./data/ch02/index.txt.gz";
+        rw::write("a\n", path).unwrap();
+        assert_eq!(LineIndex::open(path).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let path = "./data/ch02/index_utf16.txt";
+        rw::write_with_charset("a\n", path, Charset::Utf16Le).unwrap();
+        assert_eq!(LineIndex::open(path).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_cp932() {
+        let path = "./data/ch02/index_cp932.txt";
+        rw::write_with_charset("高知県\t江川崎\r\n埼玉県\t熊谷\r\n", path, Charset::ShiftJis).unwrap();
+        let _ = fs::remove_file(sidecar(path));
+
+        let index = LineIndex::open(path).unwrap();
+        assert_eq!(index.line(1).unwrap(), Some("埼玉県\t熊谷".into()));
+        // loaded from the sidecar
+        assert_eq!(LineIndex::open(path).unwrap().head(2).unwrap(), vec!["高知県\t江川崎", "埼玉県\t熊谷"]);
+    }
+}
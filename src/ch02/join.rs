@@ -0,0 +1,134 @@
+//! relational join of two delimiter-separated inputs on key columns, like `join`.
+//! this is a hash join, so the inputs need not be sorted.
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    Inner,
+    /// unpaired rows of left are also printed, `join -a1`
+    Left,
+    /// unpaired rows of both sides are also printed, `join -a1 -a2`
+    Outer,
+}
+
+#[derive(Debug, Clone)]
+pub struct Join {
+    left_key: usize,
+    right_key: usize,
+    kind: JoinKind,
+    delimiter: char,
+}
+
+impl Join {
+    /// keys are column indexes beginning with 1, like `join -1 ${left_key} -2 ${right_key}`
+    pub fn new(left_key: usize, right_key: usize)->Join {
+        assert!(left_key > 0 && right_key > 0, "key columns begin with 1");
+        Join {left_key, right_key, kind: JoinKind::Inner, delimiter: '\t'}
+    }
+
+    pub fn kind(mut self, kind: JoinKind)->Join {
+        self.kind = kind;
+        self
+    }
+
+    pub fn delimiter(mut self, delimiter: char)->Join {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// split into (key, the other fields)
+    fn split<'a>(&self, line: &'a str, key: usize)->(&'a str, Vec<&'a str>) {
+        let mut fields = line.split(self.delimiter).collect::<Vec<_>>();
+        let key = if key <= fields.len() {fields.remove(key - 1)} else {""};
+        (key, fields)
+    }
+
+    /// the number of non-key fields, decided by the first line like `join -o auto`
+    fn width<S: AsRef<str>>(&self, lines: &[S], key: usize)->usize {
+        lines.first()
+            .map_or(0, |line| self.split(line.as_ref(), key).1.len())
+    }
+
+    fn format(&self, key: &str, left: &[&str], right: &[&str])->String {
+        ::std::iter::once(key)
+            .chain(left.iter().cloned())
+            .chain(right.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(&self.delimiter.to_string())
+    }
+
+    /// output is the key, the other fields of left and those of right.
+    /// rows are in the order of left, and unpaired rows of right follow them in outer join.
+    /// fields of the missing side are empty, like `join -e '' -o auto`.
+    pub fn join<S1: AsRef<str>, S2: AsRef<str>>(&self, left: &[S1], right: &[S2])->Vec<String> {
+        let right_rows = right.iter()
+            .map(|line| self.split(line.as_ref(), self.right_key))
+            .collect::<Vec<_>>();
+        let mut index: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (idx, &(key, _)) in right_rows.iter().enumerate() {
+            index.entry(key).or_default().push(idx);
+        }
+
+        let empty_left = vec![""; self.width(left, self.left_key)];
+        let empty_right = vec![""; self.width(right, self.right_key)];
+        let mut paired = vec![false; right_rows.len()];
+        let mut res = Vec::new();
+
+        for line in left {
+            let (key, fields) = self.split(line.as_ref(), self.left_key);
+            match index.get(key) {
+                Some(indexes) => for &idx in indexes {
+                    paired[idx] = true;
+                    res.push(self.format(key, &fields, &right_rows[idx].1));
+                },
+                None => if self.kind != JoinKind::Inner {
+                    res.push(self.format(key, &fields, &empty_right));
+                },
+            }
+        }
+
+        if self.kind == JoinKind::Outer {
+            for (idx, &(key, ref fields)) in right_rows.iter().enumerate() {
+                if !paired[idx] {
+                    res.push(self.format(key, &empty_left, fields));
+                }
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join() {
+        let left = ["高知県\t江川崎\t41", "埼玉県\t熊谷\t40.9", "東京都\t練馬\t39.5", "埼玉県\t越谷\t40.4"];
+        let right = ["四国\t高知県", "関東\t埼玉県", "中部\t岐阜県"];
+
+        let join = Join::new(1, 2);
+        assert_eq!(
+            join.join(&left, &right),
+            vec!["高知県\t江川崎\t41\t四国", "埼玉県\t熊谷\t40.9\t関東", "埼玉県\t越谷\t40.4\t関東"]
+        );
+
+        let join = join.kind(JoinKind::Left);
+        assert_eq!(join.join(&left, &right)[2], "東京都\t練馬\t39.5\t");
+
+        let join = join.kind(JoinKind::Outer);
+        let res = join.join(&left, &right);
+        assert_eq!(res.len(), 5);
+        assert_eq!(res[4], "岐阜県\t\t\t中部");
+    }
+
+    #[test]
+    fn test_join_duplicated_keys() {
+        let left = ["a,1", "a,2"];
+        let right = ["a,x", "a,y"];
+        assert_eq!(
+            Join::new(1, 1).delimiter(',').join(&left, &right),
+            vec!["a,1,x", "a,1,y", "a,2,x", "a,2,y"]
+        );
+    }
+}
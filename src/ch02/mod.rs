@@ -1,5 +1,25 @@
 pub mod ch02;
 mod command;
 mod util;
-mod rw;
-mod as_csv;
\ No newline at end of file
+pub(crate) mod rw;
+mod as_csv;
+mod sort;
+mod frequency;
+mod pipeline;
+mod cut;
+mod paste;
+mod join;
+mod tail;
+mod aggregate;
+mod format;
+mod runner;
+mod wc;
+mod expand;
+mod sample;
+mod extsort;
+mod query;
+mod dedup;
+mod grep;
+pub(crate) mod index;
+mod partition;
+mod prefecture;
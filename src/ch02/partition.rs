@@ -0,0 +1,334 @@
+//! partition tab-separated lines into files by a key column, like `awk -F'\t' '{print > $1}'`.
+//! a file is opened for each key, and the least recently used one is closed when too many are open.
+extern crate sha2;
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::io::Result as ioResult;
+use std::path::{Path, PathBuf};
+
+use self::sha2::{Digest, Sha256};
+
+use super::frequency;
+
+/// longest file name in bytes on most file systems
+const NAME_MAX: usize = 255;
+/// `%%` and the hex digest which replace the end of a long key. sanitized keys never contain `%%`.
+const DIGEST_LEN: usize = 2 + 16;
+
+/// characters allowed in file names
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Sanitize {
+    /// keep non-ASCII characters like Japanese, and encode only unsafe ones
+    #[default]
+    Unicode,
+    /// encode everything except ASCII letters, digits, `-` and `_`
+    Ascii,
+}
+
+impl Sanitize {
+    fn is_safe(&self, c: char)->bool {
+        match *self {
+            Sanitize::Unicode => !c.is_control() && !"/\\:*?\"<>|%".contains(c),
+            Sanitize::Ascii => c.is_ascii_alphanumeric() || c == '-' || c == '_',
+        }
+    }
+
+    /// percent-encode unsafe characters, so distinct keys never share a file.
+    /// a leading dot is encoded not to hide the file or to escape by `..`.
+    pub fn apply(&self, key: &str)->String {
+        if key.is_empty() {
+            return "%".into();
+        }
+        let mut name = String::new();
+        for (idx, c) in key.chars().enumerate() {
+            if self.is_safe(c) && !(idx == 0 && c == '.') {
+                name.push(c);
+            } else {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    name.push_str(&format!("%{:02X}", b));
+                }
+            }
+        }
+        name
+    }
+}
+
+/// a file in the manifest
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Part {
+    pub key: String,
+    pub path: PathBuf,
+    pub rows: usize,
+}
+
+/// helper for a partition being written
+struct Output {
+    part: Part,
+    writer: Option<BufWriter<File>>,
+    /// when it is written last
+    used: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Partitioner {
+    column: usize,
+    dir: PathBuf,
+    template: String,
+    sanitize: Sanitize,
+    max_open: usize,
+}
+
+impl Partitioner {
+    /// partition by the column beginning with 0 into `./{key}.tsv`, with 64 files open at most
+    pub fn new(column: usize)->Partitioner {
+        Partitioner {
+            column,
+            dir: PathBuf::from("."),
+            template: "{key}.tsv".into(),
+            sanitize: Sanitize::default(),
+            max_open: 64,
+        }
+    }
+
+    pub fn dir<P: AsRef<Path>>(mut self, dir: P)->Partitioner {
+        self.dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// file name where `{key}` is replaced with the sanitized key, e.g. `hightemp-{key}.txt` or `{key}/rows.tsv`.
+    /// a key too long for a file name is cut, and the digest of the whole key is appended.
+    pub fn template(mut self, template: &str)->Partitioner {
+        self.template = template.into();
+        self
+    }
+
+    pub fn sanitize(mut self, sanitize: Sanitize)->Partitioner {
+        self.sanitize = sanitize;
+        self
+    }
+
+    /// cap on files open at once. one file is open at least.
+    pub fn max_open(mut self, n: usize)->Partitioner {
+        self.max_open = n.max(1);
+        self
+    }
+
+    /// path of the partition for the key
+    pub fn path_of(&self, key: &str)->PathBuf {
+        let name = self.sanitize.apply(key);
+        let mut path = self.dir.clone();
+        for part in self.template.split('/') {
+            let count = part.matches("{key}").count();
+            let fixed = part.len() - count * "{key}".len();
+            if count == 0 || fixed + count * name.len() <= NAME_MAX {
+                path.push(part.replace("{key}", &name));
+            } else {
+                path.push(part.replace("{key}", &shorten(&name, (NAME_MAX - fixed) / count)));
+            }
+        }
+        path
+    }
+
+    /// the template has `{key}`, and its file names have room for the digest of a long key
+    fn check_template(&self)->ioResult<()> {
+        if !self.template.contains("{key}") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "template has no {key}"));
+        }
+        for part in self.template.split('/') {
+            let count = part.matches("{key}").count();
+            if part.len() - count * "{key}".len() + count * DIGEST_LEN > NAME_MAX {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("file name {:?} is too long", part)));
+            }
+        }
+        Ok(())
+    }
+
+    /// write lines into the partitions, which are truncated first.
+    /// return the manifest in order of the first appearance of keys.
+    pub fn run<I, S>(&self, lines: I)->ioResult<Vec<Part>>
+        where I: IntoIterator<Item=S>, S: AsRef<str>
+    {
+        self.run_results(lines.into_iter().map(Ok))
+    }
+
+    /// partition lines of the reader without loading them at once
+    pub fn run_reader<R: BufRead>(&self, reader: R)->ioResult<Vec<Part>> {
+        self.run_results(reader.lines())
+    }
+
+    /// partition lines read one by one, e.g. decoded by `rw::open_lines`. stop at the first error.
+    pub fn run_results<I, S>(&self, lines: I)->ioResult<Vec<Part>>
+        where I: IntoIterator<Item=ioResult<S>>, S: AsRef<str>
+    {
+        self.check_template()?;
+        fs::create_dir_all(&self.dir)?;
+        let mut outputs: Vec<Output> = Vec::new();
+        let mut index = HashMap::new();
+        let mut open = 0;
+
+        for (tick, line) in lines.into_iter().enumerate() {
+            let line = line?;
+            let line = line.as_ref();
+            let key = frequency::key_of(line, &[self.column]);
+            let idx = *index.entry(key).or_insert_with_key(|key| {
+                outputs.push(Output {
+                    part: Part {key: key.clone(), path: self.path_of(key), rows: 0},
+                    writer: None,
+                    used: 0,
+                });
+                outputs.len() - 1
+            });
+
+            if outputs[idx].writer.is_none() {
+                if open == self.max_open {
+                    let lru = outputs.iter_mut()
+                        .filter(|o| o.writer.is_some())
+                        .min_by_key(|o| o.used)
+                        .unwrap();
+                    lru.writer.take().unwrap().flush()?;
+                    open -= 1;
+                }
+                let output = &mut outputs[idx];
+                // appended after reopened
+                let file = if output.part.rows == 0 {
+                    // the template may have directories
+                    if let Some(parent) = output.part.path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    File::create(&output.part.path)?
+                } else {
+                    OpenOptions::new().append(true).open(&output.part.path)?
+                };
+                output.writer = Some(BufWriter::new(file));
+                open += 1;
+            }
+
+            let output = &mut outputs[idx];
+            writeln!(output.writer.as_mut().unwrap(), "{}", line)?;
+            output.part.rows += 1;
+            output.used = tick;
+        }
+
+        let mut manifest = Vec::with_capacity(outputs.len());
+        for output in outputs {
+            if let Some(mut w) = output.writer {
+                w.flush()?;
+            }
+            manifest.push(output.part);
+        }
+        debug!("partitioned into {} files", manifest.len());
+        Ok(manifest)
+    }
+}
+
+/// cut the sanitized key into `max` bytes with the digest of the whole key, so distinct keys stay distinct
+fn shorten(name: &str, max: usize)->String {
+    let mut end = max.saturating_sub(DIGEST_LEN).min(name.len());
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    let digest = Sha256::digest(name.as_bytes());
+    let hex = digest[..8].iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    format!("{}%%{}", &name[..end], hex)
+}
+
+/// manifest as tab-separated lines of key, path and rows
+pub fn render(manifest: &[Part])->String {
+    manifest.iter()
+        .map(|p| format!("{}\t{}\t{}", p.key, p.path.display(), p.rows))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use ch02::rw;
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(Sanitize::Unicode.apply("高知県"), "高知県");
+        assert_eq!(Sanitize::Unicode.apply("a/b:c%"), "a%2Fb%3Ac%25");
+        assert_eq!(Sanitize::Unicode.apply(".."), "%2E.");
+        assert_eq!(Sanitize::Unicode.apply(""), "%");
+        assert_eq!(Sanitize::Ascii.apply("高知 a-1"), "%E9%AB%98%E7%9F%A5%20a-1");
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = "./data/ch02/partition";
+        let _ = fs::remove_dir_all(dir);
+        let mut lines = (0..200)
+            .map(|i| format!("{}\t{}", ["高知県", "埼玉県", "a/b", "", "山形県"][i * 7 % 5], i))
+            .collect::<Vec<_>>();
+        lines.push("missing".into());
+        let input = format!("{}.txt", dir);
+        rw::write_lines(&lines, &input).unwrap();
+
+        for &max_open in &[1, 2, 64] {
+            let manifest = Partitioner::new(0)
+                .dir(dir)
+                .template("p-{key}.txt")
+                .max_open(max_open)
+                .run_reader(rw::open(&input).unwrap())
+                .unwrap();
+            assert_eq!(
+                manifest.iter().map(|p| (p.key.as_str(), p.rows)).collect::<Vec<_>>(),
+                vec![("高知県", 40), ("a/b", 40), ("山形県", 40), ("埼玉県", 40), ("", 40), ("missing", 1)]
+            );
+            assert_eq!(manifest[1].path, Path::new(dir).join("p-a%2Fb.txt"));
+            assert_eq!(manifest[4].path, Path::new(dir).join("p-%.txt"));
+
+            // same lines as awk selects
+            for part in &manifest {
+                let output = Command::new("awk")
+                    .args(["-F", "\t", "-v", &format!("k={}", part.key), "$1 == k", &input])
+                    .output()
+                    .unwrap();
+                assert_eq!(rw::read(&part.path).unwrap(), String::from_utf8(output.stdout).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_template() {
+        let dir = "./data/ch02/partition_template";
+        let res = Partitioner::new(0).dir(dir).template("all.txt").run(vec!["a"]);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let manifest = Partitioner::new(1).dir(dir).template("{key}").sanitize(Sanitize::Ascii).run(vec!["a\t東京", "b"]).unwrap();
+        assert_eq!(render(&manifest), format!("東京\t{0}/%E6%9D%B1%E4%BA%AC\t1\n\t{0}/%\t1", dir));
+
+        // a directory for each key
+        let manifest = Partitioner::new(0).dir(dir).template("{key}/rows.tsv").run(vec!["高知県\t1", "埼玉県\t2", "高知県\t3"]).unwrap();
+        assert_eq!(manifest[0].path, Path::new(dir).join("高知県").join("rows.tsv"));
+        assert_eq!(rw::read(&manifest[0].path).unwrap(), "高知県\t1\n高知県\t3\n");
+
+        // no room for the digest of a long key
+        let res = Partitioner::new(0).dir(dir).template(&format!("{}-{{key}}", "a".repeat(240))).run(vec!["a"]);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_long_key() {
+        let dir = "./data/ch02/partition_long";
+        let _ = fs::remove_dir_all(dir);
+        let (long, longer) = ("あ".repeat(100), format!("{}い", "あ".repeat(100)));
+        let lines = vec![format!("{}\t1", long), format!("{}\t2", longer), "a\t3".to_string()];
+        let manifest = Partitioner::new(0).dir(dir).template("p-{key}.tsv").run(&lines).unwrap();
+
+        // cut into the limit, and distinct keys have distinct files
+        let names = manifest.iter()
+            .map(|p| p.path.file_name().unwrap().to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert!(names.iter().all(|name| name.len() <= NAME_MAX), "{:?}", names);
+        assert!(names[0].starts_with("p-あ") && names[0].ends_with(".tsv") && names[0].contains("%%"));
+        assert_ne!(names[0], names[1]);
+        assert_eq!(names[2], "p-a.tsv");
+        assert_eq!(rw::read(&manifest[1].path).unwrap(), format!("{}\t2\n", longer));
+    }
+}
@@ -0,0 +1,73 @@
+//! `paste` over N inputs
+
+/// delimiter after the `idx`-th column. an empty list means no delimiter like `paste -d ''`.
+fn delimiter_at(delimiters: &str, idx: usize)->String {
+    delimiters.chars()
+        .cycle()
+        .nth(idx)
+        .map(|c| c.to_string())
+        .unwrap_or_default()
+}
+
+/// `paste -d ${delimiters}`; delimiters are used cyclically.
+/// a shorter input is regarded as having empty lines, so nothing is truncated.
+pub fn paste<V: AsRef<[S]>, S: AsRef<str>>(inputs: &[V], delimiters: &str)->Vec<String> {
+    let len = inputs.iter().map(|v| v.as_ref().len()).max().unwrap_or(0);
+    (0..len)
+        .map(|row| {
+            inputs.iter()
+                .enumerate()
+                .fold(String::new(), |mut acc, (idx, input)| {
+                    if idx > 0 {
+                        acc.push_str(&delimiter_at(delimiters, idx - 1));
+                    }
+                    acc.push_str(input.as_ref().get(row).map_or("", |s| s.as_ref()));
+                    acc
+                })
+        })
+        .collect()
+}
+
+/// `paste -s -d ${delimiters}`; each input becomes one line.
+pub fn paste_serial<V: AsRef<[S]>, S: AsRef<str>>(inputs: &[V], delimiters: &str)->Vec<String> {
+    inputs.iter()
+        .map(|input| {
+            input.as_ref()
+                .iter()
+                .enumerate()
+                .fold(String::new(), |mut acc, (idx, s)| {
+                    if idx > 0 {
+                        acc.push_str(&delimiter_at(delimiters, idx - 1));
+                    }
+                    acc.push_str(s.as_ref());
+                    acc
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paste() {
+        let inputs = vec![vec!["a", "b", "c"], vec!["1", "2"], vec!["x", "y", "z"]];
+        assert_eq!(
+            paste(&inputs, "\t"),
+            vec!["a\t1\tx", "b\t2\ty", "c\t\tz"]
+        );
+        assert_eq!(
+            paste(&inputs, ",;"),
+            vec!["a,1;x", "b,2;y", "c,;z"]
+        );
+        assert_eq!(paste(&inputs[..1], ""), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_paste_serial() {
+        let inputs = vec![vec!["a", "b", "c"], vec!["1", "2"]];
+        assert_eq!(paste_serial(&inputs, "\t"), vec!["a\tb\tc", "1\t2"]);
+        assert_eq!(paste_serial(&inputs, ",;"), vec!["a,b;c", "1,2"]);
+    }
+}
@@ -0,0 +1,471 @@
+//! streaming pipeline like `cut -f1 | sort | uniq`.
+//! each stage is an in-process transform or an external command, and runs in its own thread or process.
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Write, Result as ioResult};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::sort::SortSpec;
+use super::runner::Invocation;
+
+/// the number of chunks buffered between in-process stages
+const CHANNEL_BOUND: usize = 16;
+
+type Transform = Box<dyn FnOnce(&mut dyn BufRead, &mut dyn Write)->ioResult<()> + Send>;
+
+enum Stage {
+    Transform {name: String, f: Transform},
+    Command {name: String, program: String, args: Vec<OsString>, envs: Vec<(String, String)>},
+}
+
+enum Source {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+/// running stage which is waited for after the last output is read.
+enum Running {
+    Thread {name: String, handle: JoinHandle<ioResult<()>>},
+    Child {name: String, child: Child, feeder: JoinHandle<ioResult<()>>, stderr: JoinHandle<Vec<u8>>},
+}
+
+/// status of each stage
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageStatus {
+    pub name: String,
+    pub success: bool,
+    /// exit code of a command. None for in-process stages or commands killed by signal.
+    pub code: Option<i32>,
+    /// stderr of a command, or error of an in-process stage
+    pub error: String,
+}
+
+#[derive(Debug)]
+pub struct Output {
+    pub stdout: Vec<u8>,
+    pub statuses: Vec<StageStatus>,
+}
+
+impl Output {
+    pub fn success(&self)->bool {
+        self.statuses.iter().all(|s| s.success)
+    }
+
+    /// error with the first failed stage
+    pub fn check(&self)->ioResult<()> {
+        match self.statuses.iter().find(|s| !s.success) {
+            Some(s) => Err(io::Error::other(
+                format!("stage `{}` failed (code: {:?}): {}", s.name, s.code, s.error.trim())
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// sender half of an in-process pipe
+struct ChannelWriter(SyncSender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8])->ioResult<usize> {
+        self.0.send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "downstream stage exited"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self)->ioResult<()> {
+        Ok(())
+    }
+}
+
+/// receiver half of an in-process pipe. EOF when the sender is dropped.
+struct ChannelReader {rx: Receiver<Vec<u8>>, buf: Vec<u8>, pos: usize}
+
+impl Read for ChannelReader {
+    fn read(&mut self, out: &mut [u8])->ioResult<usize> {
+        while self.pos == self.buf.len() {
+            match self.rx.recv() {
+                Ok(buf) => {
+                    self.buf = buf;
+                    self.pos = 0;
+                },
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// a closed downstream is not an error of the writer, like SIGPIPE in shell.
+fn ignore_broken_pipe(res: ioResult<()>)->ioResult<()> {
+    match res {
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        res => res,
+    }
+}
+
+/// apply `f` to each line. the line is dropped if `f` returns None.
+fn map_lines<F>(input: &mut dyn BufRead, output: &mut dyn Write, mut f: F)->ioResult<()>
+    where F: FnMut(&str)->Option<String>
+{
+    for line in input.lines() {
+        if let Some(s) = f(&line?) {
+            writeln!(output, "{}", s)?;
+        }
+    }
+    Ok(())
+}
+
+pub struct Pipeline {
+    source: Source,
+    stages: Vec<Stage>,
+    envs: Vec<(String, String)>,
+    timeout: Option<Duration>,
+}
+
+impl Pipeline {
+    pub fn from_file<P: AsRef<Path>>(path: P)->Pipeline {
+        Pipeline::from_source(Source::File(path.as_ref().to_path_buf()))
+    }
+
+    pub fn from_bytes<B: Into<Vec<u8>>>(bytes: B)->Pipeline {
+        Pipeline::from_source(Source::Bytes(bytes.into()))
+    }
+
+    fn from_source(source: Source)->Pipeline {
+        Pipeline {source, stages: Vec::new(), envs: Vec::new(), timeout: None}
+    }
+
+    /// kill all commands if the whole pipeline doesn't finish in time, and fail with `TimedOut`.
+    /// in-process stages cannot be killed, but they end when the killed commands close their pipes.
+    pub fn timeout(mut self, timeout: Duration)->Pipeline {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// set environment variable of all external commands, e.g. `LC_ALL=C`
+    pub fn env(mut self, key: &str, val: &str)->Pipeline {
+        self.envs.push((key.into(), val.into()));
+        self
+    }
+
+    /// external command stage
+    pub fn command(self, program: &str, args: &[&str])->Pipeline {
+        self.invocation(&Invocation::new(program).args(args))
+    }
+
+    /// external command stage with its own environment variables. its stdin is the upstream.
+    pub fn invocation(mut self, invocation: &Invocation)->Pipeline {
+        self.stages.push(Stage::Command {
+            name: Invocation {envs: Vec::new(), ..invocation.clone()}.to_string(),
+            program: invocation.program.clone(),
+            args: invocation.args.clone(),
+            envs: invocation.envs.clone(),
+        });
+        self
+    }
+
+    /// in-process stage which reads upstream and writes downstream.
+    pub fn transform<F>(mut self, name: &str, f: F)->Pipeline
+        where F: FnOnce(&mut dyn BufRead, &mut dyn Write)->ioResult<()> + Send + 'static
+    {
+        self.stages.push(Stage::Transform {name: name.into(), f: Box::new(f)});
+        self
+    }
+
+    /// `cut -f ${fields}`; fields begin with 1.
+    pub fn cut(self, fields: &[usize])->Pipeline {
+        let fields = fields.to_vec();
+        self.transform("cut", move |input, output| {
+            map_lines(input, output, |line| {
+                let cols = line.split('\t').collect::<Vec<_>>();
+                // like cut, a line without delimiter is printed as it is.
+                if cols.len() == 1 {
+                    return Some(line.to_string());
+                }
+                Some(
+                    cols.into_iter()
+                        .enumerate()
+                        .filter(|&(idx, _)| fields.contains(&(idx + 1)))
+                        .map(|(_, s)| s)
+                        .collect::<Vec<_>>()
+                        .join("\t")
+                )
+            })
+        })
+    }
+
+    /// `sort` with the key specification. this stage buffers all lines.
+    pub fn sort(self, spec: SortSpec)->Pipeline {
+        self.transform("sort", move |input, output| {
+            let mut lines = input.lines().collect::<ioResult<Vec<_>>>()?;
+            spec.sort(&mut lines);
+            lines.iter().try_for_each(|line| writeln!(output, "{}", line))
+        })
+    }
+
+    /// `uniq`; remove adjacent duplicated lines.
+    pub fn uniq(self)->Pipeline {
+        self.transform("uniq", |input, output| {
+            let mut prev: Option<String> = None;
+            map_lines(input, output, |line| {
+                if prev.as_deref() == Some(line) {
+                    return None;
+                }
+                prev = Some(line.to_string());
+                prev.clone()
+            })
+        })
+    }
+
+    /// `tr ${from} ${to}`; a char in `from` is replaced with the char at the same position in `to`.
+    /// if `to` is shorter, its last char is repeated as tr does.
+    pub fn tr(self, from: &str, to: &str)->Pipeline {
+        let (from, to) = (from.chars().collect::<Vec<_>>(), to.chars().collect::<Vec<_>>());
+        self.transform("tr", move |input, output| {
+            let mut line = String::new();
+            while input.read_line(&mut line)? > 0 {
+                let res = line.chars()
+                    .map(|c| match from.iter().position(|&f| f == c) {
+                        Some(idx) => *to.get(idx).or_else(|| to.last()).unwrap_or(&c),
+                        None => c,
+                    })
+                    .collect::<String>();
+                output.write_all(res.as_bytes())?;
+                line.clear();
+            }
+            Ok(())
+        })
+    }
+
+    /// run all stages concurrently and collect the output of the last stage.
+    pub fn run(self)->ioResult<Output> {
+        let mut input: Box<dyn Read + Send> = match self.source {
+            Source::File(path) => Box::new(File::open(path)?),
+            Source::Bytes(bytes) => Box::new(Cursor::new(bytes)),
+        };
+
+        let mut running = Vec::new();
+        for stage in self.stages {
+            match stage {
+                Stage::Transform {name, f} => {
+                    let (tx, rx) = sync_channel(CHANNEL_BOUND);
+                    let upstream = input;
+                    let handle = thread::spawn(move || {
+                        let mut reader = BufReader::new(upstream);
+                        let mut writer = BufWriter::new(ChannelWriter(tx));
+                        ignore_broken_pipe(
+                            f(&mut reader, &mut writer).and_then(|_| writer.flush())
+                        )
+                    });
+                    input = Box::new(ChannelReader {rx, buf: Vec::new(), pos: 0});
+                    running.push(Running::Thread {name, handle});
+                },
+                Stage::Command {name, program, args, envs} => {
+                    let spawned = Command::new(&program)
+                        .args(&args)
+                        .envs(self.envs.iter().chain(&envs).map(|(k, v)| (k, v)))
+                        .stdin(Stdio::piped())
+                        .stdout(Stdio::piped())
+                        .stderr(Stdio::piped())
+                        .spawn();
+                    let mut child = match spawned {
+                        Ok(child) => child,
+                        Err(e) => {
+                            kill(&mut running);
+                            return Err(e);
+                        },
+                    };
+                    let mut stdin = child.stdin.take().unwrap();
+                    let mut upstream = input;
+                    // stdin is closed when the feeder finishes.
+                    let feeder = thread::spawn(move || {
+                        ignore_broken_pipe(io::copy(&mut upstream, &mut stdin).map(|_| ()))
+                    });
+                    let mut err = child.stderr.take().unwrap();
+                    let stderr = thread::spawn(move || {
+                        let mut buf = Vec::new();
+                        let _ = err.read_to_end(&mut buf);
+                        buf
+                    });
+                    input = Box::new(child.stdout.take().unwrap());
+                    running.push(Running::Child {name, child, feeder, stderr});
+                },
+            }
+        }
+
+        // read in another thread to watch the deadline meanwhile
+        let reader = thread::spawn(move || {
+            let mut stdout = Vec::new();
+            input.read_to_end(&mut stdout).map(|_| stdout)
+        });
+        if let Some(timeout) = self.timeout {
+            let started = Instant::now();
+            while !finished(&reader, &mut running) {
+                if started.elapsed() > timeout {
+                    kill(&mut running);
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, format!("pipeline timed out after {:?}", timeout)));
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+        let stdout = reader.join()
+            .unwrap_or_else(|_| Err(io::Error::other("panicked")))?;
+
+        let statuses = running.into_iter()
+            .map(|stage| match stage {
+                Running::Thread {name, handle} => {
+                    let (success, error) = match handle.join() {
+                        Ok(Ok(())) => (true, String::new()),
+                        Ok(Err(e)) => (false, e.to_string()),
+                        Err(_) => (false, "panicked".to_string()),
+                    };
+                    StageStatus {name, success, code: None, error}
+                },
+                Running::Child {name, mut child, feeder, stderr} => {
+                    let fed = feeder.join().unwrap_or_else(|_| Err(io::Error::other("panicked")));
+                    let mut error = String::from_utf8_lossy(&stderr.join().unwrap_or_default()).into_owned();
+                    let (success, code) = match child.wait() {
+                        Ok(status) => (status.success() && fed.is_ok(), status.code()),
+                        Err(e) => {
+                            error.push_str(&e.to_string());
+                            (false, None)
+                        },
+                    };
+                    if let Err(e) = fed {
+                        error.push_str(&e.to_string());
+                    }
+                    StageStatus {name, success, code, error}
+                },
+            })
+            .collect();
+
+        Ok(Output {stdout, statuses})
+    }
+}
+
+/// whether the output is read to the end and every stage has exited
+/// kill and reap the commands, e.g. when the pipeline is abandoned
+fn kill(running: &mut [Running]) {
+    for stage in running.iter_mut() {
+        if let Running::Child {ref mut child, ..} = *stage {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn finished(reader: &JoinHandle<ioResult<Vec<u8>>>, running: &mut [Running])->bool {
+    reader.is_finished() && running.iter_mut().all(|stage| match *stage {
+        Running::Thread {ref handle, ..} => handle.is_finished(),
+        Running::Child {ref mut child, ..} => child.try_wait().map_or(true, |status| status.is_some()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_in_process() {
+        let output = Pipeline::from_bytes("b\t1\na\t2\nb\t3\n")
+            .cut(&[1])
+            .sort("".parse().unwrap())
+            .uniq()
+            .tr("ab", "AB")
+            .run()
+            .unwrap();
+
+        assert!(output.success());
+        assert_eq!(output.stdout, b"A\nB\n");
+        assert_eq!(
+            output.statuses.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+            vec!["cut", "sort", "uniq", "tr"]
+        );
+    }
+
+    #[test]
+    fn test_command() {
+        let output = Pipeline::from_bytes("b\na\nb\n")
+            .env("LC_ALL", "C")
+            .command("sort", &[])
+            .uniq()
+            .command("tr", &["ab", "AB"])
+            .run()
+            .unwrap();
+
+        assert!(output.check().is_ok());
+        assert_eq!(output.stdout, b"A\nB\n");
+        assert_eq!(output.statuses[0].name, "sort");
+        assert_eq!(output.statuses[0].code, Some(0));
+    }
+
+    #[test]
+    fn test_failed_stage() {
+        let output = Pipeline::from_bytes("a\n")
+            .command("sort", &["--no-such-option"])
+            .uniq()
+            .run()
+            .unwrap();
+
+        assert!(!output.success());
+        assert!(!output.statuses[0].success);
+        assert!(!output.statuses[0].error.is_empty());
+        assert!(output.statuses[1].success);
+        assert!(output.check().is_err());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let started = Instant::now();
+        let res = Pipeline::from_bytes("a\n")
+            .command("cat", &[])
+            .command("sleep", &["5"])
+            .uniq()
+            .timeout(Duration::from_millis(100))
+            .run();
+
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let output = Pipeline::from_bytes("a\n").command("cat", &[]).timeout(Duration::from_secs(5)).run().unwrap();
+        assert_eq!(output.stdout, b"a\n");
+    }
+
+    #[test]
+    fn test_spawn_error() {
+        // the stages spawned before the missing command are killed
+        let res = Pipeline::from_bytes("a\n")
+            .command("sleep", &["31.25"])
+            .command("./no-such-command", &[])
+            .run();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::NotFound);
+        let sleeping = fs::read_dir("/proc").unwrap()
+            .flatten()
+            .filter_map(|entry| fs::read(entry.path().join("cmdline")).ok())
+            .any(|cmdline| cmdline == b"sleep\x0031.25\x00");
+        assert!(!sleeping);
+    }
+
+    #[test]
+    fn test_large_input() {
+        // a pipeline copying through Vec<u8> deadlocks when the pipe buffer is full.
+        let input = (0..200000).map(|i| format!("{}\n", i % 1000)).collect::<String>();
+        let output = Pipeline::from_bytes(input)
+            .command("cat", &[])
+            .cut(&[1])
+            .command("sort", &["-n", "-u"])
+            .run()
+            .unwrap();
+
+        assert!(output.success());
+        assert_eq!(output.stdout.iter().filter(|&&b| b == b'\n').count(), 1000);
+    }
+}
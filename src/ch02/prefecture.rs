@@ -0,0 +1,230 @@
+//! prefectures of Japan in the order of JIS X 0401 code, and the collation by the code.
+extern crate chrono;
+extern crate serde;
+
+use std::cmp::Ordering;
+use std::fmt;
+use self::chrono::NaiveDate;
+use self::serde::{Serialize, Serializer};
+
+use super::as_csv::Record;
+
+/// the eight regions. Okinawa belongs to Kyushu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Block {
+    Hokkaido,
+    Tohoku,
+    Kanto,
+    Chubu,
+    Kinki,
+    Chugoku,
+    Shikoku,
+    Kyushu,
+}
+
+impl Block {
+    pub fn name(&self)->&'static str {
+        match *self {
+            Block::Hokkaido => "北海道",
+            Block::Tohoku => "東北",
+            Block::Kanto => "関東",
+            Block::Chubu => "中部",
+            Block::Kinki => "近畿",
+            Block::Chugoku => "中国",
+            Block::Shikoku => "四国",
+            Block::Kyushu => "九州",
+        }
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter)->fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// serialized as the name in Japanese
+impl Serialize for Block {
+    fn serialize<S: Serializer>(&self, serializer: S)->Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Prefecture {
+    /// JIS X 0401 code, 1 to 47
+    pub code: u8,
+    pub name: &'static str,
+    pub romaji: &'static str,
+    pub block: Block,
+    /// seat of the government. Tokyo's is Shinjuku.
+    pub capital: &'static str,
+}
+
+macro_rules! prefectures {
+    ($(($code:expr, $name:expr, $romaji:expr, $block:ident, $capital:expr)),* $(,)*) => {
+        [$(Prefecture {code: $code, name: $name, romaji: $romaji, block: Block::$block, capital: $capital}),*]
+    };
+}
+
+pub static PREFECTURES: [Prefecture; 47] = prefectures![
+    (1, "北海道", "Hokkaido", Hokkaido, "札幌市"),
+    (2, "青森県", "Aomori", Tohoku, "青森市"),
+    (3, "岩手県", "Iwate", Tohoku, "盛岡市"),
+    (4, "宮城県", "Miyagi", Tohoku, "仙台市"),
+    (5, "秋田県", "Akita", Tohoku, "秋田市"),
+    (6, "山形県", "Yamagata", Tohoku, "山形市"),
+    (7, "福島県", "Fukushima", Tohoku, "福島市"),
+    (8, "茨城県", "Ibaraki", Kanto, "水戸市"),
+    (9, "栃木県", "Tochigi", Kanto, "宇都宮市"),
+    (10, "群馬県", "Gunma", Kanto, "前橋市"),
+    (11, "埼玉県", "Saitama", Kanto, "さいたま市"),
+    (12, "千葉県", "Chiba", Kanto, "千葉市"),
+    (13, "東京都", "Tokyo", Kanto, "新宿区"),
+    (14, "神奈川県", "Kanagawa", Kanto, "横浜市"),
+    (15, "新潟県", "Niigata", Chubu, "新潟市"),
+    (16, "富山県", "Toyama", Chubu, "富山市"),
+    (17, "石川県", "Ishikawa", Chubu, "金沢市"),
+    (18, "福井県", "Fukui", Chubu, "福井市"),
+    (19, "山梨県", "Yamanashi", Chubu, "甲府市"),
+    (20, "長野県", "Nagano", Chubu, "長野市"),
+    (21, "岐阜県", "Gifu", Chubu, "岐阜市"),
+    (22, "静岡県", "Shizuoka", Chubu, "静岡市"),
+    (23, "愛知県", "Aichi", Chubu, "名古屋市"),
+    (24, "三重県", "Mie", Kinki, "津市"),
+    (25, "滋賀県", "Shiga", Kinki, "大津市"),
+    (26, "京都府", "Kyoto", Kinki, "京都市"),
+    (27, "大阪府", "Osaka", Kinki, "大阪市"),
+    (28, "兵庫県", "Hyogo", Kinki, "神戸市"),
+    (29, "奈良県", "Nara", Kinki, "奈良市"),
+    (30, "和歌山県", "Wakayama", Kinki, "和歌山市"),
+    (31, "鳥取県", "Tottori", Chugoku, "鳥取市"),
+    (32, "島根県", "Shimane", Chugoku, "松江市"),
+    (33, "岡山県", "Okayama", Chugoku, "岡山市"),
+    (34, "広島県", "Hiroshima", Chugoku, "広島市"),
+    (35, "山口県", "Yamaguchi", Chugoku, "山口市"),
+    (36, "徳島県", "Tokushima", Shikoku, "徳島市"),
+    (37, "香川県", "Kagawa", Shikoku, "高松市"),
+    (38, "愛媛県", "Ehime", Shikoku, "松山市"),
+    (39, "高知県", "Kochi", Shikoku, "高知市"),
+    (40, "福岡県", "Fukuoka", Kyushu, "福岡市"),
+    (41, "佐賀県", "Saga", Kyushu, "佐賀市"),
+    (42, "長崎県", "Nagasaki", Kyushu, "長崎市"),
+    (43, "熊本県", "Kumamoto", Kyushu, "熊本市"),
+    (44, "大分県", "Oita", Kyushu, "大分市"),
+    (45, "宮崎県", "Miyazaki", Kyushu, "宮崎市"),
+    (46, "鹿児島県", "Kagoshima", Kyushu, "鹿児島市"),
+    (47, "沖縄県", "Okinawa", Kyushu, "那覇市"),
+];
+
+impl Prefecture {
+    /// name without the suffix of 都, 府 or 県, e.g. `東京`. 北海道 is kept.
+    pub fn short_name(&self)->&'static str {
+        self.name.strip_suffix(['都', '府', '県']).unwrap_or(self.name)
+    }
+}
+
+pub fn by_code(code: u8)->Option<&'static Prefecture> {
+    PREFECTURES.get((code as usize).wrapping_sub(1))
+}
+
+/// find by the name like `東京都`, the short name like `東京`, or romaji ignoring case
+pub fn find(name: &str)->Option<&'static Prefecture> {
+    PREFECTURES.iter()
+        .find(|p| p.name == name || p.short_name() == name || p.romaji.eq_ignore_ascii_case(name))
+}
+
+/// order of prefecture names
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Collation {
+    /// UTF-8 byte order like `sort` in C locale
+    #[default]
+    Bytes,
+    /// JIS X 0401 code, from Hokkaido to Okinawa. unknown names follow in byte order.
+    Jis,
+}
+
+impl Collation {
+    pub fn compare(&self, a: &str, b: &str)->Ordering {
+        match *self {
+            Collation::Bytes => a.cmp(b),
+            Collation::Jis => {
+                let code = |s| find(s).map_or(u8::MAX, |p| p.code);
+                code(a).cmp(&code(b)).then(a.cmp(b))
+            },
+        }
+    }
+
+    /// sort and remove duplicates
+    pub fn uniq<S: AsRef<str>>(&self, mut names: Vec<S>)->Vec<S> {
+        names.sort_by(|a, b| self.compare(a.as_ref(), b.as_ref()));
+        names.dedup_by(|a, b| a.as_ref() == b.as_ref());
+        names
+    }
+}
+
+/// a row of hightemp.txt joined with the prefecture table
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Enriched {
+    pub pref: String,
+    pub region: String,
+    pub temp: f32,
+    pub date: NaiveDate,
+    /// None if the prefecture is unknown
+    pub code: Option<u8>,
+    pub block: Option<Block>,
+}
+
+/// left join of records with the prefecture table by name
+pub fn enrich(records: &[Record])->Vec<Enriched> {
+    records.iter()
+        .map(|r| {
+            let pref = find(&r.pref);
+            Enriched {
+                pref: r.pref.clone(),
+                region: r.region.clone(),
+                temp: r.temp,
+                date: r.date,
+                code: pref.map(|p| p.code),
+                block: pref.map(|p| p.block),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        assert!(PREFECTURES.iter().enumerate().all(|(idx, p)| p.code as usize == idx + 1));
+        assert_eq!(by_code(13).unwrap().name, "東京都");
+        assert!(by_code(0).is_none() && by_code(48).is_none());
+        assert_eq!(find("東京").unwrap().code, 13);
+        assert_eq!(find("北海道").unwrap().short_name(), "北海道");
+        assert_eq!(find("osaka").unwrap().block, Block::Kinki);
+        assert!(find("東").is_none());
+        assert_eq!(PREFECTURES.iter().filter(|p| p.block == Block::Kanto).count(), 7);
+    }
+
+    #[test]
+    fn test_collation() {
+        let names = vec!["高知県", "埼玉県", "不明", "北海道", "埼玉県", "大阪府"];
+        assert_eq!(Collation::Jis.uniq(names.clone()), vec!["北海道", "埼玉県", "大阪府", "高知県", "不明"]);
+        assert_eq!(Collation::Bytes.uniq(names), vec!["不明", "北海道", "埼玉県", "大阪府", "高知県"]);
+    }
+
+    #[test]
+    fn test_enrich() {
+        let records = vec![
+            Record {pref: "埼玉県".into(), region: "熊谷".into(), temp: 40.9, date: "2007-08-16".parse().unwrap()},
+            Record {pref: "不明".into(), region: "".into(), temp: 0.0, date: "2000-01-01".parse().unwrap()},
+        ];
+        let enriched = enrich(&records);
+        assert_eq!((enriched[0].code, enriched[0].block), (Some(11), Some(Block::Kanto)));
+        assert_eq!(serde_json::to_string(&enriched[0].block).unwrap(), r#""関東""#);
+        assert_eq!(enriched[1].block, None);
+    }
+}
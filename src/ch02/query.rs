@@ -0,0 +1,1055 @@
+//! a small SQL-like query language over tables of typed columns, e.g.
+//! `SELECT pref, max(temp) FROM hightemp WHERE date >= '2000-01-01' GROUP BY pref ORDER BY 2 DESC LIMIT 3`.
+//!
+//! supported: `SELECT` of `*` or expressions with `AS`, `WHERE`, `GROUP BY` with `count`, `sum`, `avg`,
+//! `min` and `max`, `ORDER BY` of output names, ordinals or expressions with `ASC`/`DESC`, and `LIMIT`.
+extern crate chrono;
+extern crate serde;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use self::chrono::NaiveDate;
+use self::serde::Serialize;
+
+use super::format::{self, Format};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    Int,
+    Float,
+    /// `%Y-%m-%d`
+    Date,
+    Str,
+}
+
+impl FromStr for ColumnType {
+    type Err = String;
+
+    fn from_str(s: &str)->Result<ColumnType, String> {
+        match s.to_lowercase().as_str() {
+            "int" | "integer" => Ok(ColumnType::Int),
+            "float" | "real" => Ok(ColumnType::Float),
+            "date" => Ok(ColumnType::Date),
+            "str" | "text" => Ok(ColumnType::Str),
+            _ => Err(format!("unknown type: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// empty cell
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Date(NaiveDate),
+    Str(String),
+}
+
+impl Value {
+    /// parse the cell as the type. an empty cell is Null.
+    fn parse(s: &str, kind: ColumnType)->Option<Value> {
+        if s.is_empty() {
+            return Some(Value::Null);
+        }
+        match kind {
+            ColumnType::Int => s.parse().ok().map(Value::Int),
+            ColumnType::Float => s.parse().ok().map(Value::Float),
+            ColumnType::Date => NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().map(Value::Date),
+            ColumnType::Str => Some(Value::Str(s.to_string())),
+        }
+    }
+
+    fn as_f64(&self)->Option<f64> {
+        match *self {
+            Value::Int(n) => Some(n as f64),
+            Value::Float(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    fn is_true(&self)->bool {
+        match *self {
+            Value::Bool(b) => b,
+            Value::Int(n) => n != 0,
+            Value::Float(f) => f != 0.0,
+            _ => false,
+        }
+    }
+
+    /// Null comes first. numbers are compared numerically, and a string is parsed to compare with a date.
+    fn compare(&self, other: &Value)->Ordering {
+        match (self, other) {
+            (&Value::Null, &Value::Null) => Ordering::Equal,
+            (&Value::Null, _) => Ordering::Less,
+            (_, &Value::Null) => Ordering::Greater,
+            (&Value::Int(a), &Value::Int(b)) => a.cmp(&b),
+            (&Value::Date(a), &Value::Date(b)) => a.cmp(&b),
+            (Value::Date(a), Value::Str(b)) => match NaiveDate::parse_from_str(b, "%Y-%m-%d") {
+                Ok(b) => a.cmp(&b),
+                Err(_) => a.to_string().cmp(b),
+            },
+            (&Value::Str(_), &Value::Date(_)) => other.compare(self).reverse(),
+            (a, b) => match (a.as_f64(), b.as_f64()) {
+                (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+                _ => a.to_string().cmp(&b.to_string()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter)->fmt::Result {
+        match *self {
+            Value::Null => Ok(()),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Date(d) => write!(f, "{}", d.format("%Y-%m-%d")),
+            Value::Str(ref s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum QueryError {
+    Syntax(String),
+    UnknownTable(String),
+    UnknownColumn(String),
+    /// a value which does not fit the type, or an invalid use of aggregates
+    Invalid(String),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter)->fmt::Result {
+        match *self {
+            QueryError::Syntax(ref s) => write!(f, "syntax error: {}", s),
+            QueryError::UnknownTable(ref s) => write!(f, "unknown table: {}", s),
+            QueryError::UnknownColumn(ref s) => write!(f, "unknown column: {}", s),
+            QueryError::Invalid(ref s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl ::std::error::Error for QueryError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub kind: ColumnType,
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub name: String,
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// the narrowest type of all non-empty cells
+fn infer<S: AsRef<str>>(cells: &[S])->ColumnType {
+    let cells = cells.iter().map(|s| s.as_ref()).filter(|s| !s.is_empty()).collect::<Vec<_>>();
+    [ColumnType::Int, ColumnType::Float, ColumnType::Date]
+        .iter()
+        .cloned()
+        .find(|&kind| !cells.is_empty() && cells.iter().all(|s| Value::parse(s, kind).is_some()))
+        .unwrap_or(ColumnType::Str)
+}
+
+impl Table {
+    /// table of string cells whose types are inferred
+    pub fn from_strings(name: &str, header: &[String], rows: &[Vec<String>])->Table {
+        let columns = header.iter()
+            .enumerate()
+            .map(|(idx, h)| {
+                let cells = rows.iter().map(|r| r.get(idx).map_or("", |s| s)).collect::<Vec<_>>();
+                Column {name: h.clone(), kind: infer(&cells)}
+            })
+            .collect::<Vec<_>>();
+        let rows = rows.iter()
+            .map(|r| columns.iter()
+                .enumerate()
+                .map(|(idx, c)| Value::parse(r.get(idx).map_or("", |s| s), c.kind).unwrap())
+                .collect())
+            .collect();
+        Table {name: name.into(), columns, rows}
+    }
+
+    /// table of records whose field names are the columns.
+    /// columns of records without field names like tuples are named `c1`, `c2`, ...
+    pub fn from_records<T: Serialize>(name: &str, records: &[T])->Table {
+        let (header, rows) = format::flatten(records);
+        let header = header.unwrap_or_else(|| {
+            let ncols = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+            (1..ncols + 1).map(|i| format!("c{}", i)).collect()
+        });
+        Table::from_strings(name, &header, &rows)
+    }
+
+    /// declare the type of the column instead of the inferred one
+    pub fn declare(mut self, column: &str, kind: ColumnType)->Result<Table, QueryError> {
+        let idx = self.columns.iter()
+            .position(|c| c.name == column)
+            .ok_or_else(|| QueryError::UnknownColumn(column.into()))?;
+        for row in &mut self.rows {
+            let s = row[idx].to_string();
+            row[idx] = Value::parse(&s, kind)
+                .ok_or_else(|| QueryError::Invalid(format!("{} is not {:?} in {}", s, kind, column)))?;
+        }
+        self.columns[idx].kind = kind;
+        Ok(self)
+    }
+
+    fn index(&self, name: &str)->Result<usize, QueryError> {
+        self.columns.iter()
+            .position(|c| c.name == name)
+            .or_else(|| self.columns.iter().position(|c| c.name.eq_ignore_ascii_case(name)))
+            .ok_or_else(|| QueryError::UnknownColumn(name.into()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Aggregate {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq, Ne, Lt, Le, Gt, Ge,
+    And, Or,
+    Like,
+    Add, Sub, Mul, Div,
+}
+
+impl Op {
+    fn symbol(&self)->&'static str {
+        match *self {
+            Op::Eq => "=", Op::Ne => "!=", Op::Lt => "<", Op::Le => "<=", Op::Gt => ">", Op::Ge => ">=",
+            Op::And => "AND", Op::Or => "OR",
+            Op::Like => "LIKE",
+            Op::Add => "+", Op::Sub => "-", Op::Mul => "*", Op::Div => "/",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Column(String),
+    Literal(Value),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    IsNull(Box<Expr>),
+    Binary(Box<Expr>, Op, Box<Expr>),
+    /// the argument is None for `count(*)`
+    Aggregate(Aggregate, Option<Box<Expr>>),
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter)->fmt::Result {
+        match *self {
+            Expr::Column(ref name) => write!(f, "{}", name),
+            Expr::Literal(Value::Str(ref s)) => write!(f, "'{}'", s.replace("'", "''")),
+            Expr::Literal(ref v) => write!(f, "{}", v),
+            Expr::Not(ref e) => write!(f, "NOT {}", e),
+            Expr::Neg(ref e) => write!(f, "-{}", e),
+            Expr::IsNull(ref e) => write!(f, "{} IS NULL", e),
+            Expr::Binary(ref a, op, ref b) => write!(f, "{} {} {}", a, op.symbol(), b),
+            Expr::Aggregate(agg, ref arg) => write!(
+                f,
+                "{}({})",
+                format!("{:?}", agg).to_lowercase(),
+                arg.as_ref().map_or("*".to_string(), |e| e.to_string())
+            ),
+        }
+    }
+}
+
+impl Expr {
+    fn has_aggregate(&self)->bool {
+        match *self {
+            Expr::Aggregate(..) => true,
+            Expr::Not(ref e) | Expr::Neg(ref e) | Expr::IsNull(ref e) => e.has_aggregate(),
+            Expr::Binary(ref a, _, ref b) => a.has_aggregate() || b.has_aggregate(),
+            _ => false,
+        }
+    }
+
+    /// Err if a column is referred outside aggregates without being grouped
+    fn check_grouped(&self, groups: &[Expr])->Result<(), QueryError> {
+        if groups.contains(self) {
+            return Ok(());
+        }
+        match *self {
+            Expr::Column(ref name) => Err(QueryError::Invalid(format!("{} must be in GROUP BY or an aggregate", name))),
+            Expr::Not(ref e) | Expr::Neg(ref e) | Expr::IsNull(ref e) => e.check_grouped(groups),
+            Expr::Binary(ref a, _, ref b) => a.check_grouped(groups).and(b.check_grouped(groups)),
+            Expr::Aggregate(_, Some(ref e)) if e.has_aggregate() =>
+                Err(QueryError::Invalid(format!("nested aggregate: {}", self))),
+            _ => Ok(()),
+        }
+    }
+
+    /// evaluate on the rows of a group. columns out of aggregates take the first row.
+    fn eval(&self, table: &Table, rows: &[&Vec<Value>])->Result<Value, QueryError> {
+        match *self {
+            Expr::Column(ref name) => {
+                let idx = table.index(name)?;
+                Ok(rows.first().map_or(Value::Null, |r| r[idx].clone()))
+            },
+            Expr::Literal(ref v) => Ok(v.clone()),
+            Expr::Not(ref e) => Ok(match e.eval(table, rows)? {
+                Value::Null => Value::Null,
+                v => Value::Bool(!v.is_true()),
+            }),
+            Expr::Neg(ref e) => match e.eval(table, rows)? {
+                Value::Int(n) => n.checked_neg()
+                    .map(Value::Int)
+                    .ok_or_else(|| QueryError::Invalid(format!("-({}) overflows", n))),
+                Value::Float(x) => Ok(Value::Float(-x)),
+                Value::Null => Ok(Value::Null),
+                v => Err(QueryError::Invalid(format!("cannot negate {}", v))),
+            },
+            Expr::IsNull(ref e) => Ok(Value::Bool(e.eval(table, rows)? == Value::Null)),
+            Expr::Binary(ref a, op, ref b) => binary(a.eval(table, rows)?, op, b.eval(table, rows)?),
+            Expr::Aggregate(agg, ref arg) => {
+                let values = match *arg {
+                    Some(ref e) => rows.iter()
+                        .map(|r| e.eval(table, &[r]))
+                        .collect::<Result<Vec<_>, _>>()?
+                        .into_iter()
+                        .filter(|v| *v != Value::Null)
+                        .collect(),
+                    None => vec![Value::Bool(true); rows.len()],
+                };
+                aggregate(agg, values)
+            },
+        }
+    }
+}
+
+/// AND and OR in three-valued logic; NULL AND false is false, NULL OR true is true, and otherwise NULL.
+fn logical(a: Value, op: Op, b: Value)->Value {
+    let truth = |v: &Value| if *v == Value::Null {None} else {Some(v.is_true())};
+    let (x, y) = (truth(&a), truth(&b));
+    // the value which decides the result by itself
+    let decisive = op == Op::Or;
+    if x == Some(decisive) || y == Some(decisive) {
+        Value::Bool(decisive)
+    } else if x.is_none() || y.is_none() {
+        Value::Null
+    } else {
+        Value::Bool(!decisive)
+    }
+}
+
+fn binary(a: Value, op: Op, b: Value)->Result<Value, QueryError> {
+    if op == Op::And || op == Op::Or {
+        return Ok(logical(a, op, b));
+    }
+    if a == Value::Null || b == Value::Null {
+        return Ok(Value::Null);
+    }
+    let ord = a.compare(&b);
+    match op {
+        Op::Eq => Ok(Value::Bool(ord == Ordering::Equal)),
+        Op::Ne => Ok(Value::Bool(ord != Ordering::Equal)),
+        Op::Lt => Ok(Value::Bool(ord == Ordering::Less)),
+        Op::Le => Ok(Value::Bool(ord != Ordering::Greater)),
+        Op::Gt => Ok(Value::Bool(ord == Ordering::Greater)),
+        Op::Ge => Ok(Value::Bool(ord != Ordering::Less)),
+        Op::Like => Ok(Value::Bool(like(&a.to_string(), &b.to_string()))),
+        _ => match (a, b) {
+            (Value::Int(x), Value::Int(y)) if op != Op::Div => match op {
+                Op::Add => x.checked_add(y),
+                Op::Sub => x.checked_sub(y),
+                _ => x.checked_mul(y),
+            }
+                .map(Value::Int)
+                .ok_or_else(|| QueryError::Invalid(format!("{} {} {} overflows", x, op.symbol(), y))),
+            (a, b) => match (a.as_f64(), b.as_f64()) {
+                (Some(x), Some(y)) => Ok(Value::Float(match op {
+                    Op::Add => x + y,
+                    Op::Sub => x - y,
+                    Op::Mul => x * y,
+                    _ => x / y,
+                })),
+                _ => Err(QueryError::Invalid(format!("{} {} {} is not a number", a, op.symbol(), b))),
+            },
+        },
+    }
+}
+
+/// `%` matches any characters and `_` matches a character.
+/// on mismatch, only the last `%` takes one more character, so it runs in O(len(s) * len(pattern)).
+fn like(s: &str, pattern: &str)->bool {
+    let (s, p) = (s.chars().collect::<Vec<_>>(), pattern.chars().collect::<Vec<_>>());
+    let (mut i, mut j) = (0, 0);
+    // positions in the pattern after the last `%`, and in `s` where it starts matching
+    let mut star = None;
+    while i < s.len() {
+        if j < p.len() && p[j] == '%' {
+            star = Some((j + 1, i));
+            j += 1;
+        } else if j < p.len() && (p[j] == '_' || p[j] == s[i]) {
+            i += 1;
+            j += 1;
+        } else if let Some((after, start)) = star {
+            star = Some((after, start + 1));
+            i = start + 1;
+            j = after;
+        } else {
+            return false;
+        }
+    }
+    p[j..].iter().all(|&c| c == '%')
+}
+
+/// aggregate non-null values. sum and avg of no values are Null, and count of them is 0.
+fn aggregate(agg: Aggregate, values: Vec<Value>)->Result<Value, QueryError> {
+    let numbers = || values.iter()
+        .map(|v| v.as_f64().ok_or_else(|| QueryError::Invalid(format!("{:?} of {} is not a number", agg, v))))
+        .collect::<Result<Vec<_>, _>>();
+    match agg {
+        Aggregate::Count => Ok(Value::Int(values.len() as i64)),
+        _ if values.is_empty() => Ok(Value::Null),
+        Aggregate::Sum if values.iter().all(|v| matches!(*v, Value::Int(_))) => values.iter()
+            .try_fold(0i64, |acc, v| if let Value::Int(n) = *v {acc.checked_add(n)} else {Some(acc)})
+            .map(Value::Int)
+            .ok_or_else(|| QueryError::Invalid(format!("{:?} overflows", agg))),
+        Aggregate::Sum => Ok(Value::Float(numbers()?.iter().sum())),
+        Aggregate::Avg => {
+            let ns = numbers()?;
+            Ok(Value::Float(ns.iter().sum::<f64>() / ns.len() as f64))
+        },
+        Aggregate::Min => Ok(values.into_iter().min_by(|a, b| a.compare(b)).unwrap()),
+        Aggregate::Max => Ok(values.into_iter().rev().max_by(|a, b| a.compare(b)).unwrap()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// name and whether it is quoted by `"`
+    Ident(String, bool),
+    Number(String),
+    Str(String),
+    Symbol(String),
+}
+
+fn tokenize(s: &str)->Result<Vec<Token>, QueryError> {
+    let chars = s.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '\'' || c == '"' {
+            // a doubled quote is the quote itself
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match (chars.get(i), chars.get(i + 1)) {
+                    (Some(&q), Some(&r)) if q == c && r == c => {
+                        s.push(c);
+                        i += 2;
+                    },
+                    (Some(&q), _) if q == c => break,
+                    (Some(&x), _) => {
+                        s.push(x);
+                        i += 1;
+                    },
+                    (None, _) => return Err(QueryError::Syntax(format!("unterminated quote: {}", s))),
+                }
+            }
+            i += 1;
+            tokens.push(if c == '\'' {Token::Str(s)} else {Token::Ident(s, true)});
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit())) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push(Token::Number(chars[start..i].iter().collect()));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect(), false));
+        } else {
+            let two = chars[i..].iter().take(2).collect::<String>();
+            let symbol = match two.as_str() {
+                "<=" | ">=" | "!=" | "<>" => two.clone(),
+                _ if "(),*=<>+-/".contains(c) => c.to_string(),
+                _ => return Err(QueryError::Syntax(format!("unexpected character: {}", c))),
+            };
+            i += symbol.chars().count();
+            tokens.push(Token::Symbol(symbol));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    /// `*`
+    All,
+    Expr(Expr, Option<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum OrderKey {
+    /// output column beginning with 1
+    Ordinal(usize),
+    Expr(Expr),
+}
+
+/// a parsed query
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    items: Vec<Item>,
+    from: String,
+    filter: Option<Expr>,
+    group_by: Vec<Expr>,
+    /// key and whether it is descending
+    order_by: Vec<(OrderKey, bool)>,
+    limit: Option<usize>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self)->Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn error<T>(&self, expected: &str)->Result<T, QueryError> {
+        Err(QueryError::Syntax(match self.peek() {
+            Some(t) => format!("expected {} but found {:?}", expected, t),
+            None => format!("expected {} but found the end", expected),
+        }))
+    }
+
+    /// consume the keyword if it comes next
+    fn keyword(&mut self, kw: &str)->bool {
+        let found = match self.peek() {
+            Some(&Token::Ident(ref s, false)) => s.eq_ignore_ascii_case(kw),
+            _ => false,
+        };
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, kw: &str)->Result<(), QueryError> {
+        if self.keyword(kw) {Ok(())} else {self.error(kw)}
+    }
+
+    fn symbol(&mut self, sym: &str)->bool {
+        let found = self.peek() == Some(&Token::Symbol(sym.into()));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, sym: &str)->Result<(), QueryError> {
+        if self.symbol(sym) {Ok(())} else {self.error(sym)}
+    }
+
+    fn ident(&mut self)->Result<String, QueryError> {
+        match self.peek().cloned() {
+            Some(Token::Ident(s, _)) => {
+                self.pos += 1;
+                Ok(s)
+            },
+            _ => self.error("identifier"),
+        }
+    }
+
+    fn number(&mut self)->Result<usize, QueryError> {
+        match self.peek().cloned() {
+            Some(Token::Number(ref s)) if s.parse::<usize>().is_ok() => {
+                self.pos += 1;
+                Ok(s.parse().unwrap())
+            },
+            _ => self.error("integer"),
+        }
+    }
+
+    fn query(&mut self)->Result<Query, QueryError> {
+        self.expect_keyword("SELECT")?;
+        let mut items = Vec::new();
+        loop {
+            if self.symbol("*") {
+                items.push(Item::All);
+            } else {
+                let expr = self.expr()?;
+                let alias = if self.keyword("AS") {Some(self.ident()?)} else {None};
+                items.push(Item::Expr(expr, alias));
+            }
+            if !self.symbol(",") {
+                break;
+            }
+        }
+
+        self.expect_keyword("FROM")?;
+        let from = self.ident()?;
+        let filter = if self.keyword("WHERE") {Some(self.expr()?)} else {None};
+
+        let mut group_by = Vec::new();
+        if self.keyword("GROUP") {
+            self.expect_keyword("BY")?;
+            loop {
+                group_by.push(self.expr()?);
+                if !self.symbol(",") {
+                    break;
+                }
+            }
+        }
+
+        let mut order_by = Vec::new();
+        if self.keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            loop {
+                let key = match self.peek().cloned() {
+                    Some(Token::Number(ref s)) if s.parse::<usize>().is_ok() => {
+                        self.pos += 1;
+                        OrderKey::Ordinal(s.parse().unwrap())
+                    },
+                    _ => OrderKey::Expr(self.expr()?),
+                };
+                let desc = if self.keyword("DESC") {true} else {
+                    self.keyword("ASC");
+                    false
+                };
+                order_by.push((key, desc));
+                if !self.symbol(",") {
+                    break;
+                }
+            }
+        }
+
+        let limit = if self.keyword("LIMIT") {Some(self.number()?)} else {None};
+        if self.peek().is_some() {
+            return self.error("the end");
+        }
+        Ok(Query {items, from, filter, group_by, order_by, limit})
+    }
+
+    fn expr(&mut self)->Result<Expr, QueryError> {
+        let mut lhs = self.and()?;
+        while self.keyword("OR") {
+            lhs = Expr::Binary(Box::new(lhs), Op::Or, Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self)->Result<Expr, QueryError> {
+        let mut lhs = self.not()?;
+        while self.keyword("AND") {
+            lhs = Expr::Binary(Box::new(lhs), Op::And, Box::new(self.not()?));
+        }
+        Ok(lhs)
+    }
+
+    fn not(&mut self)->Result<Expr, QueryError> {
+        if self.keyword("NOT") {
+            Ok(Expr::Not(Box::new(self.not()?)))
+        } else {
+            self.comparison()
+        }
+    }
+
+    fn comparison(&mut self)->Result<Expr, QueryError> {
+        let lhs = self.additive()?;
+        if self.keyword("IS") {
+            let negated = self.keyword("NOT");
+            self.expect_keyword("NULL")?;
+            let e = Expr::IsNull(Box::new(lhs));
+            return Ok(if negated {Expr::Not(Box::new(e))} else {e});
+        }
+        if self.keyword("NOT") {
+            self.expect_keyword("LIKE")?;
+            let e = Expr::Binary(Box::new(lhs), Op::Like, Box::new(self.additive()?));
+            return Ok(Expr::Not(Box::new(e)));
+        }
+        if self.keyword("LIKE") {
+            return Ok(Expr::Binary(Box::new(lhs), Op::Like, Box::new(self.additive()?)));
+        }
+        let ops = [("=", Op::Eq), ("!=", Op::Ne), ("<>", Op::Ne), ("<=", Op::Le), (">=", Op::Ge), ("<", Op::Lt), (">", Op::Gt)];
+        for &(sym, op) in ops.iter() {
+            if self.symbol(sym) {
+                return Ok(Expr::Binary(Box::new(lhs), op, Box::new(self.additive()?)));
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn additive(&mut self)->Result<Expr, QueryError> {
+        let mut lhs = self.multiplicative()?;
+        loop {
+            let op = if self.symbol("+") {Op::Add} else if self.symbol("-") {Op::Sub} else {break};
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.multiplicative()?));
+        }
+        Ok(lhs)
+    }
+
+    fn multiplicative(&mut self)->Result<Expr, QueryError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = if self.symbol("*") {Op::Mul} else if self.symbol("/") {Op::Div} else {break};
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self)->Result<Expr, QueryError> {
+        if self.symbol("-") {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self)->Result<Expr, QueryError> {
+        match self.peek().cloned() {
+            Some(Token::Number(s)) => {
+                self.pos += 1;
+                s.parse::<i64>().map(Value::Int)
+                    .or_else(|_| s.parse::<f64>().map(Value::Float))
+                    .map(Expr::Literal)
+                    .map_err(|_| QueryError::Syntax(format!("invalid number: {}", s)))
+            },
+            Some(Token::Str(s)) => {
+                self.pos += 1;
+                Ok(Expr::Literal(Value::Str(s)))
+            },
+            Some(Token::Symbol(ref s)) if s == "(" => {
+                self.pos += 1;
+                let e = self.expr()?;
+                self.expect_symbol(")")?;
+                Ok(e)
+            },
+            Some(Token::Ident(ref s, false)) if s.eq_ignore_ascii_case("NULL") => {
+                self.pos += 1;
+                Ok(Expr::Literal(Value::Null))
+            },
+            Some(Token::Ident(name, quoted)) => {
+                self.pos += 1;
+                if quoted || !self.symbol("(") {
+                    return Ok(Expr::Column(name));
+                }
+                let agg = match name.to_lowercase().as_str() {
+                    "count" => Aggregate::Count,
+                    "sum" => Aggregate::Sum,
+                    "avg" => Aggregate::Avg,
+                    "min" => Aggregate::Min,
+                    "max" => Aggregate::Max,
+                    _ => return Err(QueryError::Syntax(format!("unknown function: {}", name))),
+                };
+                let arg = if agg == Aggregate::Count && self.symbol("*") {None} else {Some(Box::new(self.expr()?))};
+                self.expect_symbol(")")?;
+                Ok(Expr::Aggregate(agg, arg))
+            },
+            _ => self.error("expression"),
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(s: &str)->Result<Query, QueryError> {
+        Parser {tokens: tokenize(s)?, pos: 0}.query()
+    }
+}
+
+/// result of a query
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl ResultSet {
+    /// render with the header
+    pub fn render(&self, format: Format)->String {
+        let rows = self.rows.iter()
+            .map(|r| r.iter().map(|v| v.to_string()).collect())
+            .collect::<Vec<_>>();
+        format::rows(Some(&self.columns), &rows, format)
+    }
+}
+
+impl Query {
+    /// execute on the table regardless of its name
+    pub fn execute(&self, table: &Table)->Result<ResultSet, QueryError> {
+        // columns and their names in the output
+        let mut exprs = Vec::new();
+        for item in &self.items {
+            match *item {
+                Item::All => exprs.extend(table.columns.iter().map(|c| (Expr::Column(c.name.clone()), c.name.clone()))),
+                Item::Expr(ref e, ref alias) => exprs.push((e.clone(), alias.clone().unwrap_or_else(|| e.to_string()))),
+            }
+        }
+
+        if let Some(ref f) = self.filter {
+            if f.has_aggregate() {
+                return Err(QueryError::Invalid(format!("aggregate in WHERE: {}", f)));
+            }
+        }
+        let mut rows = Vec::new();
+        for row in &table.rows {
+            let keep = match self.filter {
+                Some(ref f) => f.eval(table, &[row])?.is_true(),
+                None => true,
+            };
+            if keep {
+                rows.push(row);
+            }
+        }
+
+        // keys of ORDER BY resolved to expressions. output names are preferred to columns of the table.
+        let mut order = Vec::new();
+        for (key, desc) in &self.order_by {
+            let expr = match *key {
+                OrderKey::Ordinal(n) => exprs.get(n.wrapping_sub(1))
+                    .map(|(e, _)| e.clone())
+                    .ok_or_else(|| QueryError::Invalid(format!("ORDER BY {} is out of the columns", n)))?,
+                OrderKey::Expr(Expr::Column(ref name)) => exprs.iter()
+                    .find(|(_, out)| out == name)
+                    .map_or(Expr::Column(name.clone()), |(e, _)| e.clone()),
+                OrderKey::Expr(ref e) => e.clone(),
+            };
+            order.push((expr, *desc));
+        }
+
+        // a group is the rows in it. without GROUP BY, each row is a group unless aggregated.
+        let grouped = !self.group_by.is_empty() || exprs.iter().any(|(e, _)| e.has_aggregate());
+        let groups: Vec<Vec<&Vec<Value>>> = if grouped {
+            for e in exprs.iter().map(|(e, _)| e).chain(order.iter().map(|(e, _)| e)) {
+                e.check_grouped(&self.group_by)?;
+            }
+            let mut index = HashMap::new();
+            let mut groups = Vec::new();
+            for row in rows {
+                let key = self.group_by.iter()
+                    .map(|e| e.eval(table, &[row]))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let idx = *index.entry(format!("{:?}", key)).or_insert_with(|| {
+                    groups.push(Vec::new());
+                    groups.len() - 1
+                });
+                groups[idx].push(row);
+            }
+            // aggregates without GROUP BY make a row even if no rows match
+            if groups.is_empty() && self.group_by.is_empty() {
+                groups.push(Vec::new());
+            }
+            groups
+        } else {
+            rows.into_iter().map(|r| vec![r]).collect()
+        };
+
+        let mut results = Vec::new();
+        for group in &groups {
+            let values = exprs.iter()
+                .map(|(e, _)| e.eval(table, group))
+                .collect::<Result<Vec<_>, _>>()?;
+            let keys = order.iter()
+                .map(|(e, _)| e.eval(table, group))
+                .collect::<Result<Vec<_>, _>>()?;
+            results.push((keys, values));
+        }
+        // stable, so rows of equal keys keep the order of the table
+        results.sort_by(|a, b| {
+            a.0.iter()
+                .zip(b.0.iter())
+                .zip(order.iter())
+                .map(|((x, y), &(_, desc))| if desc {y.compare(x)} else {x.compare(y)})
+                .find(|&o| o != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+        });
+
+        Ok(ResultSet {
+            columns: exprs.into_iter().map(|(_, name)| name).collect(),
+            rows: results.into_iter()
+                .map(|(_, values)| values)
+                .take(self.limit.unwrap_or(usize::MAX))
+                .collect(),
+        })
+    }
+}
+
+/// tables queried by their names
+#[derive(Default)]
+pub struct Database {
+    tables: HashMap<String, Table>,
+}
+
+impl Database {
+    pub fn new()->Database {
+        Database::default()
+    }
+
+    pub fn register(mut self, table: Table)->Database {
+        self.tables.insert(table.name.clone(), table);
+        self
+    }
+
+    pub fn query(&self, sql: &str)->Result<ResultSet, QueryError> {
+        let query = sql.parse::<Query>()?;
+        let table = self.tables.get(&query.from)
+            .ok_or_else(|| QueryError::UnknownTable(query.from.clone()))?;
+        query.execute(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(rows: &[&[&str]])->Vec<Vec<String>> {
+        rows.iter().map(|r| r.iter().map(|s| s.to_string()).collect()).collect()
+    }
+
+    fn database()->Database {
+        let header = strings(&[&["pref", "region", "temp", "date", "rank"]]).remove(0);
+        let rows = strings(&[
+            &["高知県", "江川崎", "41", "2013-08-12", "1"],
+            &["埼玉県", "熊谷", "40.9", "2007-08-16", "2"],
+            &["岐阜県", "多治見", "40.9", "2007-08-16", "3"],
+            &["山形県", "山形", "40.8", "1933-07-25", "4"],
+            &["埼玉県", "越谷", "40.4", "2007-08-16", ""],
+        ]);
+        Database::new().register(Table::from_strings("hightemp", &header, &rows))
+    }
+
+    fn tsv(sql: &str)->String {
+        database().query(sql).unwrap().render(Format::Tsv)
+    }
+
+    #[test]
+    fn test_infer() {
+        let db = database();
+        let table = &db.tables["hightemp"];
+        assert_eq!(
+            table.columns.iter().map(|c| c.kind).collect::<Vec<_>>(),
+            vec![ColumnType::Str, ColumnType::Str, ColumnType::Float, ColumnType::Date, ColumnType::Int]
+        );
+        assert_eq!(table.rows[4][4], Value::Null);
+
+        let table = table.clone().declare("rank", ColumnType::Str).unwrap();
+        assert_eq!(table.rows[0][4], Value::Str("1".into()));
+        assert!(table.clone().declare("pref", ColumnType::Int).is_err());
+        assert!(table.declare("no_such", ColumnType::Int).is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        assert_eq!(
+            tsv("SELECT region, temp FROM hightemp WHERE temp >= 40.9 AND date > '2010-01-01'"),
+            "region\ttemp\n江川崎\t41"
+        );
+        assert_eq!(
+            tsv("select * from hightemp where pref like '%玉%' order by temp limit 1"),
+            "pref\tregion\ttemp\tdate\trank\n埼玉県\t越谷\t40.4\t2007-08-16\t"
+        );
+        assert_eq!(
+            tsv("SELECT region, rank * 10 AS r FROM hightemp WHERE rank IS NOT NULL ORDER BY r DESC LIMIT 2"),
+            "region\tr\n山形\t40\n多治見\t30"
+        );
+        // stable for equal keys
+        assert_eq!(
+            tsv("SELECT region FROM hightemp WHERE NOT pref = '高知県' ORDER BY date DESC, \"temp\""),
+            "region\n越谷\n熊谷\n多治見\n山形"
+        );
+    }
+
+    #[test]
+    fn test_null_logic() {
+        // rank of 越谷 is NULL, so the condition is NULL and the row is dropped even with NOT
+        assert_eq!(tsv("SELECT region FROM hightemp WHERE NOT (rank > 2 AND temp > 40)"), "region\n江川崎\n熊谷");
+        assert_eq!(tsv("SELECT region FROM hightemp WHERE NOT rank > 2"), "region\n江川崎\n熊谷");
+        // NULL AND false is false, and NULL OR true is true
+        assert_eq!(tsv("SELECT region FROM hightemp WHERE NOT (rank > 2 AND temp > 40.5)"), "region\n江川崎\n熊谷\n越谷");
+        assert_eq!(tsv("SELECT region FROM hightemp WHERE rank > 2 OR temp < 40.5"), "region\n多治見\n山形\n越谷");
+        assert_eq!(tsv("SELECT count(*) FROM hightemp WHERE rank > 2 OR temp > 41"), "count(*)\n2");
+    }
+
+    #[test]
+    fn test_like() {
+        assert!(like("埼玉県", "%玉%"));
+        assert!(like("埼玉県", "埼_県"));
+        assert!(like("", "%"));
+        assert!(like("abcab", "%ab"));
+        assert!(!like("abcab", "%ac%"));
+        assert!(!like("埼玉県", "_県"));
+        // many `%` on a long text don't backtrack exponentially
+        assert!(!like(&"a".repeat(10000), "%a%a%a%a%a%a%a%b"));
+        assert!(like(&format!("{}b", "a".repeat(10000)), "%a%a%a%a%a%a%a%b"));
+    }
+
+    #[test]
+    fn test_group_by() {
+        assert_eq!(
+            tsv("SELECT pref, max(temp), count(*) FROM hightemp GROUP BY pref ORDER BY 2 DESC, pref"),
+            "pref\tmax(temp)\tcount(*)\n高知県\t41\t1\n埼玉県\t40.9\t2\n岐阜県\t40.9\t1\n山形県\t40.8\t1"
+        );
+        assert_eq!(
+            tsv("SELECT count(rank) AS n, sum(rank), avg(rank), min(date) FROM hightemp"),
+            "n\tsum(rank)\tavg(rank)\tmin(date)\n4\t10\t2.5\t1933-07-25"
+        );
+        assert_eq!(tsv("SELECT count(*), sum(temp) FROM hightemp WHERE temp > 50"), "count(*)\tsum(temp)\n0\t");
+    }
+
+    #[test]
+    fn test_errors() {
+        let db = database();
+        let err = |sql: &str| db.query(sql).unwrap_err();
+        assert!(matches!(err("SELECT FROM hightemp"), QueryError::Syntax(_)));
+        assert!(matches!(err("SELECT pref FROM hightemp LIMIT"), QueryError::Syntax(_)));
+        assert!(matches!(err("SELECT 'a FROM hightemp"), QueryError::Syntax(_)));
+        assert!(matches!(err("SELECT pref FROM lowtemp"), QueryError::UnknownTable(_)));
+        assert!(matches!(err("SELECT city FROM hightemp"), QueryError::UnknownColumn(_)));
+        assert!(matches!(err("SELECT region, max(temp) FROM hightemp GROUP BY pref"), QueryError::Invalid(_)));
+        assert!(matches!(err("SELECT pref FROM hightemp WHERE max(temp) > 1"), QueryError::Invalid(_)));
+        assert!(matches!(err("SELECT pref FROM hightemp ORDER BY 3"), QueryError::Invalid(_)));
+
+        // integer overflow
+        assert!(matches!(err("SELECT rank * 9223372036854775807 FROM hightemp"), QueryError::Invalid(_)));
+        assert!(matches!(err("SELECT 9223372036854775807 + 1 FROM hightemp"), QueryError::Invalid(_)));
+        assert!(matches!(err("SELECT -(-9223372036854775807 - 1) FROM hightemp"), QueryError::Invalid(_)));
+        assert!(matches!(err("SELECT sum(rank * 1000000000000000000) FROM hightemp"), QueryError::Invalid(_)));
+    }
+
+    #[test]
+    fn test_from_records() {
+        let table = Table::from_records("t", &[("高知県", 41.0), ("埼玉県", 40.9)]);
+        assert_eq!(table.columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["c1", "c2"]);
+        assert_eq!(table.rows.len(), 2);
+        let db = Database::new().register(table);
+        assert_eq!(db.query("SELECT c1 FROM t WHERE c2 > 40.95").unwrap().render(Format::Tsv), "c1\n高知県");
+
+        #[derive(Serialize)]
+        struct Row {
+            pref: String,
+            temp: f32,
+        }
+        let table = Table::from_records("t", &[Row {pref: "高知県".into(), temp: 41.0}]);
+        assert_eq!(table.columns[1].name, "temp");
+        assert_eq!(table.rows.len(), 1);
+        assert!(Table::from_records::<Row>("t", &[]).columns.is_empty());
+    }
+}
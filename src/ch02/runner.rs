@@ -0,0 +1,588 @@
+//! execution of external commands behind `Runner`, so that `Commander` can be tested
+//! with recorded outputs instead of the real commands.
+extern crate serde_json;
+
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io::{self, Read, Write, Result as ioResult};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::rw;
+use super::pipeline::Pipeline;
+
+/// a command line with environment variables and input
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<OsString>,
+    pub envs: Vec<(String, String)>,
+    pub stdin: Option<Vec<u8>>,
+}
+
+impl Invocation {
+    pub fn new(program: &str)->Invocation {
+        Invocation {program: program.into(), args: Vec::new(), envs: Vec::new(), stdin: None}
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S)->Invocation {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    pub fn args<I: IntoIterator<Item=S>, S: AsRef<OsStr>>(mut self, args: I)->Invocation {
+        self.args.extend(args.into_iter().map(|s| s.as_ref().to_os_string()));
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str)->Invocation {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn stdin<B: Into<Vec<u8>>>(mut self, bytes: B)->Invocation {
+        self.stdin = Some(bytes.into());
+        self
+    }
+}
+
+/// like shell, e.g. `LC_ALL=C sort -t '\t' -k1,1`. non UTF-8 arguments are shown lossily.
+impl fmt::Display for Invocation {
+    fn fmt(&self, f: &mut fmt::Formatter)->fmt::Result {
+        let quote = |s: &str| if !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || "-_.,/:=+%".contains(c)) {
+            s.to_string()
+        } else {
+            format!("'{}'", s.replace("'", "'\\''").replace("\t", "\\t"))
+        };
+        let words = self.envs.iter()
+            .map(|&(ref k, ref v)| format!("{}={}", k, quote(v)))
+            .chain(::std::iter::once(quote(&self.program)))
+            .chain(self.args.iter().map(|a| quote(&a.to_string_lossy())))
+            .collect::<Vec<_>>();
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+/// result of a finished command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Outcome {
+    /// None if killed by a signal
+    pub code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Outcome {
+    pub fn success(&self)->bool {
+        self.code == Some(0)
+    }
+
+    pub fn stdout_lossy(&self)->String {
+        String::from_utf8_lossy(&self.stdout).into_owned()
+    }
+
+    /// Err if the command exits with failure
+    pub fn check(self, invocation: &Invocation)->Result<Outcome, RunError> {
+        self.check_command(invocation.to_string())
+    }
+
+    /// Err if any command of the pipeline exits with failure, like `set -o pipefail`
+    pub fn check_pipeline(self, invocations: &[Invocation])->Result<Outcome, RunError> {
+        self.check_command(pipeline_command(invocations))
+    }
+
+    fn check_command(self, command: String)->Result<Outcome, RunError> {
+        if self.success() {
+            Ok(self)
+        } else {
+            Err(RunError::Failed {
+                command,
+                code: self.code,
+                stderr: String::from_utf8_lossy(&self.stderr).trim().to_string(),
+            })
+        }
+    }
+}
+
+/// like shell, e.g. `cut -f 1 a.txt | LC_ALL=C sort`
+pub fn pipeline_command(invocations: &[Invocation])->String {
+    invocations.iter()
+        .map(|inv| inv.to_string())
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// run commands one by one, passing the whole stdout of each to the next.
+/// the first failed command stops the pipeline.
+pub fn run_in_sequence(runner: &dyn Runner, invocations: &[Invocation])->Result<Outcome, RunError> {
+    let mut stdin = invocations.first().and_then(|inv| inv.stdin.clone());
+    let mut last = Outcome {code: Some(0), stdout: Vec::new(), stderr: Vec::new()};
+    for invocation in invocations {
+        let invocation = Invocation {stdin: stdin.take(), ..invocation.clone()};
+        last = runner.run(&invocation)?;
+        if !last.success() {
+            break;
+        }
+        stdin = Some(last.stdout.clone());
+    }
+    Ok(last)
+}
+
+#[derive(Debug)]
+pub enum RunError {
+    /// the program cannot be executed
+    Spawn(String, io::Error),
+    Timeout(String, Duration),
+    Failed {command: String, code: Option<i32>, stderr: String},
+    /// no record for the invocation in `Replay`
+    NotRecorded(String),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter)->fmt::Result {
+        match *self {
+            RunError::Spawn(ref command, ref e) => write!(f, "fail to execute `{}`: {}", command, e),
+            RunError::Timeout(ref command, after) => write!(f, "`{}` timed out after {:?}", command, after),
+            RunError::Failed {ref command, code, ref stderr} =>
+                write!(f, "`{}` failed (code: {:?}): {}", command, code, stderr),
+            RunError::NotRecorded(ref command) => write!(f, "`{}` is not recorded", command),
+        }
+    }
+}
+
+impl ::std::error::Error for RunError {}
+
+pub trait Runner: Send + Sync {
+    /// run the command to the end, and capture its outputs whether it succeeds or not.
+    fn run(&self, invocation: &Invocation)->Result<Outcome, RunError>;
+
+    /// run commands connecting stdout to stdin of the next, like `cmd1 | cmd2`.
+    /// stdin of the first invocation is the input, and that of others is ignored.
+    /// the code is of the first failed command, and stderr is of all commands.
+    fn run_pipeline(&self, invocations: &[Invocation])->Result<Outcome, RunError>;
+}
+
+/// runs real processes
+#[derive(Debug, Clone, Default)]
+pub struct SystemRunner {
+    timeout: Option<Duration>,
+}
+
+impl SystemRunner {
+    pub fn new()->SystemRunner {
+        SystemRunner::default()
+    }
+
+    /// kill the process if it doesn't finish in time. no limit by default.
+    pub fn timeout(mut self, timeout: Duration)->SystemRunner {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+/// read all in another thread not to block the process by a full pipe.
+fn drain<R: Read + Send + 'static>(mut r: R)->thread::JoinHandle<ioResult<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf)?;
+        Ok(buf)
+    })
+}
+
+impl Runner for SystemRunner {
+    fn run(&self, invocation: &Invocation)->Result<Outcome, RunError> {
+        let command = invocation.to_string();
+        let io_error = |e: io::Error| RunError::Spawn(command.clone(), e);
+
+        let mut child = Command::new(&invocation.program)
+            .args(&invocation.args)
+            .envs(invocation.envs.iter().map(|&(ref k, ref v)| (k, v)))
+            .stdin(if invocation.stdin.is_some() {Stdio::piped()} else {Stdio::null()})
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(&io_error)?;
+
+        let feeder = child.stdin.take().map(|mut stdin| {
+            let bytes = invocation.stdin.clone().unwrap_or_default();
+            thread::spawn(move || {
+                // the command may exit without reading all, like `head`
+                let _ = stdin.write_all(&bytes);
+            })
+        });
+        let stdout = drain(child.stdout.take().unwrap());
+        let stderr = drain(child.stderr.take().unwrap());
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(&io_error)? {
+                break status;
+            }
+            if let Some(timeout) = self.timeout {
+                if started.elapsed() > timeout {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(RunError::Timeout(command, timeout));
+                }
+            }
+            thread::sleep(Duration::from_millis(5));
+        };
+
+        if let Some(feeder) = feeder {
+            let _ = feeder.join();
+        }
+        let join = |h: thread::JoinHandle<ioResult<Vec<u8>>>|
+            h.join().unwrap_or_else(|_| Ok(Vec::new())).map_err(&io_error);
+        Ok(Outcome {code: status.code(), stdout: join(stdout)?, stderr: join(stderr)?})
+    }
+
+    /// stream through `Pipeline`, so intermediate outputs are not buffered.
+    /// the timeout is applied to the whole pipeline, and all commands are killed when it expires.
+    fn run_pipeline(&self, invocations: &[Invocation])->Result<Outcome, RunError> {
+        let command = pipeline_command(invocations);
+        let stdin = invocations.first().and_then(|inv| inv.stdin.clone()).unwrap_or_default();
+        let pipeline = invocations.iter()
+            .fold(Pipeline::from_bytes(stdin), |pipeline, inv| pipeline.invocation(inv));
+        let pipeline = match self.timeout {
+            Some(timeout) => pipeline.timeout(timeout),
+            None => pipeline,
+        };
+        let output = pipeline.run()
+            .map_err(|e| match (e.kind(), self.timeout) {
+                (io::ErrorKind::TimedOut, Some(timeout)) => RunError::Timeout(command.clone(), timeout),
+                _ => RunError::Spawn(command.clone(), e),
+            })?;
+
+        let code = output.statuses.iter()
+            .find(|s| !s.success)
+            .map_or(Some(0), |s| s.code);
+        let stderr = output.statuses.iter()
+            .flat_map(|s| s.error.bytes())
+            .collect();
+        Ok(Outcome {code, stdout: output.stdout, stderr})
+    }
+}
+
+/// an invocation and its outcome, saved in JSON Lines
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Record {
+    command: String,
+    stdin: Option<Vec<u8>>,
+    outcome: Outcome,
+}
+
+impl Record {
+    fn matches(&self, invocation: &Invocation)->bool {
+        self.command == invocation.to_string() && self.stdin == invocation.stdin
+    }
+
+    /// a pipeline is recorded as a whole with stdin of the first command
+    fn matches_pipeline(&self, invocations: &[Invocation])->bool {
+        self.command == pipeline_command(invocations)
+            && self.stdin == invocations.first().and_then(|inv| inv.stdin.clone())
+    }
+}
+
+/// records outcomes of the inner runner to replay them later
+pub struct Recorder<R> {
+    inner: R,
+    records: Mutex<Vec<Record>>,
+}
+
+impl<R: Runner> Recorder<R> {
+    pub fn new(inner: R)->Recorder<R> {
+        Recorder {inner, records: Mutex::new(Vec::new())}
+    }
+
+    /// save records to be loaded by `Replay::load`
+    pub fn save<P: AsRef<Path>>(&self, save_path: P)->ioResult<()> {
+        let lines = self.records.lock().unwrap()
+            .iter()
+            .map(|r| serde_json::to_string(r).unwrap())
+            .collect::<Vec<_>>();
+        rw::write_lines(&lines, save_path)
+    }
+}
+
+impl<R: Runner> Runner for Recorder<R> {
+    fn run(&self, invocation: &Invocation)->Result<Outcome, RunError> {
+        let outcome = self.inner.run(invocation)?;
+        self.records.lock().unwrap().push(Record {
+            command: invocation.to_string(),
+            stdin: invocation.stdin.clone(),
+            outcome: outcome.clone(),
+        });
+        Ok(outcome)
+    }
+
+    fn run_pipeline(&self, invocations: &[Invocation])->Result<Outcome, RunError> {
+        let outcome = self.inner.run_pipeline(invocations)?;
+        self.records.lock().unwrap().push(Record {
+            command: pipeline_command(invocations),
+            stdin: invocations.first().and_then(|inv| inv.stdin.clone()),
+            outcome: outcome.clone(),
+        });
+        Ok(outcome)
+    }
+}
+
+/// returns recorded outcomes without executing any process
+pub struct Replay {
+    records: Vec<Record>,
+}
+
+impl Replay {
+    pub fn new()->Replay {
+        Replay {records: Vec::new()}
+    }
+
+    pub fn load<P: AsRef<Path>>(load_path: P)->ioResult<Replay> {
+        let records = rw::read_lines(load_path)?
+            .iter()
+            .map(|line| serde_json::from_str(line))
+            .collect::<Result<Vec<Record>, _>>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Replay {records})
+    }
+
+    /// add the outcome of the invocation
+    pub fn with(mut self, invocation: &Invocation, outcome: Outcome)->Replay {
+        self.records.push(Record {command: invocation.to_string(), stdin: invocation.stdin.clone(), outcome});
+        self
+    }
+
+    /// add the outcome of the whole pipeline
+    pub fn with_pipeline(mut self, invocations: &[Invocation], outcome: Outcome)->Replay {
+        self.records.push(Record {
+            command: pipeline_command(invocations),
+            stdin: invocations.first().and_then(|inv| inv.stdin.clone()),
+            outcome,
+        });
+        self
+    }
+}
+
+impl Runner for Replay {
+    fn run(&self, invocation: &Invocation)->Result<Outcome, RunError> {
+        self.records.iter()
+            .find(|r| r.matches(invocation))
+            .map(|r| r.outcome.clone())
+            .ok_or_else(|| RunError::NotRecorded(invocation.to_string()))
+    }
+
+    /// the outcome of the whole pipeline, or else of each command fed with the recorded stdout
+    fn run_pipeline(&self, invocations: &[Invocation])->Result<Outcome, RunError> {
+        match self.records.iter().find(|r| r.matches_pipeline(invocations)) {
+            Some(r) => Ok(r.outcome.clone()),
+            None => run_in_sequence(self, invocations),
+        }
+    }
+}
+
+/// GNU long options and their BSD equivalents by program. `=` means the option takes a value.
+const LONG_OPTIONS: &[(&str, &str, &str)] = &[
+    ("cut", "--fields=", "-f"),
+    ("cut", "--delimiter=", "-d"),
+    ("grep", "--count", "-c"),
+    ("grep", "--invert-match", "-v"),
+    ("grep", "--extended-regexp", "-E"),
+    ("grep", "--fixed-strings", "-F"),
+    ("grep", "--before-context=", "-B"),
+    ("grep", "--after-context=", "-A"),
+    ("head", "--lines=", "-n"),
+    ("join", "--ignore-case", "-i"),
+    ("paste", "--delimiters=", "-d"),
+    ("paste", "--serial", "-s"),
+    ("sort", "--reverse", "-r"),
+    ("sort", "--stable", "-s"),
+    ("sort", "--unique", "-u"),
+    ("sort", "--numeric-sort", "-n"),
+    ("sort", "--month-sort", "-M"),
+    ("sort", "--key=", "-k"),
+    ("sort", "--field-separator=", "-t"),
+    ("split", "--lines=", "-l"),
+    ("tail", "--lines=", "-n"),
+    ("tail", "--bytes=", "-c"),
+    ("uniq", "--count", "-c"),
+    ("wc", "--lines", "-l"),
+];
+
+/// options of GNU only without BSD equivalents, e.g. `join -o auto`
+const GNU_ONLY: &[(&str, &str, &str)] = &[
+    ("join", "-o", "auto"),
+];
+
+/// variants of command line tools. options are chosen by them to compare outputs fairly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flavor {
+    /// GNU coreutils
+    Gnu,
+    /// BSD userland such as macOS
+    Bsd,
+}
+
+impl Flavor {
+    /// flavor of the platform
+    pub fn platform()->Flavor {
+        if cfg!(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly")) {
+            Flavor::Bsd
+        } else {
+            Flavor::Gnu
+        }
+    }
+
+    /// width of counts in `uniq -c`
+    pub fn uniq_count_width(&self)->usize {
+        match *self {
+            Flavor::Gnu => 7,
+            Flavor::Bsd => 4,
+        }
+    }
+
+    /// options of the program in the flavor, given in GNU style.
+    /// BSD has short options instead of long ones, and Err if it has no equivalent.
+    pub fn args(&self, program: &str, args: &[&str])->Result<Vec<String>, String> {
+        let unsupported = |arg: &str| format!("`{} {}` is not supported in {:?}", program, arg, self);
+        if *self == Flavor::Gnu {
+            return Ok(args.iter().map(|s| s.to_string()).collect());
+        }
+        let mut res = Vec::new();
+        for (idx, &arg) in args.iter().enumerate() {
+            let prev = if idx > 0 {args[idx - 1]} else {""};
+            if GNU_ONLY.iter().any(|&(p, opt, value)| p == program && opt == prev && value == arg) {
+                return Err(unsupported(&format!("{} {}", prev, arg)));
+            }
+            if !arg.starts_with("--") || arg == "--" {
+                res.push(arg.to_string());
+                continue;
+            }
+            let (name, value) = match arg.find('=') {
+                Some(pos) => (&arg[..pos + 1], Some(&arg[pos + 1..])),
+                None => (arg, None),
+            };
+            let short = LONG_OPTIONS.iter()
+                .find(|&&(p, long, _)| p == program && long == name)
+                .map(|&(_, _, short)| short)
+                .ok_or_else(|| unsupported(arg))?;
+            res.push(short.to_string());
+            res.extend(value.map(|v| v.to_string()));
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let inv = Invocation::new("sort").env("LC_ALL", "C").args(&["-t", "\t", "-k1,1"]).arg("a b.txt");
+        assert_eq!(inv.to_string(), "LC_ALL=C sort -t '\\t' -k1,1 'a b.txt'");
+    }
+
+    #[test]
+    fn test_system_runner() {
+        let runner = SystemRunner::new();
+        let outcome = runner.run(&Invocation::new("tr").args(&["a-z", "A-Z"]).stdin("abc\n")).unwrap();
+        assert!(outcome.success());
+        assert_eq!(outcome.stdout, b"ABC\n");
+
+        let inv = Invocation::new("cut").args(&["-f", "1", "./data/ch02/no_such_file.txt"]);
+        let outcome = runner.run(&inv).unwrap();
+        assert_eq!(outcome.code, Some(1));
+        match outcome.check(&inv) {
+            Err(RunError::Failed {stderr, ..}) => assert!(stderr.contains("no_such_file.txt")),
+            res => panic!("unexpected {:?}", res),
+        }
+
+        match runner.run(&Invocation::new("no-such-command")) {
+            Err(RunError::Spawn(..)) => (),
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        let runner = SystemRunner::new().timeout(Duration::from_millis(100));
+        let started = Instant::now();
+        match runner.run(&Invocation::new("sleep").arg("5")) {
+            Err(RunError::Timeout(..)) => (),
+            res => panic!("unexpected {:?}", res),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_pipeline_timeout() {
+        let runner = SystemRunner::new().timeout(Duration::from_millis(100));
+        let started = Instant::now();
+        match runner.run_pipeline(&[Invocation::new("cat").stdin("a\n"), Invocation::new("sleep").arg("5")]) {
+            Err(RunError::Timeout(command, _)) => assert_eq!(command, "cat | sleep 5"),
+            res => panic!("unexpected {:?}", res),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_flavor() {
+        let args = ["--reverse", "--key=3,3n", "-t", "\t"];
+        assert_eq!(Flavor::Gnu.args("sort", &args).unwrap(), args.to_vec());
+        assert_eq!(Flavor::Bsd.args("sort", &args).unwrap(), vec!["-r", "-k", "3,3n", "-t", "\t"]);
+        assert_eq!(Flavor::Bsd.args("cut", &["-f", "1", "--", "-x"]).unwrap(), vec!["-f", "1", "--", "-x"]);
+        assert!(Flavor::Bsd.args("cut", &["--complement", "-f", "2"]).is_err());
+        assert!(Flavor::Bsd.args("cut", &["--output-delimiter=,"]).is_err());
+        assert!(Flavor::Bsd.args("join", &["-a1", "-o", "auto"]).is_err());
+        assert!(Flavor::Gnu.args("join", &["-a1", "-o", "auto"]).is_ok());
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let path = "./data/ch02/runner_records.jsonl";
+        let inv = Invocation::new("head").args(&["-n", "1", "./data/ch02/hightemp.txt"]);
+
+        let recorder = Recorder::new(SystemRunner::new());
+        let outcome = recorder.run(&inv).unwrap();
+        recorder.save(path).unwrap();
+
+        let replay = Replay::load(path).unwrap();
+        assert_eq!(replay.run(&inv).unwrap(), outcome);
+        match replay.run(&Invocation::new("head").args(&["-n", "2", "./data/ch02/hightemp.txt"])) {
+            Err(RunError::NotRecorded(command)) => assert_eq!(command, "head -n 2 ./data/ch02/hightemp.txt"),
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let path = "./data/ch02/runner_pipeline.jsonl";
+        let pipeline = [
+            Invocation::new("tr").args(&["a-z", "A-Z"]).stdin("b\na\nb\n"),
+            Invocation::new("sort").env("LC_ALL", "C"),
+            Invocation::new("uniq").arg("-c"),
+        ];
+
+        let recorder = Recorder::new(SystemRunner::new());
+        let outcome = recorder.run_pipeline(&pipeline).unwrap();
+        assert_eq!(outcome.stdout_lossy().split_whitespace().collect::<Vec<_>>(), vec!["1", "A", "2", "B"]);
+        recorder.save(path).unwrap();
+        assert_eq!(Replay::load(path).unwrap().run_pipeline(&pipeline).unwrap(), outcome);
+
+        // stdout is streamed, so the input larger than pipe buffers does not block
+        let input = (0..200000).map(|i| format!("{}\n", i % 1000)).collect::<String>();
+        let outcome = SystemRunner::new()
+            .run_pipeline(&[Invocation::new("cat").stdin(input), Invocation::new("sort").args(&["-n", "-u"])])
+            .unwrap();
+        assert_eq!(outcome.stdout.iter().filter(|&&b| b == b'\n').count(), 1000);
+
+        let pipeline = [Invocation::new("sort").arg("--no-such-option").stdin("a\n"), Invocation::new("cat")];
+        let outcome = SystemRunner::new().run_pipeline(&pipeline).unwrap();
+        match outcome.check_pipeline(&pipeline) {
+            Err(RunError::Failed {command, code, ..}) => assert_eq!((command.as_str(), code), ("sort --no-such-option | cat", Some(2))),
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+}
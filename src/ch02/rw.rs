@@ -1,32 +1,618 @@
-use std::io::{BufReader, BufRead, Read, BufWriter, Write};
-use std::fs::File;
+//! read and write files. input in legacy Japanese encodings is decoded to UTF-8,
+//! and gzip, bzip2 and xz files are decompressed transparently.
+extern crate encoding_rs;
+extern crate flate2;
+extern crate bzip2;
+extern crate xz2;
+
+use std::io::{BufReader, BufRead, BufWriter, Read, Write};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, Result as ioResult};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use self::encoding_rs::{Encoding, SHIFT_JIS, EUC_JP, ISO_2022_JP, UTF_16LE, UTF_16BE};
+use self::flate2::bufread::MultiGzDecoder;
+use self::flate2::write::GzEncoder;
+use self::bzip2::bufread::MultiBzDecoder;
+use self::bzip2::write::BzEncoder;
+use self::xz2::bufread::XzDecoder;
+use self::xz2::write::XzEncoder;
 
-pub fn read<P: AsRef<Path>>(load_path: P)-> ioResult<String> {
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+
+/// character encoding of a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Charset {
+    Utf8,
+    /// UTF-8 with byte order mark
+    Utf8Bom,
+    /// CP932 (Windows-31J), which is the superset of Shift_JIS
+    ShiftJis,
+    EucJp,
+    Iso2022Jp,
+    /// written with byte order mark
+    Utf16Le,
+    /// written with byte order mark
+    Utf16Be,
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    fn from_str(s: &str)->Result<Charset, String> {
+        match s.to_lowercase().replace("_", "-").as_str() {
+            "utf-8" | "utf8" => Ok(Charset::Utf8),
+            "utf-8-bom" | "utf-8-sig" => Ok(Charset::Utf8Bom),
+            "shift-jis" | "sjis" | "cp932" | "ms932" | "windows-31j" => Ok(Charset::ShiftJis),
+            "euc-jp" | "eucjp" => Ok(Charset::EucJp),
+            "iso-2022-jp" | "jis" => Ok(Charset::Iso2022Jp),
+            "utf-16le" | "utf-16" => Ok(Charset::Utf16Le),
+            "utf-16be" => Ok(Charset::Utf16Be),
+            _ => Err(format!("unknown charset: {}", s)),
+        }
+    }
+}
+
+impl Charset {
+    fn encoding(&self)->Option<&'static Encoding> {
+        match *self {
+            Charset::Utf8 | Charset::Utf8Bom => None,
+            Charset::ShiftJis => Some(SHIFT_JIS),
+            Charset::EucJp => Some(EUC_JP),
+            Charset::Iso2022Jp => Some(ISO_2022_JP),
+            Charset::Utf16Le => Some(UTF_16LE),
+            Charset::Utf16Be => Some(UTF_16BE),
+        }
+    }
+
+    fn bom(&self)->&'static [u8] {
+        match *self {
+            Charset::Utf8Bom => UTF8_BOM,
+            Charset::Utf16Le => UTF16LE_BOM,
+            Charset::Utf16Be => UTF16BE_BOM,
+            _ => b"",
+        }
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn Error + Send + Sync>>>(e: E)->io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// UTF-16 without BOM is guessed by NUL bytes of ASCII characters.
+fn guess_utf16(bytes: &[u8])->Option<Charset> {
+    let pairs = bytes.len() / 2;
+    if pairs == 0 || bytes.len() % 2 == 1 {
+        return None;
+    }
+    let even = bytes.iter().step_by(2).filter(|&&b| b == 0).count();
+    let odd = bytes.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+    if even == 0 && odd * 4 >= pairs {
+        Some(Charset::Utf16Le)
+    } else if odd == 0 && even * 4 >= pairs {
+        Some(Charset::Utf16Be)
+    } else {
+        None
+    }
+}
+
+/// likelihood of Japanese text; kana and kanji count up, and half-width kana counts down
+/// because bytes of EUC-JP often look like half-width kana in Shift_JIS.
+fn japanese_score(s: &str)->i64 {
+    s.chars()
+        .map(|c| match c {
+            '\u{3040}'..='\u{30FF}' | '\u{4E00}'..='\u{9FFF}' => 1,
+            '\u{FF61}'..='\u{FF9F}' => -1,
+            _ => 0,
+        })
+        .sum()
+}
 
-    let mut reader = BufReader::new(File::open(load_path.as_ref()).unwrap());
-    let mut buf = String::new();
-    let _ = reader.read_to_string(&mut buf)?;
+/// detect the encoding by BOM, escape sequences and which decoding looks like Japanese.
+pub fn detect(bytes: &[u8])->Charset {
+    if bytes.starts_with(UTF8_BOM) {
+        return Charset::Utf8Bom;
+    } else if bytes.starts_with(UTF16LE_BOM) {
+        return Charset::Utf16Le;
+    } else if bytes.starts_with(UTF16BE_BOM) {
+        return Charset::Utf16Be;
+    } else if let Some(charset) = guess_utf16(bytes) {
+        return charset;
+    }
+
+    if ::std::str::from_utf8(bytes).is_ok() {
+        // ISO-2022-JP is 7-bit, so it is also valid as UTF-8.
+        let escaped = bytes.windows(3).any(|w| w == b"\x1B$B" || w == b"\x1B$@" || w == b"\x1B(J");
+        return if escaped {Charset::Iso2022Jp} else {Charset::Utf8};
+    }
+
+    let score = |encoding: &'static Encoding|
+        encoding.decode_without_bom_handling_and_without_replacement(bytes)
+            .map(|s| japanese_score(&s));
+    match (score(SHIFT_JIS), score(EUC_JP)) {
+        (Some(sjis), Some(euc)) => if euc > sjis {Charset::EucJp} else {Charset::ShiftJis},
+        (None, Some(_)) => Charset::EucJp,
+        _ => Charset::ShiftJis,
+    }
+}
+
+/// bytes at the beginning of a file to detect the encoding without reading it entirely
+pub const SNIFF_SIZE: u64 = 65536;
+
+/// detect the encoding by the beginning of a file. a partial line at the end is ignored,
+/// because it may end in the middle of a multibyte character.
+pub fn detect_prefix(bytes: &[u8])->Charset {
+    match bytes.iter().rposition(|&b| b == b'\n') {
+        Some(idx) => detect(&bytes[..idx + 1]),
+        None => detect(bytes),
+    }
+}
+
+/// decode bytes into UTF-8. BOM is removed. Err if there are malformed bytes.
+pub fn decode(bytes: &[u8], charset: Charset)->ioResult<String> {
+    let bom = charset.bom();
+    let bytes = if bytes.starts_with(bom) {&bytes[bom.len()..]} else {bytes};
+    match charset.encoding() {
+        None => String::from_utf8(bytes.to_vec()).map_err(invalid_data),
+        Some(encoding) => encoding.decode_without_bom_handling_and_without_replacement(bytes)
+            .map(|s| s.into_owned())
+            .ok_or_else(|| invalid_data(format!("malformed {:?} sequence", charset))),
+    }
+}
+
+/// encode into the charset with its BOM. Err if a character cannot be mapped.
+pub fn encode(s: &str, charset: Charset)->ioResult<Vec<u8>> {
+    let mut buf = charset.bom().to_vec();
+    match charset {
+        Charset::Utf8 | Charset::Utf8Bom => buf.extend(s.as_bytes()),
+        // encoding_rs encodes UTF-16 into UTF-8, so encode by hand.
+        Charset::Utf16Le => buf.extend(s.encode_utf16().flat_map(|u| vec![u as u8, (u >> 8) as u8])),
+        Charset::Utf16Be => buf.extend(s.encode_utf16().flat_map(|u| vec![(u >> 8) as u8, u as u8])),
+        _ => {
+            let (bytes, _, unmappable) = charset.encoding().unwrap().encode(s);
+            if unmappable {
+                return Err(invalid_data(format!("unmappable character in {:?}", charset)));
+            }
+            buf.extend(bytes.iter());
+        },
+    }
     Ok(buf)
 }
 
-pub fn read_lines<P: AsRef<Path>>(load_path: P)->ioResult<Vec<String>> {
+/// compression of a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Plain,
+    Gzip,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    /// by the extension, `.gz`, `.bz2` or `.xz`
+    pub fn from_path<P: AsRef<Path>>(path: P)->Compression {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("bz2") => Compression::Bzip2,
+            Some("xz") => Compression::Xz,
+            _ => Compression::Plain,
+        }
+    }
+
+    /// by the magic bytes at the beginning
+    pub fn from_magic(bytes: &[u8])->Option<Compression> {
+        if bytes.starts_with(b"\x1F\x8B") {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else if bytes.starts_with(b"\xFD7zXZ\x00") {
+            Some(Compression::Xz)
+        } else {
+            None
+        }
+    }
+}
+
+/// whether the decoder accepts the beginning of a stream. data cut at the end of `head` is accepted
+/// if `head` is longer than the headers of the formats, which are 32 bytes at most.
+fn accepts(compression: Compression, head: &[u8])->bool {
+    let mut buf = [0; 1];
+    let res = match compression {
+        Compression::Plain => return true,
+        Compression::Gzip => MultiGzDecoder::new(head).read(&mut buf),
+        Compression::Bzip2 => MultiBzDecoder::new(head).read(&mut buf),
+        Compression::Xz => XzDecoder::new_multi_decoder(head).read(&mut buf),
+    };
+    match res {
+        Ok(_) => true,
+        Err(e) => e.kind() == io::ErrorKind::UnexpectedEof && head.len() > 32,
+    }
+}
+
+/// compression by magic bytes at the beginning, or by `hint` if they are unknown.
+/// if the hint is plain, text which happens to begin with magic bytes like `BZh` stays plain
+/// unless the decoder accepts it.
+pub fn sniff(head: &[u8], hint: Compression)->Compression {
+    match Compression::from_magic(head) {
+        Some(c) if hint == Compression::Plain && !accepts(c, head) => {
+            debug!("{:?} magic bytes are rejected by the decoder, so read as plain", c);
+            Compression::Plain
+        },
+        Some(c) => c,
+        None => hint,
+    }
+}
+
+/// wrap the reader with the decoder chosen by magic bytes, or by `hint` if they are unknown.
+/// decompression is streaming, so the whole content is not loaded into memory.
+pub fn decoder<'a, R: BufRead + 'a>(mut reader: R, hint: Compression)->ioResult<Box<dyn BufRead + 'a>> {
+    let compression = sniff(reader.fill_buf()?, hint);
+    Ok(match compression {
+        Compression::Plain => Box::new(reader),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        Compression::Bzip2 => Box::new(BufReader::new(MultiBzDecoder::new(reader))),
+        Compression::Xz => Box::new(BufReader::new(XzDecoder::new_multi_decoder(reader))),
+    })
+}
+
+/// compression of the file in the same way as `open`
+pub fn compression<P: AsRef<Path>>(path: P)->ioResult<Compression> {
+    let mut reader = BufReader::new(File::open(path.as_ref())?);
+    Ok(sniff(reader.fill_buf()?, Compression::from_path(path)))
+}
+
+/// open the file decompressing it if needed
+pub fn open<P: AsRef<Path>>(load_path: P)->ioResult<Box<dyn BufRead>> {
     let f = File::open(load_path.as_ref())?;
-    BufReader::new(f)
-        .lines()
-        .collect()
+    decoder(BufReader::new(f), Compression::from_path(load_path))
+}
+
+/// compressing layer over the file
+enum Codec {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Bzip2(BzEncoder<BufWriter<File>>),
+    Xz(XzEncoder<BufWriter<File>>),
+}
+
+impl Codec {
+    fn new(f: File, compression: Compression)->Codec {
+        let w = BufWriter::new(f);
+        match compression {
+            Compression::Plain => Codec::Plain(w),
+            Compression::Gzip => Codec::Gzip(GzEncoder::new(w, flate2::Compression::default())),
+            Compression::Bzip2 => Codec::Bzip2(BzEncoder::new(w, bzip2::Compression::default())),
+            Compression::Xz => Codec::Xz(XzEncoder::new(w, 6)),
+        }
+    }
+
+    /// write the trailer of compression, and flush the file to the disk.
+    fn finish(self)->ioResult<()> {
+        let w = match self {
+            Codec::Plain(w) => w,
+            Codec::Gzip(w) => w.finish()?,
+            Codec::Bzip2(w) => w.finish()?,
+            Codec::Xz(w) => w.finish()?,
+        };
+        w.into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()
+    }
+}
+
+/// writer compressing by the extension of the file.
+/// a new file is written in a temporary file in the same directory, and renamed in `finish`.
+/// so the target is either the old one or the complete new one even if the process crashes.
+/// the temporary file is removed if dropped without `finish`.
+pub struct Encoder {
+    codec: Option<Codec>,
+    /// temporary file renamed to `dst`. None in append mode or after renamed.
+    tmp: Option<PathBuf>,
+    dst: PathBuf,
+}
+
+/// temporary file next to the target, so that rename doesn't cross file systems.
+fn temp_path(dst: &Path)->PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let name = format!(
+        ".{}.{}.{}.tmp",
+        dst.file_name().map_or("".into(), |s| s.to_string_lossy()),
+        process::id(),
+        COUNT.fetch_add(1, AtomicOrdering::SeqCst)
+    );
+    dst.with_file_name(name)
+}
+
+/// persist the rename in the directory entry
+#[cfg(unix)]
+fn sync_dir(path: &Path)->ioResult<()> {
+    match path.parent() {
+        Some(dir) if dir != Path::new("") => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path)->ioResult<()> {
+    Ok(())
+}
+
+impl Encoder {
+    fn codec(&mut self)->&mut Codec {
+        self.codec.as_mut().expect("already finished")
+    }
+
+    /// flush all content to the disk without renaming
+    fn sync(&mut self)->ioResult<()> {
+        match self.codec.take() {
+            Some(codec) => codec.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// move the temporary file to the target
+    fn rename(&mut self)->ioResult<()> {
+        if let Some(ref tmp) = self.tmp {
+            // the temporary file has the default mode, so keep the mode of the replaced file
+            match fs::metadata(&self.dst) {
+                Ok(meta) => fs::set_permissions(tmp, meta.permissions())?,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e),
+            }
+            fs::rename(tmp, &self.dst)?;
+            self.tmp = None;
+            sync_dir(&self.dst)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self)->ioResult<()> {
+        self.sync()?;
+        self.rename()
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        if let Some(ref tmp) = self.tmp {
+            let _ = fs::remove_file(tmp);
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8])->ioResult<usize> {
+        match *self.codec() {
+            Codec::Plain(ref mut w) => w.write(buf),
+            Codec::Gzip(ref mut w) => w.write(buf),
+            Codec::Bzip2(ref mut w) => w.write(buf),
+            Codec::Xz(ref mut w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self)->ioResult<()> {
+        match *self.codec() {
+            Codec::Plain(ref mut w) => w.flush(),
+            Codec::Gzip(ref mut w) => w.flush(),
+            Codec::Bzip2(ref mut w) => w.flush(),
+            Codec::Xz(ref mut w) => w.flush(),
+        }
+    }
+}
+
+/// create the file compressed by its extension. the file is replaced atomically in `finish`.
+pub fn create<P: AsRef<Path>>(save_path: P)->ioResult<Encoder> {
+    let dst = save_path.as_ref().to_path_buf();
+    let tmp = temp_path(&dst);
+    let f = File::create(&tmp)?;
+    Ok(Encoder {codec: Some(Codec::new(f, Compression::from_path(&dst))), tmp: Some(tmp), dst})
+}
+
+/// open the file to append. compressed content is appended as a new stream,
+/// which is readable because decoders read all of concatenated streams.
+pub fn create_append<P: AsRef<Path>>(save_path: P)->ioResult<Encoder> {
+    let dst = save_path.as_ref().to_path_buf();
+    let f = OpenOptions::new().append(true).create(true).open(&dst)?;
+    Ok(Encoder {codec: Some(Codec::new(f, Compression::from_path(&dst))), tmp: None, dst})
+}
+
+/// terminator of lines and whether the last line has it, like `\n` at the end of `cut` output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineFormat {
+    terminator: String,
+    trailing: bool,
 }
 
+impl Default for LineFormat {
+    fn default()->LineFormat {
+        LineFormat {terminator: "\n".into(), trailing: true}
+    }
+}
+
+impl LineFormat {
+    pub fn terminator(mut self, terminator: &str)->LineFormat {
+        self.terminator = terminator.into();
+        self
+    }
+
+    /// whether the last line is terminated
+    pub fn trailing(mut self, yes: bool)->LineFormat {
+        self.trailing = yes;
+        self
+    }
+
+    pub fn join<S: AsRef<str>>(&self, lines: &[S])->String {
+        let mut s = lines.iter()
+            .map(|line| line.as_ref())
+            .collect::<Vec<_>>()
+            .join(&self.terminator);
+        if self.trailing && !lines.is_empty() {
+            s.push_str(&self.terminator);
+        }
+        s
+    }
+}
+
+/// write several files all or nothing. every file is written in a temporary file,
+/// and renamed in `commit` only if all of them are written successfully.
+/// nothing is changed if dropped without `commit`, or if `commit` fails.
+#[derive(Default)]
+pub struct Transaction {
+    staged: Vec<Encoder>,
+}
+
+impl Transaction {
+    pub fn new()->Transaction {
+        Transaction::default()
+    }
+
+    pub fn write<P: AsRef<Path>, Q: AsRef<[u8]>>(&mut self, s: Q, save_path: P)->ioResult<()> {
+        let mut w = create(save_path)?;
+        w.write_all(s.as_ref())?;
+        w.sync()?;
+        self.staged.push(w);
+        Ok(())
+    }
+
+    pub fn write_lines<P: AsRef<Path>, S: AsRef<str>>(&mut self, lines: &[S], save_path: P, format: &LineFormat)->ioResult<()> {
+        self.write(format.join(lines), save_path)
+    }
+
+    /// rename all files. the number of committed files is returned.
+    /// each old file is kept as a backup until all are renamed, and restored if any rename fails.
+    pub fn commit(mut self)->ioResult<usize> {
+        let mut backups = Vec::new();
+        for w in self.staged.iter_mut() {
+            let res = backup(&w.dst).and_then(|bak| match w.rename() {
+                Ok(()) => Ok(bak),
+                Err(e) => {
+                    if let Some(bak) = bak {
+                        let _ = fs::remove_file(bak);
+                    }
+                    Err(e)
+                },
+            });
+            match res {
+                Ok(bak) => backups.push((w.dst.clone(), bak)),
+                Err(e) => {
+                    rollback(backups);
+                    return Err(e);
+                },
+            }
+        }
+        for (_, bak) in backups {
+            if let Some(bak) = bak {
+                fs::remove_file(bak)?;
+            }
+        }
+        Ok(self.staged.len())
+    }
+}
+
+/// restore the renamed files from their backups, or remove them if they were new
+fn rollback(backups: Vec<(PathBuf, Option<PathBuf>)>) {
+    for (dst, bak) in backups.into_iter().rev() {
+        let restored = match bak {
+            Some(bak) => fs::rename(&bak, &dst),
+            None => fs::remove_file(&dst),
+        };
+        if let Err(e) = restored {
+            info!("fail to roll back {:?}: {}", dst, e);
+        }
+    }
+}
+
+/// hard link of the existing file to restore it, or None if it doesn't exist
+fn backup(dst: &Path)->ioResult<Option<PathBuf>> {
+    if fs::symlink_metadata(dst).is_err() {
+        return Ok(None);
+    }
+    let bak = temp_path(dst);
+    fs::hard_link(dst, &bak)
+        .or_else(|_| fs::copy(dst, &bak).map(|_| ()))?;
+    Ok(Some(bak))
+}
+
+pub fn read_bytes<P: AsRef<Path>>(load_path: P)->ioResult<Vec<u8>> {
+    let mut buf = Vec::new();
+    open(load_path)?.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// read and decode the file in the charset, or detect it if None.
+/// the charset is returned together to write back in the source encoding.
+pub fn read_with_charset<P: AsRef<Path>>(load_path: P, charset: Option<Charset>)->ioResult<(String, Charset)> {
+    let bytes = read_bytes(load_path)?;
+    let charset = charset.unwrap_or_else(|| detect(&bytes));
+    Ok((decode(&bytes, charset)?, charset))
+}
+
+/// read the file in the detected encoding
+pub fn read<P: AsRef<Path>>(load_path: P)-> ioResult<String> {
+    read_with_charset(load_path, None)
+        .map(|(s, _)| s)
+}
+
+/// lines of the file read one at a time, and decoded in the encoding detected by the beginning.
+/// `\r` at the end is removed like `read_lines`. UTF-16 can't be split at `\n`, so it is read entirely.
+pub fn open_lines<P: AsRef<Path>>(load_path: P)->ioResult<Box<dyn Iterator<Item=ioResult<String>>>> {
+    let path = load_path.as_ref();
+    let mut head = Vec::new();
+    open(path)?.take(SNIFF_SIZE).read_to_end(&mut head)?;
+    let charset = detect_prefix(&head);
+    if let Charset::Utf16Le | Charset::Utf16Be = charset {
+        return Ok(Box::new(read_lines(path)?.into_iter().map(Ok)));
+    }
+    Ok(Box::new(open(path)?.split(b'\n').map(move |line| {
+        let mut line = line?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        decode(&line, charset)
+    })))
+}
+
+pub fn read_lines<P: AsRef<Path>>(load_path: P)->ioResult<Vec<String>> {
+    Ok(
+        read(load_path)?
+            .lines()
+            .map(|s| s.to_string())
+            .collect()
+    )
+}
+
+/// replace the file atomically
 pub fn write<P: AsRef<Path>, Q: AsRef<[u8]>>(s: Q, save_path: P)->ioResult<()> {
-    let f = File::create(save_path.as_ref())?;
-    BufWriter::new(f)
-        .write_all(s.as_ref())
+    let mut w = create(save_path)?;
+    w.write_all(s.as_ref())?;
+    w.finish()
+}
+
+pub fn append<P: AsRef<Path>, Q: AsRef<[u8]>>(s: Q, save_path: P)->ioResult<()> {
+    let mut w = create_append(save_path)?;
+    w.write_all(s.as_ref())?;
+    w.finish()
+}
+
+/// write UTF-8 string in the charset
+pub fn write_with_charset<P: AsRef<Path>>(s: &str, save_path: P, charset: Charset)->ioResult<()> {
+    write(encode(s, charset)?, save_path)
 }
 
 /// use Borrow trait instead of AsRef because of join method, https://doc.rust-lang.org/std/primitive.slice.html#method.join
-pub fn write_lines<P: AsRef<Path>, S: ::std::borrow::Borrow<str>>(lines: &Vec<S>, save_path: P)->ioResult<()> {
-    write(lines.join("\n"), save_path)
+/// each line is terminated by `\n` including the last one, like output of `cut` and `paste`.
+pub fn write_lines<P: AsRef<Path>, S: ::std::borrow::Borrow<str>>(lines: &[S], save_path: P)->ioResult<()> {
+    write_lines_with(lines, save_path, &LineFormat::default())
+}
+
+pub fn write_lines_with<P: AsRef<Path>, S: ::std::borrow::Borrow<str>>(lines: &[S], save_path: P, format: &LineFormat)->ioResult<()> {
+    let lines = lines.iter().map(|s| s.borrow()).collect::<Vec<&str>>();
+    write(format.join(&lines), save_path)
 }
 
 #[cfg(test)]
@@ -101,4 +687,188 @@ mod tests {
             self::write_lines(&buf, save_path).unwrap(), ()
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_charset() {
+        let s = "高知県\t江川崎\t41\n① 熊谷\n";
+        for &charset in &[
+            Charset::Utf8, Charset::Utf8Bom, Charset::ShiftJis, Charset::EucJp,
+            Charset::Iso2022Jp, Charset::Utf16Le, Charset::Utf16Be,
+        ] {
+            let bytes = encode(s, charset).unwrap();
+            assert_eq!(detect(&bytes), charset);
+            assert_eq!(decode(&bytes, charset).unwrap(), s);
+        }
+        assert_eq!("CP932".parse::<Charset>().unwrap(), Charset::ShiftJis);
+
+        // half-width kana is a single byte in Shift_JIS
+        let bytes = encode("ｺｳﾁｹﾝ 高知県", Charset::ShiftJis).unwrap();
+        assert_eq!(detect(&bytes), Charset::ShiftJis);
+
+        // UTF-16 without BOM
+        let bytes = "abc\n".encode_utf16().flat_map(|u| vec![u as u8, (u >> 8) as u8]).collect::<Vec<_>>();
+        assert_eq!(detect(&bytes), Charset::Utf16Le);
+
+        assert!(decode(b"\x82", Charset::ShiftJis).is_err());
+        assert!(encode("🍣", Charset::ShiftJis).is_err());
+    }
+
+    #[test]
+    fn test_read_write_with_charset() {
+        let save_path: &str = "./data/ch02/out_sjis.txt";
+        let s = "高知県\t江川崎\t41\t2013-08-12\n埼玉県\t熊谷\t40.9\t2007-08-16\n";
+        write_with_charset(s, save_path, Charset::ShiftJis).unwrap();
+
+        assert_eq!(read_lines(save_path).unwrap()[1], "埼玉県\t熊谷\t40.9\t2007-08-16");
+        let (buf, charset) = read_with_charset(save_path, None).unwrap();
+        assert_eq!((buf.as_str(), charset), (s, Charset::ShiftJis));
+
+        // write back in the source encoding
+        write_with_charset(&buf.replace("41", "41.0"), save_path, charset).unwrap();
+        assert_eq!(read_bytes(save_path).unwrap(), encode(&buf.replace("41", "41.0"), charset).unwrap());
+    }
+
+    #[test]
+    fn test_compression() {
+        let s = "abc\nあああ\nbcd\n";
+        for ext in &["txt", "gz", "bz2", "xz"] {
+            let save_path = format!("./data/ch02/out_compressed.{}", ext);
+            write(s, &save_path).unwrap();
+            assert_eq!(read(&save_path).unwrap(), s);
+            assert_eq!(open(&save_path).unwrap().lines().count(), 3);
+        }
+        assert_eq!(read_bytes("./data/ch02/out_compressed.gz").unwrap(), s.as_bytes());
+
+        // magic bytes take priority over the extension
+        ::std::fs::copy("./data/ch02/out_compressed.xz", "./data/ch02/out_compressed.dat").unwrap();
+        assert_eq!(read("./data/ch02/out_compressed.dat").unwrap(), s);
+        assert_eq!(Compression::from_magic(b"abc"), None);
+
+        // plain text beginning with magic bytes
+        for &bytes in &[&b"BZh is not bzip2\n"[..], &b"\x1F\x8B\n"[..]] {
+            let save_path = "./data/ch02/out_compressed_magic.txt";
+            write(bytes, save_path).unwrap();
+            assert_eq!(read_bytes(save_path).unwrap(), bytes);
+            assert_eq!(compression(save_path).unwrap(), Compression::Plain);
+        }
+        assert_eq!(compression("./data/ch02/out_compressed.dat").unwrap(), Compression::Xz);
+
+        // decoded after decompression
+        let save_path = "./data/ch02/out_compressed_sjis.txt.gz";
+        write_with_charset(s, save_path, Charset::ShiftJis).unwrap();
+        assert_eq!(read_with_charset(save_path, None).unwrap(), (s.to_string(), Charset::ShiftJis));
+    }
+
+    /// temporary files left in the directory
+    fn temp_files(dir: &str, name: &str)->usize {
+        ::std::fs::read_dir(dir).unwrap()
+            .filter(|e| {
+                let file_name = e.as_ref().unwrap().file_name();
+                let file_name = file_name.to_string_lossy();
+                file_name.starts_with(&format!(".{}.", name)) && file_name.ends_with(".tmp")
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_atomic_write() {
+        let save_path = "./data/ch02/out_atomic.txt";
+        write("old\n", save_path).unwrap();
+
+        // crash in the middle of writing
+        {
+            let mut w = create(save_path).unwrap();
+            w.write_all(b"half").unwrap();
+        }
+        assert_eq!(read(save_path).unwrap(), "old\n");
+        assert_eq!(temp_files("./data/ch02", "out_atomic.txt"), 0);
+
+        write("new\n", save_path).unwrap();
+        assert_eq!(read(save_path).unwrap(), "new\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_keep_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let save_path = "./data/ch02/out_private.txt";
+        write("old\n", save_path).unwrap();
+        for &mode in &[0o600, 0o755] {
+            fs::set_permissions(save_path, fs::Permissions::from_mode(mode)).unwrap();
+            write("new\n", save_path).unwrap();
+            assert_eq!(fs::metadata(save_path).unwrap().permissions().mode() & 0o777, mode);
+        }
+        assert_eq!(read(save_path).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn test_append() {
+        for save_path in &["./data/ch02/out_append.txt", "./data/ch02/out_append.txt.gz"] {
+            write("a\n", save_path).unwrap();
+            append("b\n", save_path).unwrap();
+            append("c\n", save_path).unwrap();
+            assert_eq!(read_lines(save_path).unwrap(), vec!["a", "b", "c"]);
+        }
+    }
+
+    #[test]
+    fn test_line_format() {
+        let lines = vec!["a", "b"];
+        assert_eq!(LineFormat::default().join(&lines), "a\nb\n");
+        assert_eq!(LineFormat::default().terminator("\r\n").trailing(false).join(&lines), "a\r\nb");
+        assert_eq!(LineFormat::default().join::<&str>(&[]), "");
+
+        let save_path = "./data/ch02/out_crlf.txt";
+        write_lines_with(&lines, save_path, &LineFormat::default().terminator("\r\n")).unwrap();
+        assert_eq!(read_bytes(save_path).unwrap(), b"a\r\nb\r\n");
+    }
+
+    #[test]
+    fn test_transaction() {
+        let (file1, file2) = ("./data/ch02/out_tx1.txt", "./data/ch02/out_tx2.txt");
+        write("old\n", file1).unwrap();
+        let _ = ::std::fs::remove_file(file2);
+
+        // rolled back
+        {
+            let mut tx = Transaction::new();
+            tx.write("new\n", file1).unwrap();
+            assert!(tx.write("new\n", "./data/ch02/no_such_dir/out.txt").is_err());
+        }
+        assert_eq!(read(file1).unwrap(), "old\n");
+        assert_eq!(temp_files("./data/ch02", "out_tx1.txt"), 0);
+
+        let mut tx = Transaction::new();
+        tx.write("new\n", file1).unwrap();
+        tx.write_lines(&["x", "y"], file2, &LineFormat::default()).unwrap();
+        assert!(!Path::new(file2).exists());
+        assert_eq!(tx.commit().unwrap(), 2);
+        assert_eq!(read(file1).unwrap(), "new\n");
+        assert_eq!(read(file2).unwrap(), "x\ny\n");
+        assert_eq!(temp_files("./data/ch02", "out_tx1.txt"), 0);
+    }
+
+    #[test]
+    fn test_transaction_rollback() {
+        let (file1, file2, file3) = ("./data/ch02/out_rb1.txt", "./data/ch02/out_rb2.txt", "./data/ch02/out_rb3");
+        write("old\n", file1).unwrap();
+        let _ = ::std::fs::remove_file(file2);
+        let _ = ::std::fs::remove_dir_all(file3);
+
+        let mut tx = Transaction::new();
+        tx.write("new\n", file1).unwrap();
+        tx.write("new\n", file2).unwrap();
+        tx.write("new\n", file3).unwrap();
+        // the third rename fails since a directory takes its place
+        ::std::fs::create_dir_all(format!("{}/sub", file3)).unwrap();
+        assert!(tx.commit().is_err());
+
+        assert_eq!(read(file1).unwrap(), "old\n");
+        assert!(!Path::new(file2).exists());
+        assert!(Path::new(file3).is_dir());
+        for name in &["out_rb1.txt", "out_rb2.txt", "out_rb3"] {
+            assert_eq!(temp_files("./data/ch02", name), 0);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! random sampling like `shuf`. every function takes a RNG, so a seeded one makes the result reproducible.
+extern crate rand;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+pub use self::rand::Rng;
+use self::rand::{SeedableRng, StdRng};
+
+/// RNG seeded by the number
+pub fn seeded(seed: usize)->StdRng {
+    StdRng::from_seed(&[seed])
+}
+
+/// all items in random order like `shuf`
+pub fn shuffle<T, R: Rng>(mut items: Vec<T>, rng: &mut R)->Vec<T> {
+    rng.shuffle(&mut items);
+    items
+}
+
+/// k items from a stream of unknown length by reservoir sampling, like `shuf -n`.
+/// the items keep the order in the stream.
+pub fn reservoir<T, I: IntoIterator<Item=T>, R: Rng>(items: I, k: usize, rng: &mut R)->Vec<T> {
+    let mut reservoir: Vec<(usize, T)> = Vec::with_capacity(k);
+    for (idx, item) in items.into_iter().enumerate() {
+        if idx < k {
+            reservoir.push((idx, item));
+        } else {
+            let j = rng.gen_range(0, idx + 1);
+            if j < k {
+                reservoir[j] = (idx, item);
+            }
+        }
+    }
+    reservoir.sort_by_key(|&(idx, _)| idx);
+    reservoir.into_iter().map(|(_, item)| item).collect()
+}
+
+/// each item with the probability
+pub fn bernoulli<T, I: IntoIterator<Item=T>, R: Rng>(items: I, rate: f64, rng: &mut R)->Vec<T> {
+    items.into_iter()
+        .filter(|_| rng.gen::<f64>() < rate)
+        .collect()
+}
+
+/// the rate of the items in each group of the key, rounded and at least one per group
+/// unless the rate is 0. the items keep their order.
+pub fn stratified<T, K, F, R>(items: Vec<T>, key: F, rate: f64, rng: &mut R)->Vec<T>
+    where K: Hash + Eq, F: Fn(&T)->K, R: Rng
+{
+    let mut groups: HashMap<K, Vec<usize>> = HashMap::new();
+    // groups in order of appearance not to depend on the order of HashMap
+    let mut keys = Vec::new();
+    for (idx, item) in items.iter().enumerate() {
+        let k = key(item);
+        if !groups.contains_key(&k) {
+            keys.push(idx);
+        }
+        groups.entry(k).or_default().push(idx);
+    }
+    let mut picked = vec![false; items.len()];
+    for first in keys {
+        let members = &groups[&key(&items[first])];
+        let n = if rate > 0.0 {
+            ((members.len() as f64 * rate).round() as usize).max(1).min(members.len())
+        } else {0};
+        for idx in reservoir(members.iter().cloned(), n, rng) {
+            picked[idx] = true;
+        }
+    }
+    items.into_iter()
+        .zip(picked)
+        .filter(|&(_, p)| p)
+        .map(|(item, _)| item)
+        .collect()
+}
+
+/// split shuffled items by the ratios, e.g. `[8, 1, 1]` for train, dev and test.
+/// the remainder of rounding goes to the last split. Err if no ratio is positive.
+pub fn split<T, R: Rng>(items: Vec<T>, ratios: &[usize], rng: &mut R)->Result<Vec<Vec<T>>, String> {
+    let total = ratios.iter().sum::<usize>();
+    if total == 0 {
+        return Err(format!("no positive ratio in {:?}", ratios));
+    }
+    let len = items.len();
+    let mut rest = shuffle(items, rng).into_iter();
+    let mut splits = Vec::new();
+    for (idx, &ratio) in ratios.iter().enumerate() {
+        let n = if idx + 1 == ratios.len() {len} else {len * ratio / total};
+        splits.push(rest.by_ref().take(n).collect());
+    }
+    Ok(splits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shuffle() {
+        let items = (0..100).collect::<Vec<_>>();
+        let a = shuffle(items.clone(), &mut seeded(1));
+        assert_eq!(a, shuffle(items.clone(), &mut seeded(1)));
+        assert_ne!(a, shuffle(items.clone(), &mut seeded(2)));
+        assert_ne!(a, items);
+
+        let mut sorted = a.clone();
+        sorted.sort();
+        assert_eq!(sorted, items);
+    }
+
+    #[test]
+    fn test_reservoir() {
+        let res = reservoir(0..1000, 10, &mut seeded(1));
+        assert_eq!(res.len(), 10);
+        assert!(res.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(res, reservoir(0..1000, 10, &mut seeded(1)));
+        assert_eq!(reservoir(0..3, 10, &mut seeded(1)), vec![0, 1, 2]);
+
+        // every item has the same chance
+        let mut counts = [0; 10];
+        let mut rng = seeded(3);
+        for _ in 0..2000 {
+            for i in reservoir(0..10, 3, &mut rng) {
+                counts[i] += 1;
+            }
+        }
+        assert!(counts.iter().all(|&c| 500 < c && c < 700), "{:?}", counts);
+    }
+
+    #[test]
+    fn test_bernoulli() {
+        let res = bernoulli(0..10000, 0.3, &mut seeded(1));
+        assert!(2800 < res.len() && res.len() < 3200);
+        assert!(bernoulli(0..100, 0.0, &mut seeded(1)).is_empty());
+        assert_eq!(bernoulli(0..100, 1.0, &mut seeded(1)).len(), 100);
+    }
+
+    #[test]
+    fn test_stratified() {
+        let items = (0..100).map(|i| (if i < 90 {"a"} else {"b"}, i)).collect::<Vec<_>>();
+        let res = stratified(items.clone(), |&(k, _)| k, 0.1, &mut seeded(1));
+        assert_eq!(res.iter().filter(|&&(k, _)| k == "a").count(), 9);
+        assert_eq!(res.iter().filter(|&&(k, _)| k == "b").count(), 1);
+        assert!(res.windows(2).all(|w| w[0].1 < w[1].1));
+        assert_eq!(res, stratified(items.clone(), |&(k, _)| k, 0.1, &mut seeded(1)));
+        assert!(stratified(items, |&(k, _)| k, 0.0, &mut seeded(1)).is_empty());
+    }
+
+    #[test]
+    fn test_split() {
+        let splits = split((0..25).collect(), &[8, 1, 1], &mut seeded(1)).unwrap();
+        assert_eq!(splits.iter().map(|s| s.len()).collect::<Vec<_>>(), vec![20, 2, 3]);
+        let mut all = splits.concat();
+        all.sort();
+        assert_eq!(all, (0..25).collect::<Vec<_>>());
+
+        assert!(split((0..25).collect::<Vec<_>>(), &[], &mut seeded(1)).is_err());
+        assert!(split((0..25).collect::<Vec<_>>(), &[0, 0], &mut seeded(1)).is_err());
+        assert_eq!(split((0..25).collect::<Vec<_>>(), &[0, 1], &mut seeded(1)).unwrap()[1].len(), 25);
+    }
+}
@@ -0,0 +1,436 @@
+//! key specification for sorting tab-separated rows, modeled on `sort -k`.
+//! e.g. `-k3,3nr -k4,4M -k1,1`
+extern crate chrono;
+
+use std::cmp::Ordering;
+use std::str::{self, FromStr};
+use self::chrono::NaiveDate;
+
+/// how the value of a key is compared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyType {
+    /// byte order, same as `LC_ALL=C sort`
+    Str,
+    /// leading number like `sort -n`. a value without number is regarded as 0.
+    Numeric,
+    /// `%Y-%m-%d` date parsed by chrono. unparsable values come first.
+    /// note that `M` of GNU sort means month names, not dates.
+    Date,
+    /// digit runs are compared as numbers like `sort -V`
+    Version,
+}
+
+/// where the rows lacking the key column go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Missing {
+    /// regard the column as empty string (GNU sort behaviour)
+    Empty,
+    /// always first regardless of reverse
+    First,
+    /// always last regardless of reverse
+    Last,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeySpec {
+    /// first column beginning with 1
+    pub start: usize,
+    /// last column (inclusive). None means the end of line.
+    pub end: Option<usize>,
+    pub kind: KeyType,
+    pub reverse: bool,
+    /// ignore leading blanks, `b` option
+    pub blank: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortSpec {
+    pub keys: Vec<KeySpec>,
+    pub delimiter: char,
+    /// `-s`; disable last-resort comparison of whole lines
+    pub stable: bool,
+    /// `-u`; output only the first of rows having equal keys
+    pub unique: bool,
+    /// `-r` given globally. also reverses last-resort comparison.
+    pub reverse: bool,
+    pub missing: Missing,
+}
+
+/// key options which are written after field numbers; `n`, `M`, `V`, `r` and `b`
+#[derive(Default)]
+struct Modifiers {
+    kind: Option<KeyType>,
+    reverse: bool,
+    blank: bool,
+}
+
+impl Modifiers {
+    fn is_empty(&self)->bool {
+        self.kind.is_none() && !self.reverse && !self.blank
+    }
+
+    fn push(&mut self, c: char)->Result<(), String> {
+        match c {
+            'n' => self.kind = Some(KeyType::Numeric),
+            'M' => self.kind = Some(KeyType::Date),
+            'V' => self.kind = Some(KeyType::Version),
+            'r' => self.reverse = true,
+            'b' => self.blank = true,
+            _ => return Err(format!("unknown option: {}", c)),
+        }
+        Ok(())
+    }
+}
+
+/// parse `F[OPTS]` and return the field number and the rest options.
+fn parse_field(s: &str, modifiers: &mut Modifiers)->Result<usize, String> {
+    let pos = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, opts) = s.split_at(pos);
+    if opts.starts_with('.') {
+        return Err(format!("character position is not supported: {}", s));
+    }
+    for c in opts.chars() {
+        modifiers.push(c)?;
+    }
+    match num.parse::<usize>() {
+        Ok(0) | Err(_) => Err(format!("invalid field number: {}", s)),
+        Ok(n) => Ok(n),
+    }
+}
+
+impl KeySpec {
+    /// parse `F[OPTS][,F[OPTS]]` which is the argument of `-k`
+    fn parse(s: &str, global: &Modifiers)->Result<KeySpec, String> {
+        let mut modifiers = Modifiers::default();
+        let mut it = s.splitn(2, ',');
+        let start = parse_field(it.next().unwrap(), &mut modifiers)?;
+        let end = match it.next() {
+            Some(e) => Some(parse_field(e, &mut modifiers)?),
+            None => None,
+        };
+        // like GNU sort, global options are applied only to keys without any options
+        let modifiers = if modifiers.is_empty() {global} else {&modifiers};
+        Ok(KeySpec {
+            start,
+            end,
+            kind: modifiers.kind.unwrap_or(KeyType::Str),
+            reverse: modifiers.reverse,
+            blank: modifiers.blank,
+        })
+    }
+
+    /// extract key from the fields. None if the line lacks the column.
+    fn extract(&self, fields: &[&[u8]], delimiter: &[u8])->Option<Vec<u8>> {
+        if fields.len() < self.start {
+            return None;
+        }
+        let end = self.end.map_or(fields.len(), |e| e.min(fields.len()));
+        let key = if self.start <= end {
+            fields[self.start-1..end].join(delimiter)
+        } else {
+            Vec::new()
+        };
+        Some(if self.blank {key.trim_ascii_start().to_vec()} else {key})
+    }
+
+    fn compare_value(&self, a: &[u8], b: &[u8])->Ordering {
+        match self.kind {
+            KeyType::Str => a.cmp(b),
+            KeyType::Numeric => numeric_prefix(a)
+                .partial_cmp(&numeric_prefix(b))
+                .unwrap_or(Ordering::Equal),
+            KeyType::Date => parse_date(a).cmp(&parse_date(b)),
+            KeyType::Version => compare_version(a, b),
+        }
+    }
+}
+
+/// leading number of `sort -n`: blanks, optional '-', digits and decimal fraction.
+fn numeric_prefix(s: &[u8])->f64 {
+    let bytes = s.trim_ascii_start();
+    let mut end = if bytes.first() == Some(&b'-') {1} else {0};
+    while end < bytes.len() && bytes[end].is_ascii_digit() {
+        end += 1;
+    }
+    if end < bytes.len() && bytes[end] == b'.' {
+        end += 1;
+        while end < bytes.len() && bytes[end].is_ascii_digit() {
+            end += 1;
+        }
+    }
+    // the prefix is ASCII
+    str::from_utf8(&bytes[..end]).unwrap_or("").trim_end_matches('.').parse::<f64>().unwrap_or(0.0)
+}
+
+/// a date in non-UTF-8 bytes is unparsable
+fn parse_date(s: &[u8])->Option<NaiveDate> {
+    str::from_utf8(s).ok().and_then(|s| NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d").ok())
+}
+
+/// split into runs of digits and non-digits
+fn runs(s: &[u8])->Vec<&[u8]> {
+    let mut res = Vec::new();
+    let mut start = 0;
+    let mut prev: Option<bool> = None;
+    for (idx, c) in s.iter().enumerate() {
+        let digit = c.is_ascii_digit();
+        if prev == Some(!digit) {
+            res.push(&s[start..idx]);
+            start = idx;
+        }
+        prev = Some(digit);
+    }
+    if start < s.len() {
+        res.push(&s[start..]);
+    }
+    res
+}
+
+/// leading zeros of a digit run
+fn trim_zeros(s: &[u8])->&[u8] {
+    &s[s.iter().take_while(|&&c| c == b'0').count()..]
+}
+
+/// compare digit runs numerically and other runs in byte order.
+fn compare_version(a: &[u8], b: &[u8])->Ordering {
+    let (ra, rb) = (runs(a), runs(b));
+    for (x, y) in ra.iter().zip(rb.iter()) {
+        let is_num = |s: &[u8]| s.first().is_some_and(|c| c.is_ascii_digit());
+        let ord = if is_num(x) && is_num(y) {
+            let (x, y) = (trim_zeros(x), trim_zeros(y));
+            x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+        } else {
+            x.cmp(y)
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    ra.len().cmp(&rb.len())
+}
+
+/// fields of the line separated by the encoded delimiter
+fn split_fields<'a>(line: &'a [u8], delimiter: &[u8])->Vec<&'a [u8]> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    while let Some(pos) = line[start..].windows(delimiter.len()).position(|w| w == delimiter) {
+        fields.push(&line[start..start + pos]);
+        start += pos + delimiter.len();
+    }
+    fields.push(&line[start..]);
+    fields
+}
+
+impl SortSpec {
+    /// compare two lines by keys only.
+    pub fn compare_keys(&self, a: &str, b: &str)->Ordering {
+        self.compare_keys_bytes(a.as_bytes(), b.as_bytes())
+    }
+
+    /// compare two lines in any encoding compatible with ASCII by keys only. keys of strings are compared in bytes.
+    pub fn compare_keys_bytes(&self, a: &[u8], b: &[u8])->Ordering {
+        let mut buf = [0; 4];
+        let delimiter = self.delimiter.encode_utf8(&mut buf).as_bytes();
+        let (fa, fb) = (split_fields(a, delimiter), split_fields(b, delimiter));
+        for key in &self.keys {
+            let (ka, kb) = (key.extract(&fa, delimiter), key.extract(&fb, delimiter));
+            let ord = match (ka, kb, self.missing) {
+                (None, Some(_), Missing::First) | (Some(_), None, Missing::Last) => return Ordering::Less,
+                (None, Some(_), Missing::Last) | (Some(_), None, Missing::First) => return Ordering::Greater,
+                (ka, kb, _) => {
+                    let ord = key.compare_value(
+                        ka.as_ref().map_or(&[][..], |s| s),
+                        kb.as_ref().map_or(&[][..], |s| s)
+                    );
+                    if key.reverse {ord.reverse()} else {ord}
+                }
+            };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// compare two lines. When keys are equal, compare whole lines unless `-s` or `-u` is given.
+    pub fn compare(&self, a: &str, b: &str)->Ordering {
+        self.compare_bytes(a.as_bytes(), b.as_bytes())
+    }
+
+    /// `compare` of lines in bytes
+    pub fn compare_bytes(&self, a: &[u8], b: &[u8])->Ordering {
+        self.compare_keys_bytes(a, b).then_with(|| {
+            if self.stable || self.unique {
+                Ordering::Equal
+            } else if self.reverse {
+                b.cmp(a)
+            } else {
+                a.cmp(b)
+            }
+        })
+    }
+
+    /// sort lines in place. Sorting is stable, so equal lines keep the input order.
+    pub fn sort<S: AsRef<[u8]>>(&self, lines: &mut Vec<S>) {
+        lines.sort_by(|a, b| self.compare_bytes(a.as_ref(), b.as_ref()));
+        if self.unique {
+            lines.dedup_by(|a, b| self.compare_keys_bytes(a.as_ref(), b.as_ref()) == Ordering::Equal);
+        }
+    }
+}
+
+impl FromStr for SortSpec {
+    type Err = String;
+
+    /// parse options of `sort` such as `-s -k3,3nr -k4,4M -k1,1`.
+    fn from_str(s: &str)->Result<SortSpec, String> {
+        let mut global = Modifiers::default();
+        let mut raw_keys = Vec::new();
+        let mut spec = SortSpec {
+            keys: Vec::new(),
+            delimiter: '\t',
+            stable: false,
+            unique: false,
+            reverse: false,
+            missing: Missing::Empty,
+        };
+
+        let mut tokens = s.split_whitespace();
+        while let Some(token) = tokens.next() {
+            if !token.starts_with('-') || token.len() < 2 {
+                return Err(format!("unexpected argument: {}", token));
+            }
+            // the option may be a multibyte character
+            let mut chars = token[1..].chars();
+            let opt = chars.next();
+            let rest = chars.as_str();
+            match opt {
+                Some('k') => {
+                    let arg = if rest.is_empty() {
+                        tokens.next().ok_or("-k requires an argument")?
+                    } else {rest};
+                    raw_keys.push(arg);
+                },
+                Some('t') => {
+                    let arg = if rest.is_empty() {
+                        tokens.next().ok_or("-t requires an argument")?
+                    } else {rest};
+                    let mut chars = arg.chars();
+                    spec.delimiter = match (chars.next(), chars.next()) {
+                        (Some(c), None) => c,
+                        _ => return Err(format!("delimiter must be a single character: {}", arg)),
+                    };
+                },
+                _ => for c in token[1..].chars() {
+                    match c {
+                        's' => spec.stable = true,
+                        'u' => spec.unique = true,
+                        c => global.push(c)?,
+                    }
+                },
+            }
+        }
+
+        spec.reverse = global.reverse;
+        spec.keys = if raw_keys.is_empty() {
+            // whole line is the key
+            vec![KeySpec::parse("1", &global)?]
+        } else {
+            raw_keys.into_iter()
+                .map(|k| KeySpec::parse(k, &global))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        Ok(spec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(spec: &str, lines: &[&str])->Vec<String> {
+        let mut v = lines.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        spec.parse::<SortSpec>().unwrap().sort(&mut v);
+        v
+    }
+
+    #[test]
+    fn test_parse() {
+        let spec = "-s -k3,3nr -k4,4M -k1".parse::<SortSpec>().unwrap();
+        assert!(spec.stable);
+        assert_eq!(
+            spec.keys,
+            vec![
+                KeySpec {start: 3, end: Some(3), kind: KeyType::Numeric, reverse: true, blank: false},
+                KeySpec {start: 4, end: Some(4), kind: KeyType::Date, reverse: false, blank: false},
+                KeySpec {start: 1, end: None, kind: KeyType::Str, reverse: false, blank: false},
+            ]
+        );
+
+        assert!("-k0".parse::<SortSpec>().is_err());
+        assert!("-k2.3".parse::<SortSpec>().is_err());
+        assert!("-k1,1x".parse::<SortSpec>().is_err());
+        assert!("-é".parse::<SortSpec>().is_err());
+        assert!("-k1 -té".parse::<SortSpec>().is_ok());
+    }
+
+    #[test]
+    fn test_global_options() {
+        // global options are applied to keys without options only
+        let spec = "-nr -k1,1 -k2,2V".parse::<SortSpec>().unwrap();
+        assert_eq!(spec.keys[0].kind, KeyType::Numeric);
+        assert!(spec.keys[0].reverse);
+        assert_eq!(spec.keys[1].kind, KeyType::Version);
+        assert!(!spec.keys[1].reverse);
+    }
+
+    #[test]
+    fn test_numeric() {
+        assert_eq!(
+            sorted("-s -k1,1nr", &["9", "40.9", "abc", "41", "-3", "40.90"]),
+            vec!["41", "40.9", "40.90", "9", "abc", "-3"]
+        );
+        assert_eq!(numeric_prefix(b" 12.5kg"), 12.5);
+        assert_eq!(numeric_prefix(b"nan"), 0.0);
+    }
+
+    #[test]
+    fn test_date_and_version() {
+        assert_eq!(
+            sorted("-k1,1M", &["2013-08-12", "1933-07-25", "unknown"]),
+            vec!["unknown", "1933-07-25", "2013-08-12"]
+        );
+        assert_eq!(
+            sorted("-k1,1V", &["file10", "file9", "file1.2", "file1.10"]),
+            vec!["file1.2", "file1.10", "file9", "file10"]
+        );
+    }
+
+    #[test]
+    fn test_multi_keys() {
+        assert_eq!(
+            sorted("-k2,2nr -k1,1", &["b\t1", "a\t2", "c\t1", "a\t1"]),
+            vec!["a\t2", "a\t1", "b\t1", "c\t1"]
+        );
+    }
+
+    #[test]
+    fn test_stable_and_last_resort() {
+        let lines = ["x\tb", "y\ta", "x\ta"];
+        assert_eq!(sorted("-s -k1,1", &lines), vec!["x\tb", "x\ta", "y\ta"]);
+        assert_eq!(sorted("-k1,1", &lines), vec!["x\ta", "x\tb", "y\ta"]);
+        assert_eq!(sorted("-u -k1,1", &lines), vec!["x\tb", "y\ta"]);
+    }
+
+    #[test]
+    fn test_missing() {
+        let lines = ["a\t2", "b", "c\t1"];
+        assert_eq!(sorted("-s -k2,2", &lines), vec!["b", "c\t1", "a\t2"]);
+
+        let mut spec = "-s -k2,2r".parse::<SortSpec>().unwrap();
+        spec.missing = Missing::Last;
+        let mut v = lines.to_vec();
+        spec.sort(&mut v);
+        assert_eq!(v, vec!["a\t2", "c\t1", "b"]);
+    }
+}
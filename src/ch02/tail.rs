@@ -0,0 +1,400 @@
+//! `tail` seeking from the end of file, and `tail -F` which follows appended lines.
+//! compressed files and UTF-16 can't be read backward, so they are read through with a ring buffer.
+use std::collections::VecDeque;
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, BufRead, Result as ioResult};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use super::rw::{self, Charset, Compression, SNIFF_SIZE};
+
+const BLOCK_SIZE: u64 = 8192;
+
+/// compression of the file and the encoding of the decompressed text
+fn inspect(path: &Path)->ioResult<(Compression, Charset)> {
+    let compression = rw::compression(path)?;
+    let mut head = Vec::new();
+    rw::open(path)?.take(SNIFF_SIZE).read_to_end(&mut head)?;
+    Ok((compression, rw::detect_prefix(&head)))
+}
+
+/// whether `\n` is a byte of newline and never a part of multibyte characters
+fn splits_at_newline(charset: Charset)->bool {
+    !matches!(charset, Charset::Utf16Le | Charset::Utf16Be)
+}
+
+/// decode a line split at `\n`, removing `\r` at the end
+fn decode_line(mut line: Vec<u8>, charset: Charset)->ioResult<String> {
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    rw::decode(&line, charset)
+}
+
+/// helper for last_lines; read blocks backward until n lines are found.
+fn last_lines_with<P: AsRef<Path>>(path: P, n: usize, block_size: u64)->ioResult<Vec<String>> {
+    let path = path.as_ref();
+    let (compression, charset) = inspect(path)?;
+    if compression != Compression::Plain || !splits_at_newline(charset) {
+        return last_lines_through(path, n, charset);
+    }
+
+    let mut f = File::open(path)?;
+    let len = f.metadata()?.len();
+    let mut pos = len;
+    let mut blocks = VecDeque::new();
+    let mut newlines = 0;
+    let mut trailing = None;
+
+    // n lines need n newlines before them, except for the newline at the end of file.
+    while pos > 0 && newlines <= n {
+        let size = block_size.min(pos);
+        pos -= size;
+        let mut block = vec![0; size as usize];
+        f.seek(SeekFrom::Start(pos))?;
+        f.read_exact(&mut block)?;
+        if trailing.is_none() {
+            trailing = Some(block.last() == Some(&b'\n'));
+        }
+        newlines += block.iter().filter(|&&b| b == b'\n').count();
+        if trailing == Some(true) && pos + size == len {
+            newlines -= 1;
+        }
+        blocks.push_front(block);
+    }
+
+    let mut buf = blocks.into_iter().flatten().collect::<Vec<_>>();
+    if pos > 0 {
+        // the first line is partial and may begin in the middle of a multibyte character.
+        let first = buf.iter().position(|&b| b == b'\n').map_or(0, |idx| idx + 1);
+        buf.drain(..first);
+    }
+    let s = rw::decode(&buf, charset)?;
+    let lines = s.lines().collect::<Vec<_>>();
+    let skip = lines.len().saturating_sub(n);
+    Ok(lines[skip..].iter().map(|s| s.to_string()).collect())
+}
+
+/// helper for last_lines; read from the beginning keeping the last n lines.
+fn last_lines_through(path: &Path, n: usize, charset: Charset)->ioResult<Vec<String>> {
+    if !splits_at_newline(charset) {
+        let lines = rw::read_lines(path)?;
+        let skip = lines.len().saturating_sub(n);
+        return Ok(lines[skip..].to_vec());
+    }
+    let mut ring = VecDeque::with_capacity(n + 1);
+    for line in rw::open(path)?.split(b'\n') {
+        ring.push_back(line?);
+        if ring.len() > n {
+            ring.pop_front();
+        }
+    }
+    ring.into_iter().map(|line| decode_line(line, charset)).collect()
+}
+
+/// `tail -n ${n}`
+pub fn last_lines<P: AsRef<Path>>(path: P, n: usize)->ioResult<Vec<String>> {
+    last_lines_with(path, n, BLOCK_SIZE)
+}
+
+/// `tail -c ${n}`; bytes of the decompressed file.
+pub fn last_bytes<P: AsRef<Path>>(path: P, n: u64)->ioResult<Vec<u8>> {
+    let path = path.as_ref();
+    if rw::compression(path)? != Compression::Plain {
+        let mut reader = rw::open(path)?;
+        let mut ring = VecDeque::new();
+        loop {
+            let len = {
+                let buf = reader.fill_buf()?;
+                ring.extend(buf);
+                buf.len()
+            };
+            if len == 0 {
+                break;
+            }
+            reader.consume(len);
+            let excess = ring.len().saturating_sub(n as usize);
+            ring.drain(..excess);
+        }
+        return Ok(ring.into());
+    }
+
+    let mut f = File::open(path)?;
+    let len = f.metadata()?.len();
+    f.seek(SeekFrom::Start(len.saturating_sub(n)))?;
+    let mut buf = Vec::new();
+    f.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// `tail -n +${n}`; lines from the n-th line beginning with 1.
+pub fn from_line<P: AsRef<Path>>(path: P, n: usize)->ioResult<Vec<String>> {
+    let path = path.as_ref();
+    let (_, charset) = inspect(path)?;
+    let skip = n.saturating_sub(1);
+    if !splits_at_newline(charset) {
+        return Ok(rw::read_lines(path)?.into_iter().skip(skip).collect());
+    }
+    rw::open(path)?
+        .split(b'\n')
+        .skip(skip)
+        .map(|line| decode_line(line?, charset))
+        .collect()
+}
+
+/// `tail -c +${n}`; bytes of the decompressed file from the n-th byte beginning with 1.
+pub fn from_byte<P: AsRef<Path>>(path: P, n: u64)->ioResult<Vec<u8>> {
+    let path = path.as_ref();
+    let mut buf = Vec::new();
+    if rw::compression(path)? != Compression::Plain {
+        let mut reader = rw::open(path)?;
+        io::copy(&mut reader.by_ref().take(n.saturating_sub(1)), &mut io::sink())?;
+        reader.read_to_end(&mut buf)?;
+        return Ok(buf);
+    }
+
+    let mut f = File::open(path)?;
+    f.seek(SeekFrom::Start(n.saturating_sub(1)))?;
+    f.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// identity of the file to detect rotation
+#[cfg(unix)]
+fn file_id(meta: &Metadata)->Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &Metadata)->Option<(u64, u64)> {
+    None
+}
+
+/// `tail -F -n 0`; yield lines appended to the file.
+/// the file is read from the beginning again when it is truncated,
+/// and the new file is opened when it is rotated (renamed and created).
+/// lines are decoded in the encoding detected by the beginning of the file.
+/// compressed files can't be followed.
+pub struct Follow {
+    path: PathBuf,
+    file: File,
+    id: Option<(u64, u64)>,
+    pos: u64,
+    /// incomplete last line waiting for newline
+    partial: Vec<u8>,
+    /// None until the file has a complete line
+    charset: Option<Charset>,
+    pending: VecDeque<String>,
+    interval: Duration,
+}
+
+impl Follow {
+    /// start following at the end of file
+    pub fn new<P: AsRef<Path>>(path: P)->ioResult<Follow> {
+        if rw::compression(path.as_ref())? != Compression::Plain {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot follow a compressed file"));
+        }
+        let mut file = File::open(path.as_ref())?;
+        let meta = file.metadata()?;
+        let charset = Follow::sniff(&mut file)?;
+        Ok(Follow {
+            path: path.as_ref().to_path_buf(),
+            file,
+            id: file_id(&meta),
+            pos: meta.len(),
+            partial: Vec::new(),
+            charset,
+            pending: VecDeque::new(),
+            interval: Duration::from_millis(500),
+        })
+    }
+
+    /// interval of polling in the iterator. 500ms by default
+    pub fn interval(mut self, interval: Duration)->Follow {
+        self.interval = interval;
+        self
+    }
+
+    /// detect the encoding by complete lines at the beginning of the file
+    fn sniff(file: &mut File)->ioResult<Option<Charset>> {
+        let mut head = Vec::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.by_ref().take(SNIFF_SIZE).read_to_end(&mut head)?;
+        if !head.contains(&b'\n') {
+            return Ok(None);
+        }
+        let charset = rw::detect_prefix(&head);
+        if !splits_at_newline(charset) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "cannot follow UTF-16"));
+        }
+        Ok(Some(charset))
+    }
+
+    /// read from the current position to the end, and return completed lines.
+    fn read_appended(&mut self)->ioResult<Vec<String>> {
+        let len = self.file.metadata()?.len();
+        if len < self.pos {
+            // truncated
+            self.pos = 0;
+            self.partial.clear();
+            self.charset = None;
+        }
+        if self.charset.is_none() {
+            self.charset = Follow::sniff(&mut self.file)?;
+        }
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        let mut buf = Vec::new();
+        self.pos += self.file.read_to_end(&mut buf)? as u64;
+        self.partial.extend(buf);
+
+        let mut lines = Vec::new();
+        while let Some(idx) = self.partial.iter().position(|&b| b == b'\n') {
+            let rest = self.partial.split_off(idx + 1);
+            let mut line = ::std::mem::replace(&mut self.partial, rest);
+            line.pop();
+            lines.push(decode_line(line, self.charset.unwrap_or(Charset::Utf8))?);
+        }
+        Ok(lines)
+    }
+
+    /// lines appended since the last call. this doesn't block.
+    pub fn poll(&mut self)->ioResult<Vec<String>> {
+        let mut lines = self.read_appended()?;
+
+        // while the file is rotated, the path may not exist for a moment.
+        let rotated = match ::std::fs::metadata(&self.path) {
+            Ok(meta) => file_id(&meta) != self.id,
+            Err(_) => false,
+        };
+        if rotated {
+            self.file = File::open(&self.path)?;
+            self.id = file_id(&self.file.metadata()?);
+            self.pos = 0;
+            self.partial.clear();
+            self.charset = None;
+            lines.extend(self.read_appended()?);
+        }
+        Ok(lines)
+    }
+}
+
+/// block until a new line is appended.
+impl Iterator for Follow {
+    type Item = ioResult<String>;
+
+    fn next(&mut self)->Option<ioResult<String>> {
+        while self.pending.is_empty() {
+            match self.poll() {
+                Ok(lines) => self.pending.extend(lines),
+                Err(e) => return Some(Err(e)),
+            }
+            if self.pending.is_empty() {
+                thread::sleep(self.interval);
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    fn append(path: &str, s: &str) {
+        append_bytes(path, s.as_bytes());
+    }
+
+    fn append_bytes(path: &str, bytes: &[u8]) {
+        OpenOptions::new().append(true).create(true).open(path).unwrap()
+            .write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn test_last_lines() {
+        let path = "./data/ch02/tail_lines.txt";
+        fs::write(path, "abc\nあああ\nbcd\n").unwrap();
+
+        // block boundaries in the middle of lines and multibyte characters
+        for block_size in 1..8 {
+            assert_eq!(last_lines_with(path, 2, block_size).unwrap(), vec!["あああ", "bcd"]);
+            assert_eq!(last_lines_with(path, 5, block_size).unwrap(), vec!["abc", "あああ", "bcd"]);
+            assert!(last_lines_with(path, 0, block_size).unwrap().is_empty());
+        }
+
+        // without trailing newline
+        fs::write(path, "abc\nあああ\nbcd").unwrap();
+        assert_eq!(last_lines_with(path, 1, 2).unwrap(), vec!["bcd"]);
+        assert_eq!(last_lines_with(path, 2, 2).unwrap(), vec!["あああ", "bcd"]);
+    }
+
+    #[test]
+    fn test_bytes_and_offsets() {
+        let path = "./data/ch02/tail_offsets.txt";
+        fs::write(path, "abc\nあああ\nbcd\n").unwrap();
+
+        assert_eq!(last_bytes(path, 4).unwrap(), b"bcd\n");
+        assert_eq!(last_bytes(path, 100).unwrap().len(), 18);
+        assert_eq!(from_line(path, 2).unwrap(), vec!["あああ", "bcd"]);
+        assert_eq!(from_line(path, 0).unwrap().len(), 3);
+        assert_eq!(from_byte(path, 15).unwrap(), b"bcd\n");
+    }
+
+    #[test]
+    fn test_compressed() {
+        let path = "./data/ch02/tail_compressed.txt.gz";
+        rw::write("abc\nあああ\nbcd\n", path).unwrap();
+
+        assert_eq!(last_lines(path, 2).unwrap(), vec!["あああ", "bcd"]);
+        assert!(last_lines(path, 0).unwrap().is_empty());
+        assert_eq!(from_line(path, 2).unwrap(), vec!["あああ", "bcd"]);
+        assert_eq!(last_bytes(path, 4).unwrap(), b"bcd\n");
+        assert_eq!(last_bytes(path, 100).unwrap().len(), 18);
+        assert_eq!(from_byte(path, 15).unwrap(), b"bcd\n");
+        assert_eq!(Follow::new(path).err().unwrap().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_cp932() {
+        let path = "./data/ch02/tail_cp932.txt";
+        rw::write_with_charset("高知県\t江川崎\r\n埼玉県\t熊谷\r\n山形県\t山形\r\n", path, Charset::ShiftJis).unwrap();
+
+        for block_size in 1..8 {
+            assert_eq!(last_lines_with(path, 2, block_size).unwrap(), vec!["埼玉県\t熊谷", "山形県\t山形"]);
+        }
+        assert_eq!(from_line(path, 3).unwrap(), vec!["山形県\t山形"]);
+
+        let mut follow = Follow::new(path).unwrap();
+        append_bytes(path, &rw::encode("愛知県\t名古屋\n", Charset::ShiftJis).unwrap());
+        assert_eq!(follow.poll().unwrap(), vec!["愛知県\t名古屋"]);
+    }
+
+    #[test]
+    fn test_follow() {
+        let path = "./data/ch02/tail_follow.txt";
+        fs::write(path, "old\n").unwrap();
+
+        let mut follow = Follow::new(path).unwrap();
+        assert!(follow.poll().unwrap().is_empty());
+
+        append(path, "line1\nli");
+        assert_eq!(follow.poll().unwrap(), vec!["line1"]);
+        append(path, "ne2\n");
+        assert_eq!(follow.next().unwrap().unwrap(), "line2");
+
+        // truncation
+        fs::write(path, "new\n").unwrap();
+        assert_eq!(follow.poll().unwrap(), vec!["new"]);
+
+        // rotation
+        let rotated = "./data/ch02/tail_follow.txt.1";
+        append(path, "before rotation\n");
+        fs::rename(path, rotated).unwrap();
+        fs::write(path, "after rotation\n").unwrap();
+        assert_eq!(follow.poll().unwrap(), vec!["before rotation", "after rotation"]);
+        let _ = fs::remove_file(rotated);
+    }
+}
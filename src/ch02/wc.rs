@@ -0,0 +1,328 @@
+//! `wc` over a memory-mapped file. the file is split into chunks counted in parallel,
+//! and the counts are merged with the correction of words across chunk boundaries.
+extern crate bytecount;
+extern crate memmap2;
+extern crate rayon;
+
+use std::fs::File;
+use std::io::Result as ioResult;
+use std::ops::Add;
+use std::path::Path;
+use self::memmap2::Mmap;
+use self::rayon::prelude::*;
+
+use super::rw::{self, Compression};
+
+/// counts of `wc -l -w -m -c`
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Counts {
+    /// number of newlines. the last line without newline is not counted like `wc -l`.
+    pub lines: usize,
+    /// sequences of non-whitespace characters
+    pub words: usize,
+    /// UTF-8 characters
+    pub chars: usize,
+    pub bytes: usize,
+}
+
+impl Add for Counts {
+    type Output = Counts;
+
+    fn add(self, other: Counts)->Counts {
+        Counts {
+            lines: self.lines + other.lines,
+            words: self.words + other.words,
+            chars: self.chars + other.chars,
+            bytes: self.bytes + other.bytes,
+        }
+    }
+}
+
+/// whitespace of `wc -w` in a UTF-8 locale, e.g. U+3000 of Japanese text.
+/// glibc doesn't classify U+0085, U+2028 and U+2029 as space unlike Unicode.
+fn is_space(c: char)->bool {
+    c.is_whitespace() && !matches!(c, '\u{85}' | '\u{2028}' | '\u{2029}')
+}
+
+/// counts of a chunk, and whether its first and last characters are whitespace to merge words
+/// across chunks. None if the chunk has no valid character.
+struct Chunk {
+    counts: Counts,
+    first_space: Option<bool>,
+    last_space: Option<bool>,
+}
+
+/// counts of a chunk. a word is counted at its first character.
+/// invalid UTF-8 bytes neither begin nor end a word like `wc`.
+fn count_chunk(chunk: &[u8])->Chunk {
+    let mut words = 0;
+    let mut first_space = None;
+    let mut last_space = None;
+    for c in chunk.utf8_chunks().flat_map(|part| part.valid().chars()) {
+        let space = is_space(c);
+        if !space && last_space != Some(false) {
+            words += 1;
+        }
+        first_space = first_space.or(Some(space));
+        last_space = Some(space);
+    }
+    let counts = Counts {
+        lines: bytecount::count(chunk, b'\n'),
+        words,
+        // count bytes except continuation bytes of UTF-8
+        chars: bytecount::num_chars(chunk),
+        bytes: chunk.len(),
+    };
+    Chunk {counts, first_space, last_space}
+}
+
+/// statistics of a column in tab-separated lines
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct ColumnStats {
+    /// lines which have the column
+    pub count: usize,
+    pub empty: usize,
+    /// cells parsed as a number
+    pub numeric: usize,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub sum: f64,
+    /// the longest cell in characters
+    pub max_chars: usize,
+}
+
+impl ColumnStats {
+    fn push(&mut self, cell: &str) {
+        self.count += 1;
+        if cell.is_empty() {
+            self.empty += 1;
+        }
+        self.max_chars = self.max_chars.max(cell.chars().count());
+        if let Ok(v) = cell.trim().parse::<f64>() {
+            self.numeric += 1;
+            self.sum += v;
+            self.min = Some(self.min.map_or(v, |m| m.min(v)));
+            self.max = Some(self.max.map_or(v, |m| m.max(v)));
+        }
+    }
+
+    fn merge(mut self, other: &ColumnStats)->ColumnStats {
+        self.count += other.count;
+        self.empty += other.empty;
+        self.numeric += other.numeric;
+        self.sum += other.sum;
+        self.max_chars = self.max_chars.max(other.max_chars);
+        self.min = match (self.min, other.min) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.max = match (self.max, other.max) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        self
+    }
+
+    pub fn mean(&self)->Option<f64> {
+        if self.numeric == 0 {None} else {Some(self.sum / self.numeric as f64)}
+    }
+}
+
+fn column_stats(chunk: &[u8])->Vec<ColumnStats> {
+    let mut stats: Vec<ColumnStats> = Vec::new();
+    for line in String::from_utf8_lossy(chunk).lines() {
+        for (idx, cell) in line.split('\t').enumerate() {
+            if stats.len() <= idx {
+                stats.push(ColumnStats::default());
+            }
+            stats[idx].push(cell);
+        }
+    }
+    stats
+}
+
+fn merge_columns(mut a: Vec<ColumnStats>, b: Vec<ColumnStats>)->Vec<ColumnStats> {
+    for (idx, s) in b.into_iter().enumerate() {
+        if idx < a.len() {
+            a[idx] = a[idx].clone().merge(&s);
+        } else {
+            a.push(s);
+        }
+    }
+    a
+}
+
+/// content of the file. plain files are memory-mapped, and compressed ones are decompressed in memory.
+enum Content {
+    Mapped(Mmap),
+    Decoded(Vec<u8>),
+    Empty,
+}
+
+impl Content {
+    fn open<P: AsRef<Path>>(path: P)->ioResult<Content> {
+        let file = File::open(path.as_ref())?;
+        // mapping an empty file fails on some platforms
+        if file.metadata()?.len() == 0 {
+            return Ok(Content::Empty);
+        }
+        let mmap = unsafe {Mmap::map(&file)?};
+        match Compression::from_magic(&mmap) {
+            Some(Compression::Plain) | None => Ok(Content::Mapped(mmap)),
+            Some(_) => Ok(Content::Decoded(rw::read_bytes(path)?)),
+        }
+    }
+
+    fn bytes(&self)->&[u8] {
+        match *self {
+            Content::Mapped(ref mmap) => mmap,
+            Content::Decoded(ref buf) => buf,
+            Content::Empty => &[],
+        }
+    }
+}
+
+pub struct Wc {
+    chunk_size: usize,
+}
+
+impl Wc {
+    pub fn new()->Wc {
+        Wc {chunk_size: 1 << 20}
+    }
+
+    /// bytes of a chunk counted in a thread. 1MiB by default.
+    pub fn chunk_size(mut self, size: usize)->Wc {
+        self.chunk_size = size.max(1);
+        self
+    }
+
+    /// counts of the bytes
+    pub fn count_bytes(&self, bytes: &[u8])->Counts {
+        // split at character boundaries not to break UTF-8 sequences
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < bytes.len() {
+            let mut end = (start + self.chunk_size).min(bytes.len());
+            while end < bytes.len() && end - start < self.chunk_size + 3 && bytes[end] & 0xc0 == 0x80 {
+                end += 1;
+            }
+            chunks.push(&bytes[start..end]);
+            start = end;
+        }
+        let counted = chunks.par_iter()
+            .map(|chunk| count_chunk(chunk))
+            .collect::<Vec<_>>();
+        // a word split by the boundary of chunks is counted twice
+        let mut total = Counts::default();
+        let mut in_word = false;
+        for chunk in counted {
+            if in_word && chunk.first_space == Some(false) {
+                total.words -= 1;
+            }
+            in_word = chunk.last_space.map_or(in_word, |space| !space);
+            total = total + chunk.counts;
+        }
+        total
+    }
+
+    /// counts of the file like `wc -l -w -m -c`
+    pub fn count<P: AsRef<Path>>(&self, path: P)->ioResult<Counts> {
+        Ok(self.count_bytes(Content::open(path)?.bytes()))
+    }
+
+    /// statistics of each column of tab-separated lines
+    pub fn columns<P: AsRef<Path>>(&self, path: P)->ioResult<Vec<ColumnStats>> {
+        let content = Content::open(path)?;
+        let bytes = content.bytes();
+        // split at newlines not to break lines
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < bytes.len() {
+            let end = (start + self.chunk_size).min(bytes.len());
+            let end = bytes[end..].iter()
+                .position(|&b| b == b'\n')
+                .map_or(bytes.len(), |pos| end + pos + 1);
+            chunks.push(&bytes[start..end]);
+            start = end;
+        }
+        Ok(chunks.par_iter()
+            .map(|chunk| column_stats(chunk))
+            .reduce(Vec::new, merge_columns))
+    }
+}
+
+impl Default for Wc {
+    fn default()->Wc {
+        Wc::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    /// counts by `wc -l -w -m -c` in a UTF-8 locale
+    fn wc(path: &str)->Counts {
+        let output = Command::new("wc")
+            .args(["-l", "-w", "-m", "-c", path])
+            .env("LC_ALL", "C.UTF-8")
+            .output()
+            .unwrap();
+        let out = String::from_utf8(output.stdout).unwrap();
+        let ns = out.split_whitespace()
+            .take(4)
+            .map(|s| s.parse().unwrap())
+            .collect::<Vec<usize>>();
+        Counts {lines: ns[0], words: ns[1], chars: ns[2], bytes: ns[3]}
+    }
+
+    #[test]
+    fn test_count() {
+        let path = "./data/ch02/wc.txt";
+        let cases = [
+            "", "a", "高知県\t江川崎\n", "a b  c\n\nd\te", "  熊谷 多治見\r\n山形\n",
+            // ideographic space and no-break spaces separate words, but line and paragraph separators don't.
+            "高知県\u{3000}江川崎\u{3000}\u{3000}41\n", "a\u{a0}b\u{202f}c", "a\u{2028}b\u{85}c\u{2029}",
+        ];
+        for s in &cases {
+            rw::write(s, path).unwrap();
+            // tiny chunks to split words and characters
+            for size in &[1, 2, 3, 1 << 20] {
+                assert_eq!(Wc::new().chunk_size(*size).count(path).unwrap(), wc(path), "{:?} by {}", s, size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_count_invalid_utf8() {
+        let path = "./data/ch02/wc_invalid.txt";
+        rw::write(b"a\xff b \xe3\x80\n\xe3\x80\x80c", path).unwrap();
+        for size in &[1, 2, 1 << 20] {
+            assert_eq!(Wc::new().chunk_size(*size).count(path).unwrap().words, wc(path).words, "by {}", size);
+        }
+    }
+
+    #[test]
+    fn test_count_compressed() {
+        let path = "./data/ch02/wc.txt.gz";
+        rw::write("a b\nc\n", path).unwrap();
+        assert_eq!(Wc::new().count(path).unwrap(), Counts {lines: 2, words: 3, chars: 6, bytes: 6});
+    }
+
+    #[test]
+    fn test_columns() {
+        let path = "./data/ch02/wc_columns.txt";
+        rw::write("高知県\t41\n埼玉県\t40.9\tx\n山形県\t\n", path).unwrap();
+        for size in &[1, 1 << 20] {
+            let stats = Wc::new().chunk_size(*size).columns(path).unwrap();
+            assert_eq!(stats.len(), 3);
+            assert_eq!((stats[0].count, stats[0].numeric, stats[0].max_chars), (3, 0, 3));
+            assert_eq!((stats[1].count, stats[1].empty, stats[1].numeric), (3, 1, 2));
+            assert_eq!((stats[1].min, stats[1].max), (Some(40.9), Some(41.0)));
+            assert!((stats[1].mean().unwrap() - 40.95).abs() < 1e-9);
+            assert_eq!(stats[2].count, 1);
+        }
+    }
+}
@@ -3,10 +3,12 @@ extern crate serde_json;
 extern crate regex;
 extern crate url;
 use self::serde_json::{Value, Result as jsonResult};
-use std::io::{BufReader, BufRead, Read, copy, Result as ioResult};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::io::BufRead;
 use std::path::Path;
-use std::fs::File;
-use std::process::{Command, Stdio};
+use ch02::rw;
+use ch02::index::{IndexCache, LineIndex};
 use self::regex::{Regex, RegexBuilder};
 use std::collections::HashMap;
 use self::url::Url;
@@ -24,51 +26,88 @@ struct Section {
     level: u8,
 }
 
-fn get_json<T: reqwest::IntoUrl, P: AsRef<Path>>(url: T, save_dir: P)->ioResult<()> {
-    let mut response = reqwest::get(url).unwrap();
-    let fname = {
-        let fname = response.url()
-            .path_segments()
-            .unwrap()
-            .last()
-            .unwrap();
-        save_dir.as_ref().join(fname)
-    };
-
-    // NOT use unwrap method to ignore whether save_dir exists or not
-    let _ = ::std::fs::create_dir(save_dir);
-    let mut f = File::create(&fname)?;
-    let _ = copy(&mut response, &mut f);
-
-    let output = Command::new("gunzip")
-        .arg(&fname)
-        .output()?;
-
-    eprintln!("status: {:?}", output.status);
-    eprintln!("stdout: {}", String::from_utf8_lossy(&output.stdout));
-    eprintln!("stderr: {}", String::from_utf8_lossy(&output.stderr));
-    Ok(())
+/// only the title of an article, not to parse the text
+#[derive(Deserialize)]
+struct Title {
+    title: String,
 }
 
+/// lines read at once to list titles
+const TITLE_CHUNK: usize = 4096;
+
+/// line number of the first article of each title, and the index which they are listed by
+type Titles = (Rc<LineIndex>, Rc<HashMap<String, usize>>);
+
 struct JsonExtractor<'a> {
     path: &'a Path,
+    /// None if the file cannot be indexed, e.g. compressed
+    index: IndexCache,
+    titles: RefCell<Option<Titles>>,
 }
 
 impl<'a> JsonExtractor<'a> {
     fn new<P: AsRef<Path>+?Sized>(path: &P)->JsonExtractor {
-        JsonExtractor {path: path.as_ref()}
+        let path = path.as_ref();
+        JsonExtractor {path, index: IndexCache::new(path), titles: RefCell::new(None)}
     }
 
+    /// helper for the sidecar line index, which is reopened when the file changes
+    fn index(&self)->Option<Rc<LineIndex>> {
+        self.index
+            .get()
+            .unwrap_or_else(|e| panic!("fail to index {:?}: {}", self.path, e))
+    }
 
+    /// helper for search; titles are listed again when the index is reopened
+    fn titles(&self, index: &Rc<LineIndex>)->Rc<HashMap<String, usize>> {
+        if let Some((ref listed, ref titles)) = *self.titles.borrow() {
+            if Rc::ptr_eq(listed, index) {
+                return titles.clone();
+            }
+        }
+        let mut titles = HashMap::new();
+        for start in (0..index.count()).step_by(TITLE_CHUNK) {
+            let lines = index.lines(start..start + TITLE_CHUNK).unwrap();
+            for (n, line) in lines.iter().enumerate() {
+                if let Ok(t) = serde_json::from_str::<Title>(line) {
+                    titles.entry(t.title).or_insert(start + n);
+                }
+            }
+        }
+        let titles = Rc::new(titles);
+        *self.titles.borrow_mut() = Some((index.clone(), titles.clone()));
+        titles
+    }
 
-    /// helper for ch03.20; search designated article
+    /// helper for ch03.20; search designated article.
+    /// the article is read by the line index, and compressed files are read through.
     fn search(&self, title: &str)->Option<Article> {
-        let reader = BufReader::new(File::open(self.path).unwrap());
-        reader.lines()
+        if let Some(index) = self.index() {
+            return self.titles(&index).get(title).and_then(|&n| self.article(n));
+        }
+        rw::open(self.path)
+            .unwrap()
+            .lines()
             .flat_map(|line| serde_json::from_str::<Article>(&line.unwrap()))
             .find(|line| line.title == title)
     }
 
+    /// the n-th article beginning with 0 by the line index. None if the file cannot be indexed.
+    fn article(&self, n: usize)->Option<Article> {
+        self.index()?
+            .line(n)
+            .unwrap()
+            .and_then(|line| serde_json::from_str(&line).ok())
+    }
+
+    /// number of articles
+    fn count_articles(&self)->usize {
+        match self.index() {
+            Some(index) => index.count(),
+            None => rw::open(self.path).unwrap().lines().count(),
+        }
+    }
+
 
     /// ch03.20 extract text.
     pub fn extract_text(&self, title: &str)->String {
@@ -223,7 +262,7 @@ impl<'a> JsonExtractor<'a> {
             ]
         ).unwrap();
 
-        let json: Value = reqwest::get(url)
+        let json: Value = reqwest::blocking::get(url)
             .unwrap()
             .json()
             .unwrap();
@@ -238,25 +277,60 @@ impl<'a> JsonExtractor<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use dataset;
 
     #[test]
     fn test_get_json() {
-        let dir = "./data/ch03";
-
-        // assume json file not exist
-        let _ = ::std::fs::remove_dir_all(dir);
+        // imported from the mirror or downloaded and decompressed unless cached
+        let path = dataset::resolve("jawiki-country");
+        assert!(path.exists());
+        assert!(JsonExtractor::new(&path).search("イギリス").is_some());
+    }
 
-        get_json(
-            "http://www.cl.ecei.tohoku.ac.jp/nlp100/data/jawiki-country.json.gz",
-        dir,
-        ).unwrap();
+    #[test]
+    fn test_search_compressed() {
+        let path = "./data/ch03/jawiki-sample.json.gz";
+        let articles = [
+            Article {title: "日本".into(), text: "[[Category:日本]]".into()},
+            Article {title: "イギリス".into(), text: "{{基本情報 国\n[[Category:イギリス]]".into()},
+        ];
+        let lines = articles.iter()
+            .map(|a| serde_json::to_string(a).unwrap())
+            .collect::<Vec<_>>();
+        rw::write_lines(&lines, path).unwrap();
+
+        let ext = JsonExtractor::new(path);
+        assert_eq!(ext.extract_category_names("イギリス"), vec!["イギリス"]);
+        assert_eq!(ext.count_articles(), 2);
+        assert!(ext.article(0).is_none());
+    }
 
-        assert!(Path::new("./data/ch03/jawiki-country.json").exists());
+    #[test]
+    fn test_article() {
+        let path = "./data/ch03/jawiki-sample.json";
+        let lines = ["日本", "イギリス", "フランス"].iter()
+            .map(|&title| serde_json::to_string(&Article {title: title.into(), text: "".into()}).unwrap())
+            .collect::<Vec<_>>();
+        rw::write_lines(&lines, path).unwrap();
+
+        let ext = JsonExtractor::new(path);
+        assert_eq!(ext.count_articles(), 3);
+        assert_eq!(ext.article(1).unwrap().title, "イギリス");
+        assert!(ext.article(3).is_none());
+        assert_eq!(ext.search("フランス").unwrap().title, "フランス");
+        assert!(ext.search("ドイツ").is_none());
+        assert_eq!(ext.titles.borrow().as_ref().unwrap().1.len(), 3);
+
+        // the file is modified, so the titles are listed again
+        rw::write_lines(&lines[..2], path).unwrap();
+        assert!(ext.search("フランス").is_none());
+        assert_eq!(ext.count_articles(), 2);
     }
 
     #[test]
     fn test_search() {
-        let ext = JsonExtractor::new("./data/ch03/jawiki-country.json");
+        let path = dataset::resolve("jawiki-country");
+        let ext = JsonExtractor::new(&path);
         let key = "イギリス";
         let res = ext.search(key);
 
@@ -265,7 +339,8 @@ mod test {
 
     #[test]
     fn test_extract_text() {
-        let ext = JsonExtractor::new("./data/ch03/jawiki-country.json");
+        let path = dataset::resolve("jawiki-country");
+        let ext = JsonExtractor::new(&path);
         let key = "イギリス";
         let res = ext.extract_text(key);
 
@@ -276,7 +351,8 @@ mod test {
 
     #[test]
     fn test_extract_categories() {
-        let ext = JsonExtractor::new("./data/ch03/jawiki-country.json");
+        let path = dataset::resolve("jawiki-country");
+        let ext = JsonExtractor::new(&path);
         let key = "イギリス";
         let res = ext.extract_categories(key);
 
@@ -297,7 +373,8 @@ mod test {
 
     #[test]
     fn test_extract_category_names() {
-        let ext = JsonExtractor::new("./data/ch03/jawiki-country.json");
+        let path = dataset::resolve("jawiki-country");
+        let ext = JsonExtractor::new(&path);
         let key = "イギリス";
         let res = ext.extract_category_names(key);
 
@@ -317,7 +394,8 @@ mod test {
 
     #[test]
     fn test_extract_section() {
-        let ext = JsonExtractor::new("./data/ch03/jawiki-country.json");
+        let path = dataset::resolve("jawiki-country");
+        let ext = JsonExtractor::new(&path);
         let key = "イギリス";
         let res = ext.extract_section(key);
 
@@ -338,7 +416,8 @@ mod test {
 
     #[test]
     fn test_extract_media_file() {
-        let ext = JsonExtractor::new("./data/ch03/jawiki-country.json");
+        let path = dataset::resolve("jawiki-country");
+        let ext = JsonExtractor::new(&path);
         let key = "イギリス";
         let res = ext.extract_media_file(key);
 
@@ -355,7 +434,8 @@ mod test {
 
     #[test]
     fn test_extract_template_map() {
-        let ext = JsonExtractor::new("./data/ch03/jawiki-country.json");
+        let path = dataset::resolve("jawiki-country");
+        let ext = JsonExtractor::new(&path);
         let key = "イギリス";
         let res = ext.extract_template_map(key);
 
@@ -371,7 +451,8 @@ mod test {
 
     #[test]
     fn test_extract_template_map_removed_em() {
-        let ext = JsonExtractor::new("./data/ch03/jawiki-country.json");
+        let path = dataset::resolve("jawiki-country");
+        let ext = JsonExtractor::new(&path);
         let key = "イギリス";
         let res = ext.extract_template_map_removed_em(key);
 
@@ -382,7 +463,8 @@ mod test {
 
     #[test]
     fn test_extract_template_map_removed_internal() {
-        let ext = JsonExtractor::new("./data/ch03/jawiki-country.json");
+        let path = dataset::resolve("jawiki-country");
+        let ext = JsonExtractor::new(&path);
         let key = "イギリス";
         let res = ext.extract_template_map_removed_internal(key);
 
@@ -404,7 +486,8 @@ mod test {
 
     #[test]
     fn test_shape_template() {
-        let ext = JsonExtractor::new("./data/ch03/jawiki-country.json");
+        let path = dataset::resolve("jawiki-country");
+        let ext = JsonExtractor::new(&path);
         let key = "イギリス";
         let res = ext.shape_template(key);
 
@@ -425,7 +508,8 @@ mod test {
 
     #[test]
     fn test_get_flag_url() {
-        let ext = JsonExtractor::new("./data/ch03/jawiki-country.json");
+        let path = dataset::resolve("jawiki-country");
+        let ext = JsonExtractor::new(&path);
         // query is https://ja.wikipedia.org/w/api.php?action=query&titles=File%3AFlag+of+the+United+Kingdom.svg&prop=imageinfo&iiprop=url&format=json
         let res = ext.get_flag_url();
 
@@ -0,0 +1,406 @@
+//! registry of the datasets used in chapters. a dataset is fetched once into the cache directory
+//! from a local mirror or the network, and verified by SHA-256 if its digest is pinned.
+//! for user-registered datasets without a digest, the digest of the first fetched copy is recorded
+//! as `{name}.sha256` next to it, and later copies are verified against it.
+//! builtin datasets are verified only by their pinned digests, never by recorded ones.
+//! the cached copy is verified as well when resolved.
+//!
+//! environment variables:
+//! - `NLP100_DATA_DIR`: cache directory, `./data` by default
+//! - `NLP100_OFFLINE`: never access the network if set to `1`
+//! - `NLP100_MIRROR`: directory which has the files of datasets
+extern crate reqwest;
+extern crate sha2;
+
+use std::env;
+use std::fmt;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+use self::sha2::{Digest, Sha256};
+
+use ch02::rw::{self, Compression};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dataset {
+    pub name: String,
+    pub url: String,
+    /// hex digest of the decompressed content. not verified if None.
+    pub sha256: Option<String>,
+    /// decompressed file relative to the cache directory
+    pub path: PathBuf,
+}
+
+impl Dataset {
+    pub fn new<P: AsRef<Path>>(name: &str, url: &str, path: P)->Dataset {
+        Dataset {name: name.into(), url: url.into(), sha256: None, path: path.as_ref().to_path_buf()}
+    }
+
+    pub fn sha256(mut self, hex: &str)->Dataset {
+        self.sha256 = Some(hex.to_lowercase());
+        self
+    }
+
+    /// file name in the URL, e.g. `jawiki-country.json.gz`
+    fn remote_name(&self)->&str {
+        self.url.rsplit('/').next().unwrap_or("")
+    }
+}
+
+/// pinned digests of the builtin datasets, taken from the published files.
+/// a builtin dataset without one is fetched unverified; the digest of its first fetch is not trusted.
+const HIGHTEMP_SHA256: Option<&str> = None;
+const JAWIKI_COUNTRY_SHA256: Option<&str> = None;
+
+/// datasets of nlp100
+pub fn builtin()->Vec<Dataset> {
+    let pin = |dataset: Dataset, digest: Option<&str>| match digest {
+        Some(hex) => dataset.sha256(hex),
+        None => dataset,
+    };
+    vec![
+        pin(
+            Dataset::new("hightemp", "http://www.cl.ecei.tohoku.ac.jp/nlp100/data/hightemp.txt", "ch02/hightemp.txt"),
+            HIGHTEMP_SHA256
+        ),
+        pin(
+            Dataset::new(
+                "jawiki-country",
+                "http://www.cl.ecei.tohoku.ac.jp/nlp100/data/jawiki-country.json.gz",
+                "ch03/jawiki-country.json"
+            ),
+            JAWIKI_COUNTRY_SHA256
+        ),
+    ]
+}
+
+/// digest expected of the dataset at `path`: the pinned one, or the recorded one for user-registered datasets
+fn expected_digest<P: AsRef<Path>>(dataset: &Dataset, path: P)->Option<String> {
+    if dataset.sha256.is_some() || builtin().contains(dataset) {
+        return dataset.sha256.clone();
+    }
+    rw::read(digest_path(path)).ok().map(|s| s.trim().to_lowercase())
+}
+
+#[derive(Debug)]
+pub enum DatasetError {
+    Unknown(String),
+    /// not in the cache nor the mirror in offline mode
+    Offline(String),
+    Download(String),
+    Checksum {name: String, expected: String, actual: String},
+    Io(io::Error),
+}
+
+impl fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut fmt::Formatter)->fmt::Result {
+        match *self {
+            DatasetError::Unknown(ref name) => write!(f, "unknown dataset: {}", name),
+            DatasetError::Offline(ref name) => write!(f, "{} is not available offline", name),
+            DatasetError::Download(ref e) => write!(f, "fail to download: {}", e),
+            DatasetError::Checksum {ref name, ref expected, ref actual} =>
+                write!(f, "checksum mismatch of {}: expected {}, actual {}", name, expected, actual),
+            DatasetError::Io(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl ::std::error::Error for DatasetError {}
+
+impl From<io::Error> for DatasetError {
+    fn from(e: io::Error)->DatasetError {
+        DatasetError::Io(e)
+    }
+}
+
+/// `dir/name.sha256` for `dir/name`, which has the hex digest recorded at the first fetch
+fn digest_path<P: AsRef<Path>>(path: P)->PathBuf {
+    let path = path.as_ref();
+    let name = path.file_name().map_or("".into(), |s| s.to_string_lossy());
+    path.with_file_name(format!("{}.sha256", name))
+}
+
+fn hex(bytes: &[u8])->String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn sha256_of<R: Read>(mut reader: R)->io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 8192];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+pub struct Registry {
+    cache_dir: PathBuf,
+    offline: bool,
+    mirror: Option<PathBuf>,
+    datasets: Vec<Dataset>,
+    /// size and mtime of the cached files already verified, not to hash them at every `resolve`
+    verified: Mutex<HashMap<String, (u64, Option<SystemTime>)>>,
+}
+
+impl Registry {
+    /// builtin datasets cached in the directory
+    pub fn new<P: AsRef<Path>>(cache_dir: P)->Registry {
+        Registry {
+            cache_dir: cache_dir.as_ref().to_path_buf(),
+            offline: false,
+            mirror: None,
+            datasets: builtin(),
+            verified: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// configured by environment variables
+    pub fn from_env()->Registry {
+        let registry = Registry::new(env::var_os("NLP100_DATA_DIR").unwrap_or_else(|| "./data".into()))
+            .offline(env::var("NLP100_OFFLINE").map(|v| v == "1").unwrap_or(false));
+        match env::var_os("NLP100_MIRROR") {
+            Some(dir) => registry.mirror(dir),
+            None => registry,
+        }
+    }
+
+    pub fn offline(mut self, yes: bool)->Registry {
+        self.offline = yes;
+        self
+    }
+
+    /// import datasets from the directory before downloading
+    pub fn mirror<P: AsRef<Path>>(mut self, dir: P)->Registry {
+        self.mirror = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// add or replace the dataset of the same name
+    pub fn register(mut self, dataset: Dataset)->Registry {
+        self.datasets.retain(|d| d.name != dataset.name);
+        self.datasets.push(dataset);
+        self
+    }
+
+    pub fn get(&self, name: &str)->Result<&Dataset, DatasetError> {
+        self.datasets.iter()
+            .find(|d| d.name == name)
+            .ok_or_else(|| DatasetError::Unknown(name.into()))
+    }
+
+    /// path in the cache whether it exists or not
+    pub fn path(&self, name: &str)->Result<PathBuf, DatasetError> {
+        Ok(self.cache_dir.join(&self.get(name)?.path))
+    }
+
+    /// path of the cached dataset. it is fetched unless cached, and verified if cached.
+    pub fn resolve(&self, name: &str)->Result<PathBuf, DatasetError> {
+        let path = self.path(name)?;
+        if path.exists() {
+            let stamp = path.metadata().map(|m| (m.len(), m.modified().ok()))?;
+            if self.verified.lock().unwrap().get(name) != Some(&stamp) {
+                self.verify(name)?;
+                self.verified.lock().unwrap().insert(name.into(), stamp);
+            }
+        } else {
+            self.fetch_to(name, &path)?;
+        }
+        Ok(path)
+    }
+
+    /// fetch the dataset from the mirror or the network, and save it in `dst` decompressed.
+    /// `dst` is not changed if the checksum mismatches.
+    pub fn fetch_to<P: AsRef<Path>>(&self, name: &str, dst: P)->Result<(), DatasetError> {
+        let dataset = self.get(name)?;
+        if let Some(parent) = dst.as_ref().parent() {
+            ::std::fs::create_dir_all(parent)?;
+        }
+
+        let mirrored = self.mirror.iter()
+            .flat_map(|dir| vec![dir.join(dataset.remote_name()), dir.join(dataset.path.file_name().unwrap_or_default())])
+            .find(|path| path.is_file());
+        if let Some(path) = mirrored {
+            info!("import {} from {:?}", name, path);
+            return save_verified(dataset, rw::open(path)?, dst);
+        }
+
+        if self.offline {
+            return Err(DatasetError::Offline(name.into()));
+        }
+        info!("download {} from {}", name, dataset.url);
+        let response = reqwest::blocking::get(dataset.url.as_str())
+            .map_err(|e| DatasetError::Download(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(DatasetError::Download(format!("{} responds {}", dataset.url, response.status())));
+        }
+        let reader = rw::decoder(BufReader::new(response), Compression::from_path(dataset.remote_name()))?;
+        save_verified(dataset, reader, dst)
+    }
+
+    /// check the cached dataset by SHA-256, or by the digest recorded at the first fetch if user-registered.
+    /// Ok if neither is known.
+    pub fn verify(&self, name: &str)->Result<(), DatasetError> {
+        let dataset = self.get(name)?;
+        let path = self.path(name)?;
+        match expected_digest(dataset, &path) {
+            Some(ref expected) => {
+                let actual = sha256_of(rw::open(&path)?)?;
+                if *expected == actual {
+                    Ok(())
+                } else {
+                    Err(DatasetError::Checksum {name: name.into(), expected: expected.clone(), actual})
+                }
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+/// save the decompressed content, and rename it to `dst` only if the checksum matches.
+/// the digest of a user-registered dataset is recorded if neither pinned nor recorded yet.
+fn save_verified<R: Read, P: AsRef<Path>>(dataset: &Dataset, mut reader: R, dst: P)->Result<(), DatasetError> {
+    let mut w = rw::create(&dst)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 8192];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        w.write_all(&buf[..n])?;
+    }
+    let actual = hex(&hasher.finalize());
+    let expected = expected_digest(dataset, &dst);
+    match expected {
+        // the temporary file is removed when `w` is dropped
+        Some(expected) if expected != actual =>
+            Err(DatasetError::Checksum {name: dataset.name.clone(), expected, actual}),
+        _ => {
+            w.finish()?;
+            if expected.is_none() && !builtin().contains(dataset) {
+                rw::write(format!("{}\n", actual), digest_path(&dst))?;
+            }
+            Ok(())
+        },
+    }
+}
+
+/// registry configured by environment variables, shared in the process
+pub fn registry()->&'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::from_env)
+}
+
+/// path of the dataset in the shared registry for tests. panic if unavailable.
+#[cfg(test)]
+pub fn resolve(name: &str)->PathBuf {
+    registry().resolve(name)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    fn registry(dir: &str)->Registry {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(format!("{}/mirror", dir)).unwrap();
+        Registry::new(format!("{}/cache", dir))
+            .offline(true)
+            .mirror(format!("{}/mirror", dir))
+            .register(Dataset::new("abc", "http://example.com/abc.txt.gz", "sub/abc.txt").sha256(ABC_SHA256))
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(sha256_of(&b"abc"[..]).unwrap(), ABC_SHA256);
+    }
+
+    #[test]
+    fn test_import_from_mirror() {
+        let dir = "./data/dataset_mirror";
+        let registry = registry(dir);
+        assert!(matches!(registry.resolve("abc"), Err(DatasetError::Offline(_))));
+        assert!(matches!(registry.resolve("no-such"), Err(DatasetError::Unknown(_))));
+
+        // compressed file in the mirror is decompressed
+        rw::write("abc", format!("{}/mirror/abc.txt.gz", dir)).unwrap();
+        let path = registry.resolve("abc").unwrap();
+        assert_eq!(path, Path::new(dir).join("cache/sub/abc.txt"));
+        assert_eq!(rw::read(&path).unwrap(), "abc");
+        registry.verify("abc").unwrap();
+
+        // cached one is used without the mirror
+        fs::remove_file(format!("{}/mirror/abc.txt.gz", dir)).unwrap();
+        assert_eq!(registry.resolve("abc").unwrap(), path);
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let dir = "./data/dataset_checksum";
+        let registry = registry(dir);
+        rw::write("abd", format!("{}/mirror/abc.txt", dir)).unwrap();
+
+        match registry.resolve("abc") {
+            Err(DatasetError::Checksum {actual, ..}) => assert_eq!(actual, sha256_of(&b"abd"[..]).unwrap()),
+            res => panic!("unexpected {:?}", res),
+        }
+        assert!(!registry.path("abc").unwrap().exists());
+    }
+
+    #[test]
+    fn test_recorded_digest() {
+        let dir = "./data/dataset_recorded";
+        let registry = registry(dir).register(Dataset::new("xyz", "http://example.com/xyz.txt", "xyz.txt"));
+        rw::write("xyz", format!("{}/mirror/xyz.txt", dir)).unwrap();
+
+        let path = registry.resolve("xyz").unwrap();
+        assert_eq!(rw::read(digest_path(&path)).unwrap().trim(), sha256_of(&b"xyz"[..]).unwrap());
+        registry.verify("xyz").unwrap();
+
+        // the cached copy is modified after the first fetch
+        rw::write("xyyy", &path).unwrap();
+        assert!(matches!(registry.verify("xyz"), Err(DatasetError::Checksum {..})));
+        assert!(matches!(registry.resolve("xyz"), Err(DatasetError::Checksum {..})));
+
+        // a different copy fetched again is rejected, and the recorded digest is kept
+        fs::remove_file(&path).unwrap();
+        rw::write("xyy", format!("{}/mirror/xyz.txt", dir)).unwrap();
+        assert!(matches!(registry.resolve("xyz"), Err(DatasetError::Checksum {..})));
+        assert!(!path.exists());
+        assert_eq!(rw::read(digest_path(&path)).unwrap().trim(), sha256_of(&b"xyz"[..]).unwrap());
+    }
+
+    #[test]
+    fn test_builtin_not_recorded() {
+        let dir = "./data/dataset_builtin";
+        let registry = registry(dir);
+        let hightemp = registry.get("hightemp").unwrap().clone();
+        let path = registry.path("hightemp").unwrap();
+        if hightemp.sha256.is_some() {
+            // the pinned digest is checked instead
+            return;
+        }
+
+        // no digest is recorded at the first fetch, and a recorded one is ignored
+        rw::write("a\tb\n", format!("{}/mirror/hightemp.txt", dir)).unwrap();
+        registry.resolve("hightemp").unwrap();
+        assert!(!digest_path(&path).exists());
+        rw::write("0000\n", digest_path(&path)).unwrap();
+        registry.verify("hightemp").unwrap();
+
+        // the same dataset registered by the user is verified by the recorded digest
+        let registry = registry.register(Dataset::new("hightemp", "http://example.com/hightemp.txt", &hightemp.path));
+        assert!(matches!(registry.verify("hightemp"), Err(DatasetError::Checksum {..})));
+    }
+}
@@ -8,4 +8,5 @@ extern crate lazy_static;
 
 pub mod ch01;
 pub mod ch02;
-pub mod ch03;
\ No newline at end of file
+pub mod ch03;
+pub mod dataset;
\ No newline at end of file